@@ -0,0 +1,176 @@
+//! Matching of the path globs of tags against the filesystem.
+//!
+//! Within a component, `*` matches any run of characters and `?` any single
+//! one; a `**` component matches any number of directories.
+
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Checks that `pattern` is a well-formed glob.
+pub(crate) fn validate(pattern: &str) -> Result<(), String> {
+    if pattern.is_empty() {
+        return Err("empty pattern".to_owned());
+    }
+    for component in Path::new(pattern).components() {
+        if let Component::Normal(component) = component {
+            let component = component.to_string_lossy();
+            if component != "**" && component.contains("**") {
+                return Err(format!("`**` in {component:?} must be a whole component"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collects the existing paths matching `pattern`; unreadable directories are
+/// skipped.
+pub(crate) fn expand(pattern: &Path) -> HashSet<PathBuf> {
+    let mut root = PathBuf::new();
+    let mut components = pattern.components().peekable();
+    while let Some(component) = components.next_if(|component| !is_wildcard(component)) {
+        root.push(component);
+    }
+
+    let components = components.collect::<Vec<_>>();
+    let mut matches = HashSet::new();
+    if components.is_empty() {
+        if root.exists() {
+            matches.insert(root);
+        }
+    } else {
+        walk(root, &components, &mut matches);
+    }
+    matches
+}
+
+fn is_wildcard(component: &Component<'_>) -> bool {
+    matches!(component, Component::Normal(component) if has_wildcard(&component.to_string_lossy()))
+}
+
+fn has_wildcard(component: &str) -> bool {
+    component.contains(['*', '?'])
+}
+
+fn walk(dir: PathBuf, components: &[Component<'_>], matches: &mut HashSet<PathBuf>) {
+    let Some((component, rest)) = components.split_first() else {
+        matches.insert(dir);
+        return;
+    };
+
+    let component = component.as_os_str().to_string_lossy();
+    if component == "**" {
+        walk(dir.clone(), rest, matches);
+        for entry in read_dir(&dir) {
+            if entry.is_dir() {
+                walk(entry, components, matches);
+            }
+        }
+    } else if has_wildcard(&component) {
+        for entry in read_dir(&dir) {
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            if matches_component(&component, &name) {
+                walk(entry, rest, matches);
+            }
+        }
+    } else {
+        let path = dir.join(component.as_ref());
+        if path.exists() {
+            walk(path, rest, matches);
+        }
+    }
+}
+
+fn read_dir(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect()
+}
+
+/// Checks whether `name` matches the `*` and `?` wildcards of `pattern`.
+pub(crate) fn matches_component(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards_within_a_component() {
+        assert!(matches_component("*.rs", "main.rs"));
+        assert!(matches_component("*.rs", ".rs"));
+        assert!(!matches_component("*.rs", "main.rsx"));
+        assert!(matches_component("a?c", "abc"));
+        assert!(!matches_component("a?c", "ac"));
+        assert!(matches_component("*a*b*", "xxaxxbxx"));
+        assert!(!matches_component("*a*b*", "xxbxxaxx"));
+        assert!(matches_component("é?", "éè"));
+        assert!(matches_component("*", ""));
+    }
+
+    #[test]
+    fn validates_patterns() {
+        assert!(validate("/a/**/b/*.rs").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("/a/b**/c").is_err());
+    }
+
+    #[test]
+    fn expands_against_the_filesystem() {
+        let root = std::env::temp_dir().join(format!("path-tagging-glob-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["src/bin", "docs"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["src/lib.rs", "src/bin/main.rs", "docs/guide.md", "top.rs"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let expand = |pattern: &str| {
+            let mut paths = expand(&root.join(pattern))
+                .into_iter()
+                .map(|path| path.strip_prefix(&root).unwrap().to_path_buf())
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+        assert_eq!(expand("src/*.rs"), [PathBuf::from("src/lib.rs")]);
+        assert_eq!(
+            expand("**/*.rs"),
+            ["src/bin/main.rs", "src/lib.rs", "top.rs"].map(PathBuf::from)
+        );
+        assert_eq!(expand("*/guide.??"), [PathBuf::from("docs/guide.md")]);
+        assert_eq!(expand("docs"), [PathBuf::from("docs")]);
+        assert!(expand("missing/*").is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! Excluding paths from walks with `.tagignore` files.
+//!
+//! Each line of a `.tagignore` is a pattern in the style of `.gitignore`:
+//! blank lines and lines starting with `#` are skipped, a leading `!`
+//! re-includes what an earlier pattern excluded, and a trailing `/` matches
+//! only directories. A pattern with a `/` elsewhere is relative to the
+//! directory of its file, where `**` matches any number of directories; one
+//! without matches the name of anything below it. Later patterns, and those
+//! of deeper files, take precedence.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::glob;
+
+/// The `.tagignore` files under a directory, read as they are needed.
+#[derive(Clone, Debug)]
+pub struct TagIgnore {
+    root: PathBuf,
+    rules: HashMap<PathBuf, Vec<Rule>>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+    components: Vec<String>,
+}
+
+impl TagIgnore {
+    pub const FILE_NAME: &'static str = ".tagignore";
+
+    /// Creates an empty set of rules for the paths under `root`.
+    #[inline]
+    #[must_use]
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            rules: HashMap::new(),
+        }
+    }
+
+    /// The directory whose `.tagignore` files are honored.
+    #[inline]
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Checks whether `path` or any directory above it up to the root is
+    /// excluded; paths outside the root never are.
+    ///
+    /// Unreadable `.tagignore` files are treated as empty.
+    pub fn is_ignored(&mut self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let names = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let root = self.root.clone();
+        for end in 1..=names.len() {
+            let is_dir = end < names.len() || path.is_dir();
+            if self.is_excluded(&root, &names[..end], is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Checks the rules of every directory above the path made of `names`.
+    fn is_excluded(&mut self, root: &Path, names: &[String], is_dir: bool) -> bool {
+        let mut excluded = false;
+        let mut dir = root.to_path_buf();
+        for depth in 0..names.len() {
+            let relative = &names[depth..];
+            for rule in self.rules_in(&dir) {
+                if rule.matches(relative, is_dir) {
+                    excluded = !rule.negated;
+                }
+            }
+            dir.push(&names[depth]);
+        }
+        excluded
+    }
+
+    fn rules_in(&mut self, dir: &Path) -> &[Rule] {
+        self.rules.entry(dir.to_path_buf()).or_insert_with(|| {
+            std::fs::read_to_string(dir.join(Self::FILE_NAME))
+                .map(|contents| contents.lines().filter_map(Rule::parse).collect())
+                .unwrap_or_default()
+        })
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let components = line
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        if components.is_empty() {
+            return None;
+        }
+        Some(Self {
+            negated,
+            dir_only,
+            anchored,
+            components,
+        })
+    }
+
+    /// Checks whether the path made of `names`, relative to the directory of
+    /// the rule, matches it.
+    fn matches(&self, names: &[String], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            matches_components(&self.components, names)
+        } else {
+            (names.last()).is_some_and(|name| glob::matches_component(&self.components[0], name))
+        }
+    }
+}
+
+fn matches_components(pattern: &[String], names: &[String]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((component, rest)) if component == "**" => {
+            (0..=names.len()).any(|skip| matches_components(rest, &names[skip..]))
+        }
+        Some((component, rest)) => names.split_first().is_some_and(|(name, names)| {
+            glob::matches_component(component, name) && matches_components(rest, names)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(path: &str) -> Vec<String> {
+        path.split('/').map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn parses_and_matches_rules() {
+        assert!(Rule::parse("").is_none());
+        assert!(Rule::parse("# comment").is_none());
+        assert!(Rule::parse("/").is_none());
+
+        let rule = Rule::parse("*.log").unwrap();
+        assert!(rule.matches(&names("a/b/x.log"), false));
+        assert!(!rule.matches(&names("x.log/b"), false));
+
+        let rule = Rule::parse("build/").unwrap();
+        assert!(rule.dir_only);
+        assert!(rule.matches(&names("build"), true));
+        assert!(!rule.matches(&names("build"), false));
+
+        let rule = Rule::parse("docs/*.md").unwrap();
+        assert!(rule.anchored);
+        assert!(rule.matches(&names("docs/a.md"), false));
+        assert!(!rule.matches(&names("x/docs/a.md"), false));
+
+        let rule = Rule::parse("a/**/z").unwrap();
+        assert!(rule.matches(&names("a/z"), false));
+        assert!(rule.matches(&names("a/b/c/z"), false));
+        assert!(!rule.matches(&names("b/z"), false));
+
+        assert!(Rule::parse("!keep.log").unwrap().negated);
+    }
+
+    #[test]
+    fn honors_nested_files_and_negations() {
+        let root = std::env::temp_dir().join(format!("path-tagging-ignore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sub/target")).unwrap();
+        std::fs::write(root.join(TagIgnore::FILE_NAME), "*.log\ntarget/\n").unwrap();
+        std::fs::write(root.join("sub").join(TagIgnore::FILE_NAME), "!keep.log\n").unwrap();
+
+        let mut ignore = TagIgnore::new(&root);
+        assert!(ignore.is_ignored(&root.join("a.log")));
+        assert!(ignore.is_ignored(&root.join("sub/b.log")));
+        assert!(!ignore.is_ignored(&root.join("sub/keep.log")));
+        assert!(ignore.is_ignored(&root.join("sub/target")));
+        assert!(ignore.is_ignored(&root.join("sub/target/a.rs")));
+        assert!(!ignore.is_ignored(&root.join("sub/a.rs")));
+        assert!(!ignore.is_ignored(Path::new("/elsewhere/a.log")));
+    }
+}
@@ -1,18 +1,72 @@
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::io;
-use std::path::{Path, PathBuf};
+use std::num::NonZeroUsize;
+#[cfg(unix)]
+use std::os::unix::fs::symlink as symlink_file;
+#[cfg(windows)]
+use std::os::windows::fs::symlink_file;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use derive_new::new;
 use itertools::Itertools;
 use linked_hash_set::LinkedHashSet;
 use thiserror::Error;
 
-#[derive(Clone, Debug, Default, Eq, new, PartialEq, getset::Getters, getset::MutGetters)]
+/// Emits a [`log`] record when the `logging` feature is enabled, so hosts can
+/// observe what the library does.
+///
+/// Without it, the arguments are only type-checked, never evaluated.
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "logging")]
+        log::$level!($($arg)+);
+        #[cfg(not(feature = "logging"))]
+        let _ = || {
+            let _ = format!($($arg)+);
+        };
+    }};
+}
+
+mod glob;
+mod ignore;
+mod profile;
+mod query;
+mod sha256;
+mod toml;
+#[cfg(feature = "zstd")]
+mod zstd;
+
+pub use ignore::TagIgnore;
+pub use profile::Profile;
+pub use query::{Query, QueryError};
+
+#[derive(
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    new,
+    PartialEq,
+    getset::Getters,
+    getset::MutGetters,
+    serde::Deserialize,
+)]
 #[getset(get = "pub", get_mut = "pub")]
 pub struct PathMetadata {
     tags: HashSet<String>,
 }
 
+/// Whether a path is meant as a file or a directory, for where its metadata
+/// is stored.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PathKind {
+    File,
+    Dir,
+}
+
 /// A raw tag.
 #[derive(
     Clone,
@@ -30,12 +84,237 @@ pub struct PathMetadata {
 pub struct RawTag {
     /// Tags whose paths are included in this tag. This is the inverse of
     /// [`inheritedTags`].
+    #[serde(serialize_with = "serialize_sorted")]
     include_tags: HashSet<String>,
     /// Tags for [`paths`] to inherit.
+    #[serde(serialize_with = "serialize_sorted")]
     inherited_tags: HashSet<String>,
     /// Paths declared to this tag inherits tags through [`inheritedTags`] if
     /// any.
+    #[serde(serialize_with = "serialize_sorted")]
     paths: HashSet<PathBuf>,
+    /// Glob patterns whose matches on the filesystem are also paths of this
+    /// tag, found on resolution. Like paths, they should be absolute.
+    ///
+    /// Within a component, `*` matches any run of characters and `?` any
+    /// single one; a `**` component matches any number of directories.
+    #[new(default)]
+    #[serde(
+        default,
+        skip_serializing_if = "HashSet::is_empty",
+        serialize_with = "serialize_sorted"
+    )]
+    path_globs: HashSet<String>,
+    /// Whether the file of this tag is kept even while empty.
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    persistent: bool,
+    /// The matches of [`pathGlobs`] found when this tag was resolved. They're
+    /// never saved, so that the tag keeps following its globs.
+    #[new(default)]
+    #[getset(skip)]
+    #[serde(skip)]
+    glob_matches: HashSet<PathBuf>,
+    /// The paths as stored whose environment variables were expanded when
+    /// this tag was resolved, by their expansion. Saving writes them back as
+    /// stored.
+    #[new(default)]
+    #[getset(skip)]
+    #[serde(skip)]
+    spellings: HashMap<PathBuf, PathBuf>,
+}
+
+/// What [`RawTag::merge_in_place`] added to a tag.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, getset::CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct MergeReport {
+    /// The number of includes added.
+    include_tags: usize,
+    /// The number of inherits added.
+    inherited_tags: usize,
+    /// The number of paths added.
+    paths: usize,
+    /// The number of path globs added.
+    path_globs: usize,
+}
+
+/// Formatting used when saving tag files.
+///
+/// Set elements are always written sorted, so saving the same tag twice yields
+/// identical bytes.
+#[derive(Clone, Debug, Eq, new, PartialEq, getset::CopyGetters, getset::Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct SaveOptions {
+    /// Number of spaces per indentation level.
+    indent: usize,
+    /// Whether to end the file with a newline.
+    trailing_newline: bool,
+}
+
+/// A normalization applied to tag names as they are given.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameTransform {
+    /// Names are kept as given.
+    #[default]
+    None,
+    /// Names are lowercased, like `Rust Lang` to `rust lang`.
+    Lowercase,
+    /// Names are lowercased with runs of spaces, `_` and `-` replaced by a
+    /// single `-`, like `Rust Lang` to `rust-lang`.
+    Kebab,
+}
+
+/// The format of the files of a [`Store`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagFormat {
+    /// Tags are written as JSON, in `.json` files.
+    #[default]
+    Json,
+    /// Tags are written as TOML, in `.toml` files.
+    Toml,
+}
+
+/// A directory of tag files.
+#[derive(
+    Clone, Debug, Eq, new, PartialEq, getset::Getters, getset::MutGetters, getset::Setters,
+)]
+#[getset(get = "pub", get_mut = "pub", set = "pub")]
+pub struct Store {
+    /// The directory containing the tag files.
+    root: PathBuf,
+    /// Formatting used when saving tag files.
+    #[new(default)]
+    save_options: SaveOptions,
+    /// The directory that paths under it are stored relative to, if any.
+    ///
+    /// Loaded relative paths are resolved against it, keeping the store
+    /// portable as long as the base moves along with it. Paths outside the
+    /// base are stored as is.
+    #[new(default)]
+    base: Option<PathBuf>,
+    /// Whether to expand `$VAR` and `${VAR}` in paths when resolving.
+    ///
+    /// Loading keeps paths as stored, and saving a resolved tag writes the
+    /// expanded paths back as they were stored.
+    #[new(default)]
+    expand_env: bool,
+    /// Whether to write a SHA-256 checksum beside each tag file and verify it
+    /// when loading.
+    ///
+    /// Tag files without a checksum are loaded unverified.
+    #[new(default)]
+    checksum: bool,
+    /// Tags that tagging a path always adds along with the given ones.
+    ///
+    /// Untagging only removes them when given explicitly.
+    #[new(default)]
+    default_tags: HashSet<String>,
+    /// Whether to keep the files of tags that become empty, as if every tag
+    /// were [`RawTag::persistent`].
+    #[new(default)]
+    keep_empty: bool,
+    /// The normalization applied to tag names given to the command line.
+    #[new(default)]
+    name_transform: NameTransform,
+    /// Whether to [sanitize](RawTag::sanitize) tags as they are loaded.
+    #[new(default)]
+    sanitize: bool,
+    /// Whether saving an existing tag appends its changes to an operation log
+    /// beside it instead of rewriting it.
+    ///
+    /// Loading replays the log regardless, and [`Self::compact`] folds it back
+    /// into the tag file. Only the tag file is checksummed.
+    #[new(default)]
+    append_only: bool,
+    /// The format tag files are saved in.
+    ///
+    /// Tags load from whichever of their files exists, so changing it
+    /// converts each tag as it is next saved, and [`Self::convert`] does so
+    /// right away.
+    #[new(default)]
+    format: TagFormat,
+    /// Whether to save tag files compressed with Zstandard, such as
+    /// `.json.zst`, which likewise applies to each tag as it is next saved.
+    ///
+    /// Compressed files can only be read and written with the `zstd` feature.
+    #[new(default)]
+    compress: bool,
+    /// Tags modified in memory that are yet to be flushed.
+    #[getset(skip)]
+    #[new(default)]
+    pending: HashMap<String, RawTag>,
+}
+
+/// Defaults for a [`Store`] and the command line read from a TOML
+/// configuration file, such as `checksum = true`.
+///
+/// Relative paths are relative to the directory of the configuration file.
+#[derive(Clone, Debug, Default, Eq, PartialEq, getset::Getters, serde::Deserialize)]
+#[getset(get = "pub")]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The directory containing the tag files.
+    store: Option<PathBuf>,
+    /// Number of spaces per indentation level of saved tag files.
+    indent: Option<usize>,
+    /// Whether to end saved tag files with a newline.
+    trailing_newline: Option<bool>,
+    /// The directory that paths under it are stored relative to.
+    base: Option<PathBuf>,
+    /// Whether to expand environment variables in paths when resolving.
+    expand_env: Option<bool>,
+    /// Whether to write and verify checksums of tag files.
+    checksum: Option<bool>,
+    /// Tags that tagging a path always adds.
+    default_tags: Option<HashSet<String>>,
+    /// Whether to keep the files of empty tags.
+    keep_empty: Option<bool>,
+    /// The normalization applied to given tag names.
+    name_transform: Option<NameTransform>,
+    /// Whether to sanitize tags as they are loaded.
+    sanitize: Option<bool>,
+    /// Whether to append the changes of tags to operation logs.
+    append_only: Option<bool>,
+    /// The format of saved tag files: `json` or `toml`.
+    store_format: Option<TagFormat>,
+    /// Whether to compress saved tag files with Zstandard.
+    compress: Option<bool>,
+    /// Whether the command line matches tag names regardless of case unless
+    /// told otherwise.
+    ignore_case: Option<bool>,
+    /// The format the command line displays results in, such as `json`.
+    format: Option<String>,
+    /// When the command line colors its reports: `auto`, `always` or `never`.
+    color: Option<String>,
+}
+
+/// A lock on a whole [`Store`] from [`Store::lock`], released when dropped.
+#[derive(Debug)]
+pub struct StoreLock {
+    path: PathBuf,
+}
+
+/// A change to a tag, as recorded in its operation log.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case", tag = "op", content = "value")]
+enum TagOp {
+    AddInclude(String),
+    RemoveInclude(String),
+    AddInherit(String),
+    RemoveInherit(String),
+    AddPath(PathBuf),
+    RemovePath(PathBuf),
+    AddGlob(String),
+    RemoveGlob(String),
+    SetPersistent(bool),
+}
+
+/// The in-memory state of a [`Store`] to [`Store::restore`] later.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pending: HashMap<String, RawTag>,
 }
 
 #[derive(Clone, Debug, getset::Getters, getset::MutGetters)]
@@ -49,10 +328,44 @@ pub struct ResolvedTags {
 pub enum IoTagError {
     #[error("unable to access this executable's directory")]
     Resolve(io::Error),
-    #[error("i/o errors")]
-    Io(#[from] io::Error),
+    #[error("failed to {operation} {}: {source}", path.display())]
+    Io {
+        operation: IoOperation,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
     #[error("(de)serialization error")]
     Serde(#[from] serde_json::Error),
+    #[error("invalid TOML in {}: {reason}", path.display())]
+    Toml { path: PathBuf, reason: String },
+    #[error("invalid Zstandard data in {}: {reason}", path.display())]
+    Zstd { path: PathBuf, reason: String },
+    #[error("{} is compressed, but Zstandard support isn't compiled in", .0.display())]
+    ZstdUnsupported(PathBuf),
+    #[error("invalid tag name {0:?}")]
+    InvalidName(PathBuf),
+    #[error("environment variable {0:?} is unset or not unicode")]
+    Env(String),
+    #[error("unclosed \"${{\" in path {0:?}")]
+    EnvSyntax(PathBuf),
+    #[error("tag {0:?} already exists")]
+    Exists(String),
+    #[error("checksum mismatch for {0:?}")]
+    Checksum(PathBuf),
+    #[error("invalid path glob {pattern:?}: {reason}")]
+    Glob { pattern: String, reason: String },
+    #[error("lock {0:?} is already held")]
+    Locked(PathBuf),
+}
+
+/// The file operations an [`IoTagError::Io`] can fail at.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum IoOperation {
+    Read,
+    Write,
+    Remove,
+    CreateDir,
 }
 
 #[derive(Debug, Error, new)]
@@ -63,8 +376,10 @@ pub enum ResolveError {
         #[source]
         source: IoTagError,
     },
-    #[error("unable to load due to (de)serialization errors")]
+    #[error("unable to resolve due to tags referring to each other in a cycle")]
     Cyclic { path: ResolvePath },
+    #[error("resolution was cancelled")]
+    Cancelled,
 }
 
 #[derive(Clone, Debug)]
@@ -72,6 +387,57 @@ pub struct ResolvePath {
     inner: std::vec::IntoIter<String>,
 }
 
+/// An iterator over the metadata files under a directory, from
+/// [`PathMetadata::load_all_under`].
+#[derive(Debug)]
+pub struct LoadAllUnder {
+    root: Option<PathBuf>,
+    stack: Vec<(PathBuf, std::fs::ReadDir)>,
+    ignore: Option<TagIgnore>,
+}
+
+/// Figures about the tags of a [`ResolvedTags`], from [`ResolvedTags::stats`].
+#[derive(Clone, Debug, Default, Eq, PartialEq, getset::CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct GraphStats {
+    /// The number of tags loaded.
+    tags: usize,
+    /// The number of includes among the loaded tags.
+    include_edges: usize,
+    /// The number of inherits among the loaded tags.
+    inherit_edges: usize,
+    /// The length of the longest chain of includes and inherits from the
+    /// resolved raw tag.
+    max_depth: usize,
+    /// The number of paths declared to the loaded tags, counting each once
+    /// per tag declaring it.
+    paths: usize,
+    /// The number of distinct paths declared to the loaded tags.
+    distinct_paths: usize,
+    /// The references to tags that weren't loaded.
+    #[getset(skip)]
+    broken_refs: Vec<BrokenRef>,
+}
+
+/// A reference from one tag to another that failed to load.
+#[derive(Clone, Debug, Eq, Hash, new, Ord, PartialEq, PartialOrd, getset::Getters)]
+#[getset(get = "pub")]
+pub struct BrokenRef {
+    /// The referencing tag, or [`None`] for the resolved raw tag itself.
+    from: Option<String>,
+    /// The missing tag.
+    to: String,
+    /// How `from` refers to `to`.
+    kind: RefKind,
+}
+
+/// The ways a tag refers to another.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RefKind {
+    Include,
+    Inherit,
+}
+
 impl RawTag {
     #[inline]
     #[must_use]
@@ -82,19 +448,54 @@ impl RawTag {
         }
     }
 
+    /// Adds `paths` to this tag, such as in
+    /// `RawTag::default().with_paths(["a"]).with_includes(["b"])`.
+    #[must_use]
+    pub fn with_paths<I: IntoIterator<Item = impl Into<PathBuf>>>(mut self, paths: I) -> Self {
+        self.paths.extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds `tags` to the includes of this tag, like [`Self::with_paths`].
+    #[must_use]
+    pub fn with_includes<I: IntoIterator<Item = impl Into<String>>>(mut self, tags: I) -> Self {
+        self.include_tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds `tags` to the inherits of this tag, like [`Self::with_paths`].
+    #[must_use]
+    pub fn with_inherits<I: IntoIterator<Item = impl Into<String>>>(mut self, tags: I) -> Self {
+        self.inherited_tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Creates a query like [`Self::query`] that also inherits tags.
+    #[inline]
+    #[must_use]
+    pub fn query_all(include_tags: HashSet<String>, inherited_tags: HashSet<String>) -> Self {
+        Self {
+            include_tags,
+            inherited_tags,
+            ..Default::default()
+        }
+    }
+
     #[inline]
     pub fn resolve<P: AsRef<Path>>(name: P) -> io::Result<PathBuf> {
-        let name = name.as_ref();
-        Ok(if name.is_absolute() {
-            name.into()
-        } else {
-            let mut path = std::env::current_exe()?;
-            path.pop();
-            path.push(".tags");
-            path.push(name);
-            path.set_extension("json");
-            path
-        })
+        Ok(Store::beside_exe()?.resolve(name))
+    }
+
+    /// Parses a query such as `a AND (b OR c) NOT d`.
+    ///
+    /// See [`Query`] for the syntax.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with the position of invalid syntax.
+    #[inline]
+    pub fn from_query_str(source: &str) -> Result<Query, QueryError> {
+        Query::parse(source)
     }
 
     /// Loads a raw tag.
@@ -110,235 +511,3897 @@ impl RawTag {
     ///  * parsing error
     #[inline]
     pub fn load<P: AsRef<Path>>(name: P) -> Result<Self, IoTagError> {
-        let path = Self::resolve(name).map_err(IoTagError::Resolve)?;
-        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+        Store::beside_exe().map_err(IoTagError::Resolve)?.load(name)
     }
 
     #[inline]
     pub fn save<P: AsRef<Path>>(&self, name: P) -> Result<(), IoTagError> {
-        let path = Self::resolve(name).map_err(IoTagError::Resolve)?;
-        if self.is_empty() {
-            std::fs::remove_file(path)?;
-        } else {
-            std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        self.save_with(name, &SaveOptions::default())
+    }
+
+    /// Saves a raw tag formatted according to `options`, or removes its file
+    /// if empty and not persistent.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * relative path resolution fails
+    ///  * serialization error
+    ///  * I/O error when writing or removing the file
+    #[inline]
+    pub fn save_with<P: AsRef<Path>>(
+        &self,
+        name: P,
+        options: &SaveOptions,
+    ) -> Result<(), IoTagError> {
+        let mut store = Store::beside_exe().map_err(IoTagError::Resolve)?;
+        store.set_save_options(options.clone());
+        store.save(name, self)
+    }
+
+    /// Removes a path from every tag beside the current executable and clears
+    /// its metadata.
+    ///
+    /// See [`Store::remove_path_everywhere`].
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Store::remove_path_everywhere`] or when relative path
+    /// resolution fails.
+    #[inline]
+    pub fn remove_path_everywhere<P: AsRef<Path>>(path: P) -> Result<Vec<String>, IoTagError> {
+        Store::beside_exe()
+            .map_err(IoTagError::Resolve)?
+            .remove_path_everywhere(path)
+    }
+
+    /// Finds the tags of the store at `root` including or inheriting `name`.
+    ///
+    /// See [`Store::referencing`].
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Store::referencing`].
+    #[inline]
+    pub fn is_referenced_by<P: AsRef<Path>>(
+        name: &str,
+        root: P,
+    ) -> Result<Vec<String>, IoTagError> {
+        Store::new(root.as_ref().to_path_buf()).referencing(name)
+    }
+
+    /// Serializes a raw tag formatted according to `options`.
+    ///
+    /// Output is canonical: set elements are written sorted, so tags that are
+    /// equal once [normalized](Self::normalize) serialize to identical bytes.
+    ///
+    /// # Errors
+    ///
+    /// Fails when serialization fails.
+    pub fn to_vec_with(&self, options: &SaveOptions) -> serde_json::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.save_to(&mut bytes, options)?;
+        Ok(bytes)
+    }
+
+    /// Writes a raw tag to `writer` formatted according to `options`, as
+    /// [`Self::to_vec_with`] does.
+    ///
+    /// # Errors
+    ///
+    /// Fails when serialization or writing fails.
+    pub fn save_to<W: io::Write>(
+        &self,
+        mut writer: W,
+        options: &SaveOptions,
+    ) -> serde_json::Result<()> {
+        let indent = " ".repeat(options.indent);
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+        let mut serializer = serde_json::Serializer::with_formatter(&mut writer, formatter);
+        serde::Serialize::serialize(self, &mut serializer)?;
+        if options.trailing_newline {
+            writer.write_all(b"\n").map_err(serde_json::Error::io)?;
         }
         Ok(())
     }
 
+    /// Reads a raw tag from `reader`, as it was written by [`Self::save_to`].
+    ///
+    /// Paths are taken as they are written, without the base or environment
+    /// variable expansion of a [`Store`].
+    ///
+    /// # Errors
+    ///
+    /// Fails when reading or parsing fails.
     #[inline]
-    #[must_use]
-    pub fn is_empty(&self) -> bool {
-        self.include_tags.is_empty() && self.inherited_tags.is_empty() && self.paths.is_empty()
+    pub fn load_from<R: io::Read>(reader: R) -> serde_json::Result<Self> {
+        serde_json::from_reader(reader)
     }
-}
 
-impl ResolvePath {
-    #[inline]
-    fn new(path: LinkedHashSet<String>, cause: String) -> Self {
-        // FIXME: is there a better way to get an at-least debuggable iterator?
-        let mut inner = path.into_iter().collect_vec();
-        inner.push(cause);
-        inner.into_iter().collect()
+    /// Rewrites paths into their canonical spelling without changing what
+    /// they refer to, e.g. `a/./b/` into `a/b`, and releases excess capacity.
+    ///
+    /// Paths spelled differently compare equal already, but would otherwise be
+    /// serialized as spelled.
+    pub fn normalize(&mut self) {
+        self.paths = self
+            .paths
+            .drain()
+            .map(|path| path.components().collect())
+            .collect();
+        self.include_tags.shrink_to_fit();
+        self.inherited_tags.shrink_to_fit();
+        self.path_globs.shrink_to_fit();
     }
-}
 
-impl ResolvedTags {
+    #[inline]
     #[must_use]
-    pub fn contains(&self, path: &PathBuf) -> bool {
-        self.raw.paths.contains(path)
-            || self
-                .raw
-                .include_tags
-                .iter()
-                .filter_map(|key| self.tags.get(key))
-                .any(|tag| tag.paths.contains(path))
+    pub fn is_empty(&self) -> bool {
+        self.include_tags.is_empty()
+            && self.inherited_tags.is_empty()
+            && self.paths.is_empty()
+            && self.path_globs.is_empty()
+    }
+
+    /// Replaces the glob matches with the current matches of
+    /// [`Self::path_globs`].
+    fn expand_globs(&mut self) {
+        self.glob_matches = (self.path_globs.iter())
+            .flat_map(|pattern| glob::expand(Path::new(pattern)))
+            .collect();
     }
 
+    /// The paths matching [`Self::path_globs`] but not necessarily declared,
+    /// as found when this tag was resolved; none otherwise.
     #[inline]
     #[must_use]
-    pub fn union(&self) -> HashSet<PathBuf> {
-        Self::union_at(&self.tags, &self.raw)
+    pub fn glob_matches(&self) -> &HashSet<PathBuf> {
+        &self.glob_matches
     }
 
+    /// Iterates over the paths declared to this tag and then its glob matches,
+    /// which may repeat declared paths.
     #[inline]
-    #[must_use]
-    pub fn union_at(tags: &HashMap<String, RawTag>, tag: &RawTag) -> HashSet<PathBuf> {
-        let mut set = HashSet::new();
-        Self::union_helper(tags, tag, &mut set);
-        set
+    pub fn iter_resolved_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.paths.iter().chain(&self.glob_matches)
     }
 
-    fn union_helper(tags: &HashMap<String, RawTag>, raw: &RawTag, set: &mut HashSet<PathBuf>) {
-        for tag in raw.include_tags.iter().filter_map(|key| tags.get(key)) {
-            Self::union_helper(tags, tag, set);
+    /// Removes references to `name` itself, empty tag names, empty paths and
+    /// empty globs, which a hand-edited tag named `name` may contain.
+    ///
+    /// Returns a tag holding everything removed.
+    pub fn sanitize(&mut self, name: &str) -> Self {
+        fn take<T: Eq + std::hash::Hash>(
+            set: &mut HashSet<T>,
+            mut f: impl FnMut(&T) -> bool,
+        ) -> HashSet<T> {
+            let (removed, kept) = set.drain().partition(|item| f(item));
+            *set = kept;
+            removed
+        }
+
+        let invalid = |key: &String| key.is_empty() || key == name;
+        Self {
+            include_tags: take(&mut self.include_tags, invalid),
+            inherited_tags: take(&mut self.inherited_tags, invalid),
+            paths: take(&mut self.paths, |path| path.as_os_str().is_empty()),
+            path_globs: take(&mut self.path_globs, String::is_empty),
+            persistent: false,
+            glob_matches: HashSet::new(),
+            spellings: HashMap::new(),
         }
-        set.extend(raw.paths.iter().cloned());
     }
 
-    #[must_use]
-    pub fn intersection(&self) -> HashSet<PathBuf> {
-        fn fallible_intersection(
-            lhs: Option<HashSet<PathBuf>>,
-            rhs: Option<HashSet<PathBuf>>,
-        ) -> Option<HashSet<PathBuf>> {
-            let mut lhs = lhs?;
-            let mut rhs = rhs?;
-            if rhs.capacity() < lhs.capacity() {
-                std::mem::swap(&mut lhs, &mut rhs);
-            }
-            lhs.retain(|path| rhs.contains(path));
-            Some(lhs)
+    /// Replaces `old` with `new` among the includes and inherits of this tag,
+    /// returning whether either referred to `old`.
+    pub fn rename_included(&mut self, old: &str, new: &str) -> bool {
+        let included = retarget(&mut self.include_tags, old, new);
+        let inherited = retarget(&mut self.inherited_tags, old, new);
+        included || inherited
+    }
+
+    /// Adds the references, paths and globs of `other` to this tag, keeping it
+    /// persistent if either is.
+    ///
+    /// Returns how many of each this tag didn't have yet.
+    pub fn merge_in_place(&mut self, other: &Self) -> MergeReport {
+        fn extend<T: Clone + Eq + std::hash::Hash>(
+            set: &mut HashSet<T>,
+            other: &HashSet<T>,
+        ) -> usize {
+            let len = set.len();
+            set.extend(other.iter().cloned());
+            set.len() - len
         }
 
-        let mut set = self
-            .raw
-            .include_tags
-            .iter()
-            .map(|key| Some(Self::union_at(&self.tags, self.tags.get(key)?)))
-            .tree_reduce(fallible_intersection)
-            .flatten()
-            .unwrap_or_default();
+        self.persistent |= other.persistent;
+        extend(&mut self.glob_matches, &other.glob_matches);
+        for (expanded, stored) in &other.spellings {
+            (self.spellings.entry(expanded.clone())).or_insert_with(|| stored.clone());
+        }
+        MergeReport {
+            include_tags: extend(&mut self.include_tags, &other.include_tags),
+            inherited_tags: extend(&mut self.inherited_tags, &other.inherited_tags),
+            paths: extend(&mut self.paths, &other.paths),
+            path_globs: extend(&mut self.path_globs, &other.path_globs),
+        }
+    }
 
-        set.extend(self.raw.paths.iter().cloned());
-        set
+    /// Keeps only the paths declared to this tag that `f` returns `true` for,
+    /// returning how many were removed.
+    pub fn retain_paths(&mut self, f: impl FnMut(&PathBuf) -> bool) -> usize {
+        let len = self.paths.len();
+        self.paths.retain(f);
+        len - self.paths.len()
     }
 
+    /// The paths directly declared to this tag in sorted order.
+    #[inline]
     #[must_use]
-    pub fn all_tags(&self) -> HashSet<String> {
-        fn helper(tags: &mut HashSet<String>, raws: &HashMap<String, RawTag>, raw: &RawTag) {
-            for tag in raw.inherited_tags() {
-                if tags.insert(tag.clone()) {
-                    if let Some(raw) = raws.get(tag) {
-                        helper(tags, raws, raw);
-                    }
-                }
-            }
-        }
+    pub fn sorted_paths(&self) -> Vec<&PathBuf> {
+        self.paths.iter().sorted().collect()
+    }
 
-        let mut set = HashSet::new();
-        for tag in self.raw.include_tags() {
-            if set.insert(tag.clone()) {
-                if let Some(raw) = self.tags.get(tag) {
-                    helper(&mut set, &self.tags, raw);
-                }
-            }
-        }
-        set
+    /// The includes of this tag in sorted order.
+    #[inline]
+    #[must_use]
+    pub fn sorted_include_tags(&self) -> Vec<&String> {
+        self.include_tags.iter().sorted().collect()
     }
-}
 
-impl From<ResolvedTags> for RawTag {
+    /// The inherits of this tag in sorted order.
     #[inline]
-    fn from(resolved: ResolvedTags) -> Self {
-        resolved.raw
+    #[must_use]
+    pub fn sorted_inherited_tags(&self) -> Vec<&String> {
+        self.inherited_tags.iter().sorted().collect()
     }
-}
 
-impl FromIterator<String> for ResolvePath {
+    /// The number of paths directly declared to this tag.
     #[inline]
-    fn from_iter<T: IntoIterator<Item = String>>(iter: T) -> Self {
-        let inner = iter.into_iter().collect_vec().into_iter();
-        Self { inner }
+    #[must_use]
+    pub fn paths_len(&self) -> usize {
+        self.paths.len()
     }
-}
 
-impl Iterator for ResolvePath {
-    type Item = String;
+    /// The number of tags this tag directly includes.
+    #[inline]
+    #[must_use]
+    pub fn include_tags_len(&self) -> usize {
+        self.include_tags.len()
+    }
 
+    /// The number of tags the paths of this tag directly inherit.
     #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+    #[must_use]
+    pub fn inherited_tags_len(&self) -> usize {
+        self.inherited_tags.len()
     }
-}
 
-impl PathMetadata {
-    pub const EXTENSION_PREFIX: &'static str = ".tag.list";
+    /// Iterates over the paths directly declared to this tag.
+    #[inline]
+    pub fn iter_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.paths.iter()
+    }
 
+    /// Iterates over the tags whose paths this tag directly includes.
     #[inline]
-    pub fn resolve<P: AsRef<Path>>(path: P) -> PathBuf {
-        let path = path.as_ref();
-        if path.is_file() {
-            let mut file_name = path.file_name().unwrap_or_default().to_os_string();
-            file_name.push(Self::EXTENSION_PREFIX);
-            path.with_file_name(file_name)
-        } else {
-            path.join(Self::EXTENSION_PREFIX)
-        }
+    pub fn iter_includes(&self) -> impl Iterator<Item = &String> {
+        self.include_tags.iter()
     }
 
+    /// Iterates over the tags the paths of this tag directly inherit.
     #[inline]
-    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let path = Self::resolve(path.as_ref());
-        let tags = std::fs::read_to_string(path)?.lines().map_into().collect();
-        Ok(Self::new(tags))
+    pub fn iter_inherits(&self) -> impl Iterator<Item = &String> {
+        self.inherited_tags.iter()
     }
 
+    /// Checks whether `path` is directly declared to this tag.
     #[inline]
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        let path = Self::resolve(path.as_ref());
-        if self.is_empty() {
-            std::fs::remove_file(path)?;
-        } else {
-            std::fs::write(path, self.tags.iter().join("\n"))?;
-        }
-        Ok(())
+    #[must_use]
+    pub fn contains_path<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.paths.contains(path.as_ref())
     }
 
+    /// Checks whether this tag directly includes the paths of `tag`.
     #[inline]
     #[must_use]
-    pub fn is_empty(&self) -> bool {
-        self.tags.is_empty()
+    pub fn contains_include(&self, tag: &str) -> bool {
+        self.include_tags.contains(tag)
+    }
+
+    /// Checks whether the paths of this tag directly inherit `tag`.
+    #[inline]
+    #[must_use]
+    pub fn contains_inherit(&self, tag: &str) -> bool {
+        self.inherited_tags.contains(tag)
     }
 }
 
-impl TryFrom<RawTag> for ResolvedTags {
-    type Error = ResolveError;
+impl Store {
+    /// The name of the lock file in the root of a store, see [`Self::lock`].
+    pub const LOCK_FILE_NAME: &'static str = ".path-tagging.lock";
 
+    /// The store in the `.tags` directory beside the current executable.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the current executable's path cannot be accessed.
     #[inline]
-    fn try_from(raw: RawTag) -> Result<Self, Self::Error> {
-        fn helper(
-            mut path: LinkedHashSet<String>,
-            tags: &mut HashMap<String, RawTag>,
-            raw: &RawTag,
-        ) -> Result<LinkedHashSet<String>, ResolveError> {
-            let keys = raw.include_tags.union(raw.inherited_tags());
-            for key in keys {
-                if path.contains(key) {
-                    return Err(ResolveError::new_cyclic(ResolvePath::new(
-                        path,
-                        key.clone(),
-                    )));
+    pub fn beside_exe() -> io::Result<Self> {
+        let mut root = std::env::current_exe()?;
+        root.pop();
+        root.push(".tags");
+        Ok(Self::new(root))
+    }
+
+    /// Resolves the file of a tag; relative to this store's root unless
+    /// absolute.
+    ///
+    /// That is the file of the tag that exists in any format, preferring the
+    /// one [`Self::format`] and [`Self::compress`] select, or else that one.
+    #[must_use]
+    pub fn resolve<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        let name = name.as_ref();
+        if name.is_absolute() {
+            return name.into();
+        }
+        let preferred = self.resolve_as(name, self.format);
+        if io_path(&preferred).is_file() {
+            return preferred;
+        }
+        let existing = [TagFormat::Json, TagFormat::Toml]
+            .into_iter()
+            .flat_map(|format| [false, true].map(|compress| self.file_of(name, format, compress)))
+            .find(|path| io_path(path).is_file());
+        existing.unwrap_or(preferred)
+    }
+
+    /// Resolves the file a tag is saved to in `format`, compressed if
+    /// [`Self::compress`] is set, regardless of the files that exist.
+    #[must_use]
+    pub fn resolve_as<P: AsRef<Path>>(&self, name: P, format: TagFormat) -> PathBuf {
+        let name = name.as_ref();
+        if name.is_absolute() {
+            return name.into();
+        }
+        self.file_of(name, format, self.compress)
+    }
+
+    fn file_of(&self, name: &Path, format: TagFormat, compress: bool) -> PathBuf {
+        let mut path = self.root.join(name);
+        path.set_extension(format.extension());
+        if compress {
+            let mut compressed = path.into_os_string();
+            compressed.push(".zst");
+            path = compressed.into();
+        }
+        path
+    }
+
+    /// Resolves the file of a tag like [`Self::resolve`], rejecting names
+    /// with `..` components.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`IoTagError::InvalidName`] if the name escapes this store's
+    /// root.
+    pub fn resolve_checked<P: AsRef<Path>>(&self, name: P) -> Result<PathBuf, IoTagError> {
+        let name = name.as_ref();
+        if name
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            return Err(IoTagError::InvalidName(name.into()));
+        }
+        Ok(self.resolve(name))
+    }
+
+    /// Loads a raw tag from this store, as [modified in memory](Self::tag_mut)
+    /// if it is.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * the name escapes this store's root
+    ///  * I/O error when reading bytes
+    ///  * parsing error
+    ///  * a path glob is malformed
+    ///  * the checksum doesn't match, if enabled
+    pub fn load<P: AsRef<Path>>(&self, name: P) -> Result<RawTag, IoTagError> {
+        let _timer = profile::Timer::load();
+        let name = name.as_ref();
+        let path = self.resolve_checked(name)?;
+        if let Some(tag) = name.to_str().and_then(|name| self.pending.get(name)) {
+            return Ok(tag.clone());
+        }
+        let mut tag = self.read_stored(&path)?;
+        if self.sanitize {
+            tag.sanitize(&name.to_string_lossy());
+        }
+        if let Some(base) = &self.base {
+            tag.paths = tag.paths.iter().map(|path| join_base(base, path)).collect();
+        }
+        for pattern in &tag.path_globs {
+            glob::validate(pattern).map_err(|reason| IoTagError::Glob {
+                pattern: pattern.clone(),
+                reason,
+            })?;
+        }
+        event!(
+            trace,
+            "Loaded tag {} from {}",
+            name.display(),
+            path.display()
+        );
+        Ok(tag)
+    }
+
+    /// Expands the environment variables in the paths of a loaded `tag` if
+    /// [enabled](Self::expand_env), remembering how they were stored.
+    fn expand_env_in(&self, tag: &mut RawTag) -> Result<(), IoTagError> {
+        if !self.expand_env {
+            return Ok(());
+        }
+        let mut paths = HashSet::with_capacity(tag.paths.len());
+        for path in tag.paths.drain() {
+            let stored = match &self.base {
+                Some(base) => path.strip_prefix(base).unwrap_or(&path),
+                None => &path,
+            };
+            let expanded = expand_env(stored)?;
+            if expanded.as_path() == stored {
+                paths.insert(path);
+                continue;
+            }
+            let expanded = match &self.base {
+                Some(base) => join_base(base, &expanded),
+                None => expanded,
+            };
+            tag.spellings.insert(expanded.clone(), path);
+            paths.insert(expanded);
+        }
+        tag.paths = paths;
+        Ok(())
+    }
+
+    /// Reads the tag file at `path` as it is stored, verifying its checksum
+    /// if enabled and replaying its operation log if any.
+    fn read_stored(&self, path: &Path) -> Result<RawTag, IoTagError> {
+        let bytes =
+            std::fs::read(io_path(path)).map_err(IoTagError::io(IoOperation::Read, path))?;
+        if self.checksum {
+            let checksum = checksum_path(path);
+            match std::fs::read_to_string(io_path(&checksum)) {
+                Ok(expected) if expected.trim() != sha256::hex_digest(&bytes) => {
+                    return Err(IoTagError::Checksum(path.into()));
                 }
+                Err(cause) if !matches!(cause.kind(), io::ErrorKind::NotFound) => {
+                    return Err(IoTagError::io(IoOperation::Read, &checksum)(cause));
+                }
+                _ => {}
+            }
+        }
+        let bytes = if is_compressed(path) {
+            Cow::Owned(decompress(path, &bytes)?)
+        } else {
+            Cow::Borrowed(bytes.as_slice())
+        };
+        let mut tag = match TagFormat::of(path) {
+            TagFormat::Json => RawTag::load_from(&*bytes)?,
+            TagFormat::Toml => {
+                let invalid = |reason: String| IoTagError::Toml {
+                    path: path.into(),
+                    reason,
+                };
+                let source =
+                    std::str::from_utf8(&bytes).map_err(|cause| invalid(cause.to_string()))?;
+                let value = toml::parse(source).map_err(|cause| invalid(cause.to_string()))?;
+                serde_json::from_value(value)?
+            }
+        };
 
-                path.insert(key.clone());
+        let log = log_path(path);
+        match std::fs::read_to_string(io_path(&log)) {
+            Ok(operations) => {
+                for line in operations.lines().filter(|line| !line.is_empty()) {
+                    serde_json::from_str::<TagOp>(line)?.apply(&mut tag);
+                }
+            }
+            Err(cause) if matches!(cause.kind(), io::ErrorKind::NotFound) => {}
+            Err(cause) => return Err(IoTagError::io(IoOperation::Read, &log)(cause)),
+        }
+        Ok(tag)
+    }
 
-                let tag = match RawTag::load(key) {
-                    Ok(tag) => Some(tag),
-                    Err(IoTagError::Resolve(_)) => None,
-                    Err(IoTagError::Io(cause))
-                        if matches!(cause.kind(), io::ErrorKind::NotFound) =>
-                    {
-                        None
-                    }
-                    Err(cause) => {
-                        return Err(ResolveError::new_load(path.into_iter().collect(), cause))
+    /// Saves a raw tag to this store, or removes its file if empty and neither
+    /// persistent nor kept by [`Self::keep_empty`].
+    ///
+    /// Directories for nested names such as `lang/rust` are created as needed.
+    /// With [`Self::append_only`], the changes to an existing tag are appended
+    /// to its operation log instead.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * the name escapes this store's root
+    ///  * serialization error
+    ///  * I/O error when writing or removing the file
+    pub fn save<P: AsRef<Path>>(&self, name: P, tag: &RawTag) -> Result<(), IoTagError> {
+        let name = name.as_ref();
+        let path = self.resolve_checked(name)?;
+        if tag.is_empty() && !tag.persistent && !self.keep_empty {
+            self.remove_files(&path)?;
+            event!(
+                debug,
+                "Removed empty tag {} at {}",
+                name.display(),
+                path.display()
+            );
+            return Ok(());
+        }
+        if name.components().nth(1).is_some() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(io_path(parent))
+                    .map_err(IoTagError::io(IoOperation::CreateDir, parent))?;
+            }
+        }
+
+        let stored = if self.base.is_some() || !tag.spellings.is_empty() {
+            let paths = tag
+                .paths
+                .iter()
+                .map(|path| tag.spellings.get(path).unwrap_or(path))
+                .map(|path| match &self.base {
+                    Some(base) => strip_base(base, path),
+                    None => path.clone(),
+                })
+                .collect();
+            Cow::Owned(RawTag {
+                paths,
+                ..tag.clone()
+            })
+        } else {
+            Cow::Borrowed(tag)
+        };
+        let target = self.resolve_as(name, self.format);
+        if self.append_only && io_path(&target).is_file() {
+            let operations = TagOp::diff(&self.read_stored(&target)?, &stored);
+            if !operations.is_empty() {
+                let log = log_path(&target);
+                let mut lines = Vec::new();
+                for operation in &operations {
+                    serde_json::to_writer(&mut lines, operation)?;
+                    lines.push(b'\n');
+                }
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(io_path(&log))
+                    .and_then(|mut file| io::Write::write_all(&mut file, &lines))
+                    .map_err(IoTagError::io(IoOperation::Write, &log))?;
+            }
+            event!(
+                debug,
+                "Appended {} operation(s) to tag {}",
+                operations.len(),
+                name.display()
+            );
+            return Ok(());
+        }
+
+        self.write_snapshot(&target, &stored)?;
+        if target != path {
+            // the tag was stored in another format
+            self.remove_files(&path)?;
+        }
+        event!(
+            debug,
+            "Saved tag {} to {}",
+            name.display(),
+            target.display()
+        );
+        Ok(())
+    }
+
+    /// Folds the operation log of a tag into its file, returning whether it
+    /// had any.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * the name escapes this store's root
+    ///  * I/O error when reading, writing or removing the files
+    ///  * parsing or serialization error
+    ///  * the checksum doesn't match, if enabled
+    pub fn compact<P: AsRef<Path>>(&self, name: P) -> Result<bool, IoTagError> {
+        let path = self.resolve_checked(name)?;
+        if !io_path(&log_path(&path)).is_file() {
+            return Ok(false);
+        }
+        let tag = self.read_stored(&path)?;
+        self.write_snapshot(&path, &tag)?;
+        Ok(true)
+    }
+
+    /// Rewrites the file of a tag in `format`, compressed if [`Self::compress`]
+    /// is set, folding its operation log in; returns whether it was stored
+    /// otherwise.
+    ///
+    /// The new file is complete before the old one is removed, so either
+    /// remains if converting is interrupted. Its checksum is written anew if
+    /// enabled. A tag whose file is a symbolic link to the file of another tag
+    /// of this store, an alias, gets a link to that tag's converted file
+    /// instead, converting it first.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * the name escapes this store's root
+    ///  * I/O error when reading, writing or removing the files
+    ///  * parsing or serialization error
+    ///  * the checksum doesn't match, if enabled
+    pub fn convert<P: AsRef<Path>>(&self, name: P, format: TagFormat) -> Result<bool, IoTagError> {
+        let name = name.as_ref();
+        let path = self.resolve_checked(name)?;
+        let target = self.resolve_as(name, format);
+        if target == path {
+            return Ok(false);
+        }
+        if let Some((link, original)) = self.alias_of(&path) {
+            self.convert(&original, format)?;
+            let converted = self.resolve_as(&original, format);
+            let link = link.with_file_name(converted.file_name().unwrap_or_default());
+            symlink_file(&link, io_path(&target))
+                .map_err(IoTagError::io(IoOperation::Write, &target))?;
+            self.remove_files(&path)?;
+            event!(
+                debug,
+                "Converted alias {} of tag {original} to {}",
+                name.display(),
+                target.display()
+            );
+            return Ok(true);
+        }
+        let tag = self.read_stored(&path)?;
+        self.write_snapshot(&target, &tag)?;
+        self.remove_files(&path)?;
+        event!(
+            debug,
+            "Converted tag {} from {} to {}",
+            name.display(),
+            path.display(),
+            target.display()
+        );
+        Ok(true)
+    }
+
+    /// Reads where the symbolic link at `path` points to along with the name
+    /// of the tag whose file that is; `None` unless it links to the file of
+    /// another tag of this store.
+    fn alias_of(&self, path: &Path) -> Option<(PathBuf, String)> {
+        let link = std::fs::read_link(io_path(path)).ok()?;
+        let file = path.parent()?.join(&link);
+        let name = tag_name(&self.root, &file)?;
+        let escapes = Path::new(&name)
+            .components()
+            .any(|component| component == Component::ParentDir);
+        (!escapes && io_path(&file).is_file()).then_some((link, name))
+    }
+
+    /// Removes the file of a tag along with its checksum and operation log,
+    /// even if it is persistent or kept by [`Self::keep_empty`].
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * the name escapes this store's root
+    ///  * I/O error when removing the files, including when the tag file
+    ///    doesn't exist
+    pub fn remove<P: AsRef<Path>>(&self, name: P) -> Result<(), IoTagError> {
+        let name = name.as_ref();
+        let path = self.resolve_checked(name)?;
+        self.remove_files(&path)?;
+        event!(
+            debug,
+            "Removed tag {} at {}",
+            name.display(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Writes the whole of a tag as stored to `path` along with its checksum,
+    /// dropping the operation log it supersedes.
+    ///
+    /// The file is written to a temporary sibling first and then renamed over
+    /// `path`, so it is either left untouched or completely replaced.
+    fn write_snapshot(&self, path: &Path, stored: &RawTag) -> Result<(), IoTagError> {
+        let mut bytes = match TagFormat::of(path) {
+            TagFormat::Json => stored.to_vec_with(&self.save_options)?,
+            TagFormat::Toml => toml::to_string(&serde_json::to_value(stored)?)
+                .ok_or_else(|| IoTagError::Toml {
+                    path: path.into(),
+                    reason: "null values cannot be written".to_owned(),
+                })?
+                .into_bytes(),
+        };
+        if is_compressed(path) {
+            bytes = compress(path, &bytes)?;
+        }
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(path.file_name().unwrap_or(path.as_os_str()));
+        temp_name.push(".tmp");
+        let temp = path.with_file_name(temp_name);
+        std::fs::write(io_path(&temp), &bytes)
+            .and_then(|()| std::fs::rename(io_path(&temp), io_path(path)))
+            .map_err(|cause| {
+                let _ = std::fs::remove_file(io_path(&temp));
+                IoTagError::io(IoOperation::Write, path)(cause)
+            })?;
+        let checksum = checksum_path(path);
+        if self.checksum {
+            std::fs::write(io_path(&checksum), sha256::hex_digest(&bytes))
+                .map_err(IoTagError::io(IoOperation::Write, &checksum))?;
+        } else {
+            remove_if_exists(&checksum).map_err(IoTagError::io(IoOperation::Remove, &checksum))?;
+        }
+        let log = log_path(path);
+        remove_if_exists(&log).map_err(IoTagError::io(IoOperation::Remove, &log))
+    }
+
+    /// Removes the tag file at `path` along with its checksum and operation
+    /// log.
+    fn remove_files(&self, path: &Path) -> Result<(), IoTagError> {
+        std::fs::remove_file(io_path(path)).map_err(IoTagError::io(IoOperation::Remove, path))?;
+        for path in [checksum_path(path), log_path(path)] {
+            remove_if_exists(&path).map_err(IoTagError::io(IoOperation::Remove, &path))?;
+        }
+        Ok(())
+    }
+
+    /// Locks this whole store by creating its lock file, waiting for it to be
+    /// released if `wait` and it is already held.
+    ///
+    /// The lock is advisory: it only excludes others taking it. It is released
+    /// when the returned guard is dropped; a lock file left behind by a
+    /// process that was killed must be removed manually.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the lock is held and `wait` is unset, or when the lock file
+    /// cannot be created.
+    pub fn lock(&self, wait: bool) -> Result<StoreLock, IoTagError> {
+        std::fs::create_dir_all(io_path(&self.root))
+            .map_err(IoTagError::io(IoOperation::CreateDir, &self.root))?;
+        let path = self.root.join(Self::LOCK_FILE_NAME);
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(io_path(&path))
+            {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    // the holder is only informative, so failing to write it is fine
+                    let _ = write!(file, "{}", std::process::id());
+                    return Ok(StoreLock { path });
+                }
+                Err(cause) if matches!(cause.kind(), io::ErrorKind::AlreadyExists) => {
+                    if !wait {
+                        return Err(IoTagError::Locked(path));
                     }
-                };
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+                Err(cause) => return Err(IoTagError::io(IoOperation::Write, &path)(cause)),
+            }
+        }
+    }
 
-                let key = path.pop_back();
-                if let Some(tag) = tag {
-                    path = helper(path, tags, &tag)?;
-                    // SAFETY: assert insert was called once before this
-                    let key = unsafe { key.unwrap_unchecked() };
-                    tags.insert(key, tag);
+    /// Applies the [`NameTransform`] of this store to `name`.
+    #[inline]
+    #[must_use]
+    pub fn transform_name(&self, name: &str) -> String {
+        self.name_transform.apply(name)
+    }
+
+    /// Lists the names of the tags in this store, including nested ones and
+    /// those only modified in memory so far, sorted.
+    ///
+    /// # Errors
+    ///
+    /// Fails when a directory of this store cannot be read.
+    pub fn names(&self) -> io::Result<Vec<String>> {
+        fn helper(root: &Path, dir: &Path, names: &mut Vec<String>) -> io::Result<()> {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    helper(root, &path, names)?;
+                } else if path == root.join(Config::FILE_NAME) {
+                    continue;
+                } else if let Some(name) = tag_name(root, &path) {
+                    names.push(name);
                 }
             }
-            Ok(path)
+            Ok(())
         }
 
-        let path = LinkedHashSet::new();
-        let mut tags = HashMap::new();
-        helper(path, &mut tags, &raw)?;
-        Ok(Self { raw, tags })
+        let mut names = self.pending.keys().cloned().collect_vec();
+        if self.root.is_dir() {
+            helper(&self.root, &self.root, &mut names)?;
+        }
+        // a tag stored in several formats is listed once
+        names.sort_unstable();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Removes a path from every tag of this store and clears its metadata.
+    ///
+    /// Returns the names of the tags the path was removed from.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * I/O error when listing, reading, or writing tags
+    ///  * parsing or serialization error
+    ///  * I/O error when removing the metadata
+    pub fn remove_path_everywhere<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Vec<String>, IoTagError> {
+        let path = path.as_ref();
+        let mut removed = Vec::new();
+        for name in self
+            .names()
+            .map_err(IoTagError::io(IoOperation::Read, &self.root))?
+        {
+            let mut tag = self.load(&name)?;
+            if tag.paths.remove(path) {
+                self.save(&name, &tag)?;
+                removed.push(name);
+            }
+        }
+        match PathMetadata::default().save(path) {
+            Err(cause) if !matches!(cause.kind(), io::ErrorKind::NotFound) => {
+                Err(IoTagError::io(
+                    IoOperation::Remove,
+                    &PathMetadata::resolve(path),
+                )(cause))
+            }
+            _ => Ok(removed),
+        }
+    }
+
+    /// Finds the tags of this store including or inheriting `name`, sorted.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * I/O error when listing or reading tags
+    ///  * parsing error
+    pub fn referencing(&self, name: &str) -> Result<Vec<String>, IoTagError> {
+        let mut names = Vec::new();
+        for other in self
+            .names()
+            .map_err(IoTagError::io(IoOperation::Read, &self.root))?
+        {
+            let tag = self.load(&other)?;
+            if tag.include_tags.contains(name) || tag.inherited_tags.contains(name) {
+                names.push(other);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Renames a tag, updating the tags referencing it and the metadata of its
+    /// paths.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`IoTagError::Exists`] if `to` already exists, or like
+    /// [`Self::merge_tags`].
+    pub fn rename_tag(&self, from: &str, to: &str) -> Result<(), IoTagError> {
+        if self.resolve_checked(to)?.exists() {
+            return Err(IoTagError::Exists(to.into()));
+        }
+        self.merge_tags(from, to)
+    }
+
+    /// Folds a tag into another, updating the tags referencing it and the
+    /// metadata of its paths, then removes it.
+    ///
+    /// The paths, includes, and inherits of `from` are added to `into`, which
+    /// is created if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * `from` doesn't exist
+    ///  * I/O error when listing, reading, writing, or removing tags
+    ///  * parsing or serialization error
+    ///  * I/O error when reading or writing metadata
+    pub fn merge_tags(&self, from: &str, into: &str) -> Result<(), IoTagError> {
+        if from == into {
+            return Ok(());
+        }
+
+        let source = self.load(from)?;
+        let mut target = match self.load(into) {
+            Ok(tag) => tag,
+            Err(IoTagError::Io { source, .. })
+                if matches!(source.kind(), io::ErrorKind::NotFound) =>
+            {
+                RawTag::default()
+            }
+            Err(cause) => return Err(cause),
+        };
+        target.merge_in_place(&source);
+        for set in [&mut target.include_tags, &mut target.inherited_tags] {
+            set.remove(from);
+            set.remove(into);
+        }
+
+        for name in self
+            .names()
+            .map_err(IoTagError::io(IoOperation::Read, &self.root))?
+        {
+            if name == from || name == into {
+                continue;
+            }
+            let mut tag = self.load(&name)?;
+            if tag.rename_included(from, into) {
+                self.save(&name, &tag)?;
+            }
+        }
+
+        self.save(into, &target)?;
+        let path = self.resolve_checked(from)?;
+        self.remove_files(&path)?;
+
+        for path in &source.paths {
+            let mut meta = match PathMetadata::load(path) {
+                Ok(meta) => meta,
+                Err(cause) if matches!(cause.kind(), io::ErrorKind::NotFound) => {
+                    PathMetadata::default()
+                }
+                Err(cause) => {
+                    return Err(IoTagError::io(
+                        IoOperation::Read,
+                        &PathMetadata::resolve(path),
+                    )(cause))
+                }
+            };
+            if retarget(&mut meta.tags, from, into) || meta.tags.insert(into.to_owned()) {
+                meta.save(path).map_err(IoTagError::io(
+                    IoOperation::Write,
+                    &PathMetadata::resolve(path),
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the paths of a tag onto another, updating the metadata of the
+    /// paths, and returns how many paths were moved.
+    ///
+    /// Unlike [`Self::merge_tags`], the includes and inherits of both tags stay
+    /// as they are. `to` is created if it doesn't exist, and `from` is removed
+    /// like any other tag left empty.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * `from` doesn't exist
+    ///  * I/O error when reading, writing, or removing tags
+    ///  * parsing or serialization error
+    ///  * I/O error when reading or writing metadata
+    pub fn retag(&self, from: &str, to: &str) -> Result<usize, IoTagError> {
+        if from == to {
+            return Ok(0);
+        }
+
+        let mut source = self.load(from)?;
+        let mut target = match self.load(to) {
+            Ok(tag) => tag,
+            Err(IoTagError::Io { source, .. })
+                if matches!(source.kind(), io::ErrorKind::NotFound) =>
+            {
+                RawTag::default()
+            }
+            Err(cause) => return Err(cause),
+        };
+        let paths = std::mem::take(&mut source.paths);
+        target.paths.extend(paths.iter().cloned());
+        self.save(to, &target)?;
+        self.save(from, &source)?;
+
+        for path in &paths {
+            let mut meta = match PathMetadata::load(path) {
+                Ok(meta) => meta,
+                Err(cause) if matches!(cause.kind(), io::ErrorKind::NotFound) => {
+                    PathMetadata::default()
+                }
+                Err(cause) => {
+                    return Err(IoTagError::io(
+                        IoOperation::Read,
+                        &PathMetadata::resolve(path),
+                    )(cause))
+                }
+            };
+            let removed = meta.tags.remove(from);
+            if meta.tags.insert(to.to_owned()) || removed {
+                meta.save(path).map_err(IoTagError::io(
+                    IoOperation::Write,
+                    &PathMetadata::resolve(path),
+                ))?;
+            }
+        }
+        Ok(paths.len())
+    }
+
+    /// Loads a tag for modification in memory; defaults when it doesn't exist.
+    ///
+    /// Modifications are written to disk on [`Self::flush`].
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Self::load`] for errors other than the tag not existing.
+    pub fn tag_mut(&mut self, name: &str) -> Result<&mut RawTag, IoTagError> {
+        let loaded = if self.pending.contains_key(name) {
+            None
+        } else {
+            match self.load(name) {
+                Ok(tag) => Some(tag),
+                Err(IoTagError::Io { source, .. })
+                    if matches!(source.kind(), io::ErrorKind::NotFound) =>
+                {
+                    None
+                }
+                Err(cause) => return Err(cause),
+            }
+        };
+        Ok(self
+            .pending
+            .entry(name.to_owned())
+            .or_insert_with(|| loaded.unwrap_or_default()))
+    }
+
+    /// Writes every tag modified in memory to disk.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Self::save`]; tags not yet written remain pending.
+    pub fn flush(&mut self) -> Result<(), IoTagError> {
+        let names = self.pending.keys().cloned().collect_vec();
+        for name in names {
+            if let Some(tag) = self.pending.get(&name) {
+                self.save(&name, tag)?;
+            }
+            self.pending.remove(&name);
+        }
+        Ok(())
+    }
+
+    /// Captures the tags modified in memory.
+    #[inline]
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pending: self.pending.clone(),
+        }
+    }
+
+    /// Rolls the tags modified in memory back to a snapshot.
+    ///
+    /// Tags already flushed are not rolled back.
+    #[inline]
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.pending = snapshot.pending;
+    }
+}
+
+impl Config {
+    pub const FILE_NAME: &'static str = "path-tagging.toml";
+
+    /// Loads the TOML configuration file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Following are possible causes for errors:
+    ///  * I/O error when reading the file
+    ///  * the file isn't valid TOML
+    ///  * an unknown key or a value of the wrong type
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IoTagError> {
+        let path = path.as_ref();
+        let source =
+            std::fs::read_to_string(path).map_err(IoTagError::io(IoOperation::Read, path))?;
+        let value = toml::parse(&source).map_err(|cause| IoTagError::Toml {
+            path: path.into(),
+            reason: cause.to_string(),
+        })?;
+        let mut config: Self = serde_json::from_value(value)?;
+        if let Some(dir) = path.parent() {
+            config.store = config.store.map(|store| dir.join(store));
+            config.base = config.base.map(|base| dir.join(base));
+        }
+        Ok(config)
+    }
+
+    /// Finds and loads the configuration file of the current directory, or
+    /// else of the root of `store`; `None` if neither exists.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Self::load`] for errors other than the file not existing.
+    pub fn find(store: &Store) -> Result<Option<Self>, IoTagError> {
+        let candidates = [
+            std::env::current_dir()
+                .map_err(IoTagError::io(IoOperation::Read, Path::new(".")))?
+                .join(Self::FILE_NAME),
+            store.root.join(Self::FILE_NAME),
+        ];
+        for path in candidates {
+            match Self::load(&path) {
+                Ok(config) => return Ok(Some(config)),
+                Err(IoTagError::Io { source, .. })
+                    if matches!(source.kind(), io::ErrorKind::NotFound) => {}
+                Err(cause) => return Err(cause),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Applies the configured defaults to `store`.
+    pub fn apply(&self, store: &mut Store) {
+        if let Some(root) = &self.store {
+            store.root.clone_from(root);
+        }
+        if let Some(indent) = self.indent {
+            store.save_options.indent = indent;
+        }
+        if let Some(trailing_newline) = self.trailing_newline {
+            store.save_options.trailing_newline = trailing_newline;
+        }
+        if let Some(base) = &self.base {
+            store.base = Some(base.clone());
+        }
+        if let Some(expand_env) = self.expand_env {
+            store.expand_env = expand_env;
+        }
+        if let Some(checksum) = self.checksum {
+            store.checksum = checksum;
+        }
+        if let Some(default_tags) = &self.default_tags {
+            store.default_tags.clone_from(default_tags);
+        }
+        if let Some(keep_empty) = self.keep_empty {
+            store.keep_empty = keep_empty;
+        }
+        if let Some(name_transform) = self.name_transform {
+            store.name_transform = name_transform;
+        }
+        if let Some(sanitize) = self.sanitize {
+            store.sanitize = sanitize;
+        }
+        if let Some(append_only) = self.append_only {
+            store.append_only = append_only;
+        }
+        if let Some(format) = self.store_format {
+            store.format = format;
+        }
+        if let Some(compress) = self.compress {
+            store.compress = compress;
+        }
+    }
+}
+
+impl TagFormat {
+    /// The extension of the files in this format.
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+        }
+    }
+
+    /// The format of the tag file at `path`, by its extension once any `.zst`
+    /// is removed; JSON unless it is `.toml`.
+    #[must_use]
+    pub fn of(path: &Path) -> Self {
+        let path = if is_compressed(path) {
+            Cow::Owned(path.with_extension(""))
+        } else {
+            Cow::Borrowed(path)
+        };
+        match path.extension() {
+            Some(extension) if extension == "toml" => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+}
+
+impl std::str::FromStr for TagFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            _ => Err(format!(
+                "unknown tag format {name:?}, expected json or toml"
+            )),
+        }
+    }
+}
+
+impl NameTransform {
+    /// Normalizes `name`; `/` separating nested tags is kept as is.
+    #[must_use]
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            Self::None => name.to_owned(),
+            Self::Lowercase => name.to_lowercase(),
+            Self::Kebab => name
+                .to_lowercase()
+                .split('/')
+                .map(|part| {
+                    part.split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+                        .filter(|word| !word.is_empty())
+                        .join("-")
+                })
+                .join("/"),
+        }
+    }
+}
+
+impl TagOp {
+    /// Lists the operations changing `old` into `new`.
+    fn diff(old: &RawTag, new: &RawTag) -> Vec<Self> {
+        fn changes<T: Clone + Eq + std::hash::Hash + Ord>(
+            old: &HashSet<T>,
+            new: &HashSet<T>,
+            add: fn(T) -> TagOp,
+            remove: fn(T) -> TagOp,
+        ) -> impl Iterator<Item = TagOp> {
+            let removed = old.difference(new).sorted().cloned().map(remove);
+            let added = new.difference(old).sorted().cloned().map(add);
+            removed.chain(added).collect_vec().into_iter()
+        }
+
+        let mut operations = changes(
+            &old.include_tags,
+            &new.include_tags,
+            Self::AddInclude,
+            Self::RemoveInclude,
+        )
+        .chain(changes(
+            &old.inherited_tags,
+            &new.inherited_tags,
+            Self::AddInherit,
+            Self::RemoveInherit,
+        ))
+        .chain(changes(
+            &old.paths,
+            &new.paths,
+            Self::AddPath,
+            Self::RemovePath,
+        ))
+        .chain(changes(
+            &old.path_globs,
+            &new.path_globs,
+            Self::AddGlob,
+            Self::RemoveGlob,
+        ))
+        .collect_vec();
+        if old.persistent != new.persistent {
+            operations.push(Self::SetPersistent(new.persistent));
+        }
+        operations
+    }
+
+    fn apply(self, tag: &mut RawTag) {
+        match self {
+            Self::AddInclude(key) => {
+                tag.include_tags.insert(key);
+            }
+            Self::RemoveInclude(key) => {
+                tag.include_tags.remove(&key);
+            }
+            Self::AddInherit(key) => {
+                tag.inherited_tags.insert(key);
+            }
+            Self::RemoveInherit(key) => {
+                tag.inherited_tags.remove(&key);
+            }
+            Self::AddPath(path) => {
+                tag.paths.insert(path);
+            }
+            Self::RemovePath(path) => {
+                tag.paths.remove(&path);
+            }
+            Self::AddGlob(pattern) => {
+                tag.path_globs.insert(pattern);
+            }
+            Self::RemoveGlob(pattern) => {
+                tag.path_globs.remove(&pattern);
+            }
+            Self::SetPersistent(persistent) => tag.persistent = persistent,
+        }
+    }
+}
+
+impl Drop for StoreLock {
+    #[inline]
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(io_path(&self.path));
+    }
+}
+
+impl MergeReport {
+    /// Checks whether the merge added nothing.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for SaveOptions {
+    #[inline]
+    fn default() -> Self {
+        Self::new(2, false)
+    }
+}
+
+impl ResolvePath {
+    #[inline]
+    fn new(path: LinkedHashSet<String>, cause: String) -> Self {
+        // FIXME: is there a better way to get an at-least debuggable iterator?
+        let mut inner = path.into_iter().collect_vec();
+        inner.push(cause);
+        inner.into_iter().collect()
+    }
+}
+
+impl ResolvedTags {
+    /// Resolves a raw tag like [`TryFrom`], checking `cancel` before loading
+    /// each tag.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TryFrom`] or with [`ResolveError::Cancelled`] once `cancel`
+    /// is set.
+    #[inline]
+    pub fn resolve_cancellable(raw: RawTag, cancel: &AtomicBool) -> Result<Self, ResolveError> {
+        match Store::beside_exe() {
+            Ok(store) => Self::resolve_in_cancellable(raw, &store, cancel),
+            Err(_) => Ok(Self {
+                raw,
+                tags: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Resolves a raw tag by loading its includes and inherits from `store`.
+    ///
+    /// The path globs of every tag are expanded once here, into the glob
+    /// matches of the tag rather than its paths, so saving a resolved tag
+    /// doesn't turn them into declared paths.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TryFrom`].
+    #[inline]
+    pub fn resolve_in(raw: RawTag, store: &Store) -> Result<Self, ResolveError> {
+        Self::resolve_in_cancellable(raw, store, &AtomicBool::new(false))
+    }
+
+    /// Resolves a query including every tag in `names` like [`TryFrom`].
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`TryFrom`].
+    #[inline]
+    pub fn from_names<I: IntoIterator<Item = String>>(names: I) -> Result<Self, ResolveError> {
+        Self::try_from(RawTag::query(names.into_iter().collect()))
+    }
+
+    /// Resolves a query including every tag in `names` from `store`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Self::resolve_in`].
+    #[inline]
+    pub fn from_names_in<I: IntoIterator<Item = String>>(
+        names: I,
+        store: &Store,
+    ) -> Result<Self, ResolveError> {
+        Self::resolve_in(RawTag::query(names.into_iter().collect()), store)
+    }
+
+    /// Resolves a raw tag by loading its includes and inherits from the store
+    /// at `root`, with default settings.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Self::resolve_in`].
+    #[inline]
+    pub fn resolve_from_store<P: AsRef<Path>>(raw: RawTag, root: P) -> Result<Self, ResolveError> {
+        Self::resolve_in(raw, &Store::new(root.as_ref().to_path_buf()))
+    }
+
+    /// Resolves a raw tag like [`Self::resolve_in`], checking `cancel` before
+    /// loading each tag.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Self::resolve_in`] or with [`ResolveError::Cancelled`] once
+    /// `cancel` is set.
+    pub fn resolve_in_cancellable(
+        mut raw: RawTag,
+        store: &Store,
+        cancel: &AtomicBool,
+    ) -> Result<Self, ResolveError> {
+        let _timer = profile::Timer::resolve();
+
+        fn helper(
+            mut path: LinkedHashSet<String>,
+            tags: &mut HashMap<String, RawTag>,
+            raw: &RawTag,
+            store: &Store,
+            cancel: &AtomicBool,
+        ) -> Result<LinkedHashSet<String>, ResolveError> {
+            let keys = raw.include_tags.union(raw.inherited_tags());
+            for key in keys {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(ResolveError::Cancelled);
+                }
+
+                if path.contains(key) {
+                    return Err(ResolveError::new_cyclic(ResolvePath::new(
+                        path,
+                        key.clone(),
+                    )));
+                }
+
+                path.insert(key.clone());
+
+                let tag = match store.load(key).and_then(|mut tag| {
+                    store.expand_env_in(&mut tag)?;
+                    Ok(tag)
+                }) {
+                    Ok(mut tag) => {
+                        tag.expand_globs();
+                        Some(tag)
+                    }
+                    Err(cause @ (IoTagError::Resolve(_) | IoTagError::InvalidName(_))) => {
+                        event!(
+                            debug,
+                            "Skipping tag {key:?} which can't be resolved: {cause}"
+                        );
+                        None
+                    }
+                    Err(IoTagError::Io { source, .. })
+                        if matches!(source.kind(), io::ErrorKind::NotFound) =>
+                    {
+                        event!(debug, "Skipping missing tag {key:?}");
+                        None
+                    }
+                    Err(cause) => {
+                        event!(debug, "Unable to load tag {key:?} while resolving: {cause}");
+                        return Err(ResolveError::new_load(path.into_iter().collect(), cause));
+                    }
+                };
+
+                if let Some(tag) = tag {
+                    path = helper(path, tags, &tag, store, cancel)?;
+                    tags.insert(key.clone(), tag);
+                }
+                path.pop_back();
+            }
+            Ok(path)
+        }
+
+        raw.expand_globs();
+        let path = LinkedHashSet::new();
+        let mut tags = HashMap::new();
+        helper(path, &mut tags, &raw, store, cancel)?;
+        Ok(Self { raw, tags })
+    }
+
+    /// Splits into the resolved raw tag and the tags loaded to resolve it.
+    #[inline]
+    #[must_use]
+    pub fn into_parts(self) -> (RawTag, HashMap<String, RawTag>) {
+        (self.raw, self.tags)
+    }
+
+    #[must_use]
+    pub fn contains(&self, path: &PathBuf) -> bool {
+        let declared = |tag: &RawTag| tag.paths.contains(path) || tag.glob_matches.contains(path);
+        declared(&self.raw)
+            || self
+                .raw
+                .include_tags
+                .iter()
+                .filter_map(|key| self.tags.get(key))
+                .any(declared)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn union(&self) -> HashSet<PathBuf> {
+        Self::union_at(&self.tags, &self.raw)
+    }
+
+    /// Computes the paths in the union of `tag` that are in none of the unions
+    /// of the other queried tags; none if `tag` isn't resolved.
+    #[must_use]
+    pub fn paths_only_in(&self, tag: &str) -> HashSet<PathBuf> {
+        let Some(raw) = self.tags.get(tag) else {
+            return HashSet::new();
+        };
+        let mut paths = Self::union_at(&self.tags, raw);
+        for other in (self.raw.include_tags.iter())
+            .filter(|key| *key != tag)
+            .filter_map(|key| self.tags.get(key))
+        {
+            let other = Self::union_at(&self.tags, other);
+            paths.retain(|path| !other.contains(path));
+        }
+        paths
+    }
+
+    /// Computes the union like [`Self::union`], checking `cancel` before
+    /// visiting each tag.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ResolveError::Cancelled`] once `cancel` is set.
+    #[inline]
+    pub fn union_cancellable(&self, cancel: &AtomicBool) -> Result<HashSet<PathBuf>, ResolveError> {
+        let _timer = profile::Timer::set();
+        Self::union_at_cancellable(&self.tags, &self.raw, cancel)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn union_at(tags: &HashMap<String, RawTag>, tag: &RawTag) -> HashSet<PathBuf> {
+        Self::union_at_cancellable(tags, tag, &AtomicBool::new(false))
+            .expect("union without cancellation cannot be cancelled")
+    }
+
+    fn union_at_cancellable(
+        tags: &HashMap<String, RawTag>,
+        tag: &RawTag,
+        cancel: &AtomicBool,
+    ) -> Result<HashSet<PathBuf>, ResolveError> {
+        let mut set = HashSet::new();
+        Self::union_helper(tags, tag, &mut set, cancel)?;
+        Ok(set)
+    }
+
+    fn union_helper(
+        tags: &HashMap<String, RawTag>,
+        raw: &RawTag,
+        set: &mut HashSet<PathBuf>,
+        cancel: &AtomicBool,
+    ) -> Result<(), ResolveError> {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(ResolveError::Cancelled);
+        }
+        for tag in raw.include_tags.iter().filter_map(|key| tags.get(key)) {
+            Self::union_helper(tags, tag, set, cancel)?;
+        }
+        set.extend(raw.iter_resolved_paths().cloned());
+        Ok(())
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn intersection(&self) -> HashSet<PathBuf> {
+        self.intersection_cancellable(&AtomicBool::new(false))
+            .expect("intersection without cancellation cannot be cancelled")
+    }
+
+    /// Computes the intersection like [`Self::intersection`], checking
+    /// `cancel` before visiting each tag.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ResolveError::Cancelled`] once `cancel` is set.
+    pub fn intersection_cancellable(
+        &self,
+        cancel: &AtomicBool,
+    ) -> Result<HashSet<PathBuf>, ResolveError> {
+        let _timer = profile::Timer::set();
+        let mut cancelled = false;
+        let mut set = self
+            .raw
+            .include_tags
+            .iter()
+            .map(|key| {
+                let union = Self::union_at_cancellable(&self.tags, self.tags.get(key)?, cancel);
+                cancelled |= union.is_err();
+                union.ok()
+            })
+            .tree_reduce(fallible_intersection)
+            .flatten()
+            .unwrap_or_default();
+        if cancelled {
+            return Err(ResolveError::Cancelled);
+        }
+
+        set.extend(self.raw.iter_resolved_paths().cloned());
+        Ok(set)
+    }
+
+    /// Computes the paths in every union of `tags`, regardless of the tags
+    /// originally queried; none if any of them isn't resolved.
+    ///
+    /// Unlike [`Self::intersection`], the paths of the query itself aren't
+    /// added.
+    #[must_use]
+    pub fn intersection_of(&self, tags: &HashSet<String>) -> HashSet<PathBuf> {
+        let _timer = profile::Timer::set();
+        tags.iter()
+            .map(|key| Some(Self::union_at(&self.tags, self.tags.get(key)?)))
+            .tree_reduce(fallible_intersection)
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    /// Computes the intersection like [`Self::intersection`], splitting the
+    /// unions of the queried tags across up to `threads` threads.
+    #[must_use]
+    pub fn intersection_parallel(&self, threads: NonZeroUsize) -> HashSet<PathBuf> {
+        let _timer = profile::Timer::set();
+        let keys = self.raw.include_tags.iter().collect_vec();
+        let chunk_size = keys.len().div_ceil(threads.get()).max(1);
+        let mut set = std::thread::scope(|scope| {
+            let handles = keys
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|key| Some(Self::union_at(&self.tags, self.tags.get(*key)?)))
+                            .tree_reduce(fallible_intersection)
+                            .flatten()
+                    })
+                })
+                .collect_vec();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|cause| std::panic::resume_unwind(cause))
+                })
+                .tree_reduce(fallible_intersection)
+                .flatten()
+                .unwrap_or_default()
+        });
+
+        set.extend(self.raw.iter_resolved_paths().cloned());
+        set
+    }
+
+    /// Iterates over the names of the tags loaded to resolve the raw tag, in
+    /// no particular order.
+    #[inline]
+    pub fn tag_names(&self) -> impl Iterator<Item = &String> {
+        self.tags.keys()
+    }
+
+    #[must_use]
+    pub fn all_tags(&self) -> HashSet<String> {
+        fn helper(tags: &mut HashSet<String>, raws: &HashMap<String, RawTag>, raw: &RawTag) {
+            for tag in raw.inherited_tags() {
+                if tags.insert(tag.clone()) {
+                    if let Some(raw) = raws.get(tag) {
+                        helper(tags, raws, raw);
+                    }
+                }
+            }
+        }
+
+        let mut set = HashSet::new();
+        for tag in self.raw.include_tags() {
+            if set.insert(tag.clone()) {
+                if let Some(raw) = self.tags.get(tag) {
+                    helper(&mut set, &self.tags, raw);
+                }
+            }
+        }
+        set
+    }
+
+    /// Collects the tags like [`Self::all_tags`], following at most `depth`
+    /// levels of inherits; `0` collects only the queried tags.
+    #[must_use]
+    pub fn all_tags_within(&self, depth: usize) -> HashSet<String> {
+        let mut set = self.raw.include_tags.clone();
+        let mut level = set.iter().cloned().collect_vec();
+        for _ in 0..depth {
+            level = level
+                .iter()
+                .filter_map(|key| self.tags.get(key))
+                .flat_map(|tag| tag.inherited_tags.iter())
+                .filter(|key| set.insert((*key).clone()))
+                .cloned()
+                .collect();
+            if level.is_empty() {
+                break;
+            }
+        }
+        set
+    }
+
+    /// Ranks the paths of the queried tags by how many of them contain each
+    /// path, most shared first and ties broken by path.
+    #[must_use]
+    pub fn most_common_paths(&self) -> Vec<(PathBuf, usize)> {
+        let mut counts = HashMap::<PathBuf, usize>::new();
+        for tag in self
+            .raw
+            .include_tags
+            .iter()
+            .filter_map(|key| self.tags.get(key))
+        {
+            for path in Self::union_at(&self.tags, tag) {
+                *counts.entry(path).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .sorted_by(|(lhs_path, lhs), (rhs_path, rhs)| {
+                rhs.cmp(lhs).then_with(|| lhs_path.cmp(rhs_path))
+            })
+            .collect()
+    }
+
+    /// Collects the names of every tag involved in resolving the query,
+    /// through both includes and inherits.
+    ///
+    /// These are the keys of [`Self::tags`] along with referenced tags that
+    /// don't exist.
+    #[must_use]
+    pub fn closure(&self) -> HashSet<String> {
+        fn helper(set: &mut HashSet<String>, tags: &HashMap<String, RawTag>, raw: &RawTag) {
+            for key in raw.include_tags.union(&raw.inherited_tags) {
+                if set.insert(key.clone()) {
+                    if let Some(tag) = tags.get(key) {
+                        helper(set, tags, tag);
+                    }
+                }
+            }
+        }
+
+        let mut set = HashSet::new();
+        helper(&mut set, &self.tags, &self.raw);
+        set
+    }
+
+    /// Extracts the resolution of a query for `roots` from the tags already
+    /// resolved, without loading anything.
+    ///
+    /// The result has the roots and every tag they reach through includes and
+    /// inherits; roots that weren't resolved are left missing.
+    #[must_use]
+    pub fn subgraph(&self, roots: &HashSet<String>) -> Self {
+        let mut tags = HashMap::new();
+        let mut pending = roots.iter().collect_vec();
+        while let Some(key) = pending.pop() {
+            if tags.contains_key(key) {
+                continue;
+            }
+            if let Some(tag) = self.tags.get(key) {
+                pending.extend(tag.include_tags.union(&tag.inherited_tags));
+                tags.insert(key.clone(), tag.clone());
+            }
+        }
+        Self {
+            raw: RawTag::query(roots.clone()),
+            tags,
+        }
+    }
+
+    /// Combines tags resolved separately, such as from different stores,
+    /// without loading anything.
+    ///
+    /// Tags resolved in both are merged by the union of their fields, and so
+    /// are the raw tags resolved.
+    #[must_use]
+    pub fn merge_resolved(mut self, other: Self) -> Self {
+        self.raw.merge_in_place(&other.raw);
+        for (key, tag) in other.tags {
+            match self.tags.entry(key) {
+                Entry::Occupied(mut entry) => {
+                    entry.get_mut().merge_in_place(&tag);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(tag);
+                }
+            }
+        }
+        self
+    }
+
+    /// Counts the paths in the recursive path union of every resolved tag.
+    ///
+    /// Each union is computed once and reused by the tags including it.
+    #[must_use]
+    pub fn path_count_by_tag(&self) -> HashMap<String, usize> {
+        fn helper<'a>(
+            memo: &mut HashMap<&'a str, Option<HashSet<&'a PathBuf>>>,
+            tags: &'a HashMap<String, RawTag>,
+            key: &'a str,
+        ) {
+            if memo.contains_key(key) {
+                return;
+            }
+            let Some(tag) = tags.get(key) else {
+                return;
+            };
+            // mark as visiting, a cycle then contributes no paths
+            memo.insert(key, None);
+            let mut set = HashSet::from_iter(tag.iter_resolved_paths());
+            for include in &tag.include_tags {
+                helper(memo, tags, include);
+                if let Some(Some(paths)) = memo.get(include.as_str()) {
+                    set.extend(paths);
+                }
+            }
+            memo.insert(key, Some(set));
+        }
+
+        let mut memo = HashMap::new();
+        for key in self.tags.keys() {
+            helper(&mut memo, &self.tags, key);
+        }
+        memo.into_iter()
+            .filter_map(|(key, set)| Some((key.to_owned(), set?.len())))
+            .collect()
+    }
+
+    /// Checks whether the recursive path unions of two resolved tags share no
+    /// paths; `None` if either tag wasn't resolved.
+    ///
+    /// Both unions are walked in turns without being collected first, stopping
+    /// at the first shared path.
+    #[must_use]
+    pub fn are_disjoint(&self, a: &str, b: &str) -> Option<bool> {
+        let mut a = Self::iter_union(&self.tags, self.tags.get(a)?);
+        let mut b = Self::iter_union(&self.tags, self.tags.get(b)?);
+        let (mut seen_a, mut seen_b) = (HashSet::new(), HashSet::new());
+        loop {
+            let (next_a, next_b) = (a.next(), b.next());
+            if next_a.is_none() && next_b.is_none() {
+                return Some(true);
+            }
+            if let Some(path) = next_a {
+                if seen_b.contains(path) {
+                    return Some(false);
+                }
+                seen_a.insert(path);
+            }
+            if let Some(path) = next_b {
+                if seen_a.contains(path) {
+                    return Some(false);
+                }
+                seen_b.insert(path);
+            }
+        }
+    }
+
+    /// Iterates over the recursive path union of `raw` as it is walked,
+    /// visiting each included tag once; paths may repeat.
+    fn iter_union<'a>(
+        tags: &'a HashMap<String, RawTag>,
+        raw: &'a RawTag,
+    ) -> impl Iterator<Item = &'a PathBuf> + 'a {
+        let mut pending = vec![raw];
+        let mut visited = HashSet::new();
+        std::iter::from_fn(move || {
+            let raw = pending.pop()?;
+            let included = (raw.include_tags.iter())
+                .filter(|key| visited.insert(key.as_str()))
+                .filter_map(|key| tags.get(key));
+            pending.extend(included);
+            Some(raw.iter_resolved_paths())
+        })
+        .flatten()
+    }
+
+    /// Collects the resolved tags whose recursive path union contains `path`.
+    ///
+    /// Unlike the path's metadata, this is derived from the tag files alone and
+    /// so includes tags that contain the path through their includes.
+    #[must_use]
+    pub fn tags_for_path(&self, path: &PathBuf) -> HashSet<String> {
+        self.tags
+            .iter()
+            .filter(|(_, tag)| Self::union_at(&self.tags, tag).contains(path))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Maps each path in the union of `tag` to the chain of includes it is
+    /// reached through, from `tag` to the tag declaring it.
+    ///
+    /// Chains are as short as possible, with ties broken by name; none if
+    /// `tag` isn't resolved.
+    #[must_use]
+    pub fn reachable_from(&self, tag: &str) -> HashMap<PathBuf, Vec<String>> {
+        let mut reachable = HashMap::new();
+        let Some(raw) = self.tags.get(tag) else {
+            return reachable;
+        };
+        let mut visited = HashSet::from([tag]);
+        let mut queue = std::collections::VecDeque::from([(raw, vec![tag.to_owned()])]);
+        while let Some((raw, chain)) = queue.pop_front() {
+            for path in raw.iter_resolved_paths() {
+                reachable
+                    .entry(path.clone())
+                    .or_insert_with(|| chain.clone());
+            }
+            for key in raw.include_tags.iter().sorted() {
+                if let Some(included) = self.tags.get(key) {
+                    if visited.insert(key) {
+                        let mut chain = chain.clone();
+                        chain.push(key.clone());
+                        queue.push_back((included, chain));
+                    }
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Computes figures about the loaded tags, including the issues
+    /// [`Self::validate`] finds.
+    #[must_use]
+    pub fn stats(&self) -> GraphStats {
+        fn depth<'a>(
+            memo: &mut HashMap<&'a str, usize>,
+            tags: &'a HashMap<String, RawTag>,
+            tag: &'a RawTag,
+        ) -> usize {
+            (tag.include_tags.iter())
+                .chain(&tag.inherited_tags)
+                .filter_map(|key| {
+                    if let Some(depth) = memo.get(key.as_str()) {
+                        return Some(*depth);
+                    }
+                    let child = tags.get(key)?;
+                    // a placeholder guards against cycles among merged tags
+                    memo.insert(key, 0);
+                    let depth = 1 + depth(memo, tags, child);
+                    memo.insert(key, depth);
+                    Some(depth)
+                })
+                .max()
+                .unwrap_or_default()
+        }
+
+        let tags = self.tags.values();
+        GraphStats {
+            tags: self.tags.len(),
+            include_edges: tags.clone().map(|tag| tag.include_tags.len()).sum(),
+            inherit_edges: tags.clone().map(|tag| tag.inherited_tags.len()).sum(),
+            max_depth: depth(&mut HashMap::new(), &self.tags, &self.raw),
+            paths: tags.clone().map(|tag| tag.paths.len()).sum(),
+            distinct_paths: tags.flat_map(|tag| &tag.paths).unique().count(),
+            broken_refs: self.validate(),
+        }
+    }
+
+    /// Reports every include and inherit, sorted, that refers to a tag that
+    /// wasn't loaded during resolution, such as a misspelled name.
+    #[must_use]
+    pub fn validate(&self) -> Vec<BrokenRef> {
+        let tags = std::iter::once((None, &self.raw))
+            .chain(self.tags.iter().map(|(key, tag)| (Some(key), tag)));
+        let mut broken = Vec::new();
+        for (from, tag) in tags {
+            let refs = (tag.include_tags.iter().map(|key| (key, RefKind::Include)))
+                .chain(tag.inherited_tags.iter().map(|key| (key, RefKind::Inherit)));
+            for (key, kind) in refs {
+                if !self.tags.contains_key(key) {
+                    broken.push(BrokenRef::new(from.cloned(), key.clone(), kind));
+                }
+            }
+        }
+        broken.sort();
+        broken
+    }
+}
+
+impl GraphStats {
+    /// The references to tags that weren't loaded, sorted.
+    #[inline]
+    #[must_use]
+    pub fn broken_refs(&self) -> &[BrokenRef] {
+        &self.broken_refs
+    }
+}
+
+impl std::fmt::Display for BrokenRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = match self.kind {
+            RefKind::Include => "includes",
+            RefKind::Inherit => "inherits",
+        };
+        match &self.from {
+            Some(from) => write!(f, "{from:?} {verb} missing tag {:?}", self.to),
+            None => write!(f, "query {verb} missing tag {:?}", self.to),
+        }
+    }
+}
+
+impl IoTagError {
+    /// Wraps an I/O error of `operation` on `path`.
+    fn io(operation: IoOperation, path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            operation,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl std::fmt::Display for IoOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Remove => "remove",
+            Self::CreateDir => "create directory",
+        })
+    }
+}
+
+impl From<ResolvedTags> for RawTag {
+    #[inline]
+    fn from(resolved: ResolvedTags) -> Self {
+        resolved.raw
+    }
+}
+
+impl FromIterator<String> for ResolvePath {
+    #[inline]
+    fn from_iter<T: IntoIterator<Item = String>>(iter: T) -> Self {
+        let inner = iter.into_iter().collect_vec().into_iter();
+        Self { inner }
+    }
+}
+
+impl Iterator for ResolvePath {
+    type Item = String;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl PathMetadata {
+    pub const EXTENSION_PREFIX: &'static str = ".tag.list";
+
+    /// Gets where the metadata of `path` is stored: beside it for a file and
+    /// inside it for a directory.
+    ///
+    /// A path that doesn't exist is taken as a directory only when it ends
+    /// with a separator, such as `new/`; see [`Self::resolve_as`] to tell
+    /// otherwise.
+    #[inline]
+    pub fn resolve<P: AsRef<Path>>(path: P) -> PathBuf {
+        let path = path.as_ref();
+        let kind = if io_path(path).is_dir() || has_trailing_separator(path) {
+            PathKind::Dir
+        } else {
+            PathKind::File
+        };
+        Self::resolve_as(path, kind)
+    }
+
+    /// Gets where the metadata of `path` is stored if it is of the given kind,
+    /// whether or not it exists.
+    #[must_use]
+    pub fn resolve_as<P: AsRef<Path>>(path: P, kind: PathKind) -> PathBuf {
+        let path = path.as_ref();
+        match kind {
+            PathKind::File => {
+                let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+                file_name.push(Self::EXTENSION_PREFIX);
+                path.with_file_name(file_name)
+            }
+            PathKind::Dir => path.join(Self::EXTENSION_PREFIX),
+        }
+    }
+
+    /// Gets the path whose metadata is stored at `sidecar`, the inverse of
+    /// [`Self::resolve`]; `None` if it isn't named like metadata.
+    #[must_use]
+    pub fn owner_of<P: AsRef<Path>>(sidecar: P) -> Option<PathBuf> {
+        let sidecar = sidecar.as_ref();
+        let file_name = sidecar.file_name()?.to_str()?;
+        if file_name == Self::EXTENSION_PREFIX {
+            sidecar.parent().map(Path::to_path_buf)
+        } else {
+            let file_name = file_name.strip_suffix(Self::EXTENSION_PREFIX)?;
+            Some(sidecar.with_file_name(file_name))
+        }
+    }
+
+    #[inline]
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::load_sidecar(Self::resolve(path.as_ref()))
+    }
+
+    /// Walks `dir` recursively, loading every metadata file found.
+    ///
+    /// Yields each path that has metadata along with it, including paths that
+    /// no longer exist. Failures are yielded along with the path being read
+    /// and the walk carries on past them.
+    #[inline]
+    #[must_use]
+    pub fn load_all_under<P: AsRef<Path>>(dir: P) -> LoadAllUnder {
+        LoadAllUnder {
+            root: Some(dir.as_ref().to_path_buf()),
+            stack: Vec::new(),
+            ignore: None,
+        }
+    }
+
+    fn load_sidecar(sidecar: PathBuf) -> io::Result<Self> {
+        let _timer = profile::Timer::load();
+        let meta = Self::load_from(std::fs::File::open(io_path(&sidecar))?)?;
+        event!(trace, "Loaded metadata from {}", sidecar.display());
+        Ok(meta)
+    }
+
+    /// Reads metadata from `reader`, one tag per line.
+    ///
+    /// Input starting with `[` or `{` is first tried as JSON, either an array
+    /// of tags or an object with a `tags` array, and read as lines when it
+    /// doesn't parse.
+    ///
+    /// # Errors
+    ///
+    /// Fails when reading fails or the input isn't UTF-8.
+    pub fn load_from<R: io::Read>(mut reader: R) -> io::Result<Self> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        if let Some(meta) = Self::from_json(&contents) {
+            return Ok(meta);
+        }
+        Ok(Self::new(contents.lines().map_into().collect()))
+    }
+
+    fn from_json(contents: &str) -> Option<Self> {
+        match contents.trim_start().chars().next()? {
+            '[' => serde_json::from_str(contents).ok().map(Self::new),
+            '{' => serde_json::from_str(contents).ok(),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = Self::resolve(path.as_ref());
+        if self.is_empty() {
+            std::fs::remove_file(io_path(&path))?;
+        } else {
+            self.save_to(std::fs::File::create(io_path(&path))?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes metadata to `writer`, one tag per line in sorted order.
+    ///
+    /// # Errors
+    ///
+    /// Fails when writing fails.
+    #[inline]
+    pub fn save_to<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.tags_sorted().into_iter().join("\n").as_bytes())
+    }
+
+    /// Loads the metadata of a path merged with the metadata of every directory
+    /// above it.
+    ///
+    /// A directory's metadata thus acts as default tags for everything under
+    /// it. Missing metadata, including the path's own, is skipped.
+    ///
+    /// # Errors
+    ///
+    /// Fails when any existing metadata cannot be read.
+    pub fn load_inherited<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut meta = Self::default();
+        for path in path.as_ref().ancestors() {
+            match Self::load(path) {
+                Ok(other) => meta.merge(&other),
+                Err(cause) if matches!(cause.kind(), io::ErrorKind::NotFound) => {}
+                Err(cause) => return Err(cause),
+            }
+        }
+        Ok(meta)
+    }
+
+    /// The tags of this metadata in sorted order.
+    #[inline]
+    #[must_use]
+    pub fn tags_sorted(&self) -> Vec<&String> {
+        self.tags.iter().sorted().collect()
+    }
+
+    /// Compares the tags of this metadata against `other`.
+    ///
+    /// Returns the tags only in this metadata and the tags only in `other`;
+    /// that is, what changing `other` into this metadata adds and removes.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> (HashSet<String>, HashSet<String>) {
+        let added = self.tags.difference(&other.tags).cloned().collect();
+        let removed = other.tags.difference(&self.tags).cloned().collect();
+        (added, removed)
+    }
+
+    /// Removes all tags from this metadata, returning them.
+    #[inline]
+    pub fn clear(&mut self) -> HashSet<String> {
+        std::mem::take(&mut self.tags)
+    }
+
+    /// Adds the tags of `other` into this metadata.
+    #[inline]
+    pub fn merge(&mut self, other: &Self) {
+        self.tags.extend(other.tags.iter().cloned());
+    }
+
+    /// Loads the metadata of `path` and merges it into this metadata.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the metadata of `path` cannot be loaded, leaving this
+    /// metadata unchanged.
+    #[inline]
+    pub fn merge_from_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let other = Self::load(path)?;
+        self.merge(&other);
+        Ok(())
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+}
+
+impl LoadAllUnder {
+    /// Skips the paths excluded by the `.tagignore` files under the walked
+    /// directory, without descending into excluded directories.
+    #[inline]
+    #[must_use]
+    pub fn respecting_ignore(mut self) -> Self {
+        self.ignore = (self.root.clone()).map(TagIgnore::new);
+        self
+    }
+
+    fn is_ignored(&mut self, path: &Path) -> bool {
+        (self.ignore.as_mut()).is_some_and(|ignore| ignore.is_ignored(path))
+    }
+}
+
+impl Iterator for LoadAllUnder {
+    type Item = Result<(PathBuf, PathMetadata), (PathBuf, io::Error)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.root.take() {
+            match std::fs::read_dir(&root) {
+                Ok(entries) => self.stack.push((root, entries)),
+                Err(cause) => return Some(Err((root, cause))),
+            }
+        }
+
+        loop {
+            let (dir, entries) = self.stack.last_mut()?;
+            let entry = match entries.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(cause)) => return Some(Err((dir.clone(), cause))),
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() && self.is_ignored(&path) => {}
+                Ok(file_type) if file_type.is_dir() => match std::fs::read_dir(&path) {
+                    Ok(entries) => self.stack.push((path, entries)),
+                    Err(cause) => return Some(Err((path, cause))),
+                },
+                Ok(file_type) if file_type.is_file() => {
+                    let Some(owner) = PathMetadata::owner_of(&path) else {
+                        continue;
+                    };
+                    if self.is_ignored(&owner) {
+                        continue;
+                    }
+                    return Some(match PathMetadata::load_sidecar(path.clone()) {
+                        Ok(meta) => Ok((owner, meta)),
+                        Err(cause) => Err((path, cause)),
+                    });
+                }
+                Ok(_) => {}
+                Err(cause) => return Some(Err((path, cause))),
+            }
+        }
+    }
+}
+
+impl TryFrom<RawTag> for ResolvedTags {
+    type Error = ResolveError;
+
+    #[inline]
+    fn try_from(raw: RawTag) -> Result<Self, Self::Error> {
+        Self::resolve_cancellable(raw, &AtomicBool::new(false))
+    }
+}
+
+fn fallible_intersection(
+    lhs: Option<HashSet<PathBuf>>,
+    rhs: Option<HashSet<PathBuf>>,
+) -> Option<HashSet<PathBuf>> {
+    let mut lhs = lhs?;
+    let mut rhs = rhs?;
+    if rhs.capacity() < lhs.capacity() {
+        std::mem::swap(&mut lhs, &mut rhs);
+    }
+    lhs.retain(|path| rhs.contains(path));
+    Some(lhs)
+}
+
+fn serialize_sorted<S, T>(set: &HashSet<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: Ord + serde::Serialize,
+{
+    serializer.collect_seq(set.iter().sorted())
+}
+
+/// Spells long absolute paths in the verbatim `\\?\` form on Windows, which
+/// isn't limited to `MAX_PATH`.
+#[cfg(windows)]
+fn io_path(path: &Path) -> Cow<'_, Path> {
+    use std::path::Prefix;
+
+    // MAX_PATH less the terminating NUL
+    if path.as_os_str().len() < 260 {
+        return Cow::Borrowed(path);
+    }
+    let mut components = path.components();
+    let mut verbatim = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(disk) => PathBuf::from(format!(r"\\?\{}:\", char::from(disk))),
+            Prefix::UNC(server, share) => {
+                let mut verbatim = std::ffi::OsString::from(r"\\?\UNC\");
+                verbatim.push(server);
+                verbatim.push(r"\");
+                verbatim.push(share);
+                verbatim.push(r"\");
+                PathBuf::from(verbatim)
+            }
+            _ => return Cow::Borrowed(path),
+        },
+        _ => return Cow::Borrowed(path),
+    };
+    // verbatim paths are used as is, so normalize like Windows would
+    for component in components {
+        match component {
+            Component::Normal(name) => verbatim.push(name),
+            Component::ParentDir => {
+                if verbatim.parent().is_some() {
+                    verbatim.pop();
+                }
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    Cow::Owned(verbatim)
+}
+
+#[cfg(not(windows))]
+#[inline]
+fn io_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Gets the operation log of the tag file at `path`, shared by its compressed
+/// and uncompressed files.
+fn log_path(path: &Path) -> PathBuf {
+    if is_compressed(path) {
+        path.with_extension("").with_extension("log")
+    } else {
+        path.with_extension("log")
+    }
+}
+
+/// The name of the tag whose file in the store at `root` is `path`; `None`
+/// if it isn't named like a tag file under `root`.
+fn tag_name(root: &Path, path: &Path) -> Option<String> {
+    let name = path.strip_prefix(root).ok()?.to_str()?;
+    let name = name.strip_suffix(".zst").unwrap_or(name);
+    let name = (name.strip_suffix(".json")).or_else(|| name.strip_suffix(".toml"))?;
+    Some(name.to_owned())
+}
+
+/// Whether the tag file at `path` is compressed with Zstandard.
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zst")
+}
+
+/// Compresses the contents of the tag file at `path`.
+#[cfg(feature = "zstd")]
+#[allow(clippy::unnecessary_wraps)]
+fn compress(_path: &Path, bytes: &[u8]) -> Result<Vec<u8>, IoTagError> {
+    Ok(zstd::compress(bytes))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(path: &Path, _bytes: &[u8]) -> Result<Vec<u8>, IoTagError> {
+    Err(IoTagError::ZstdUnsupported(path.into()))
+}
+
+/// Decompresses the contents of the tag file at `path`.
+#[cfg(feature = "zstd")]
+fn decompress(path: &Path, bytes: &[u8]) -> Result<Vec<u8>, IoTagError> {
+    zstd::decompress(bytes).map_err(|cause| IoTagError::Zstd {
+        path: path.into(),
+        reason: cause.to_string(),
+    })
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(path: &Path, _bytes: &[u8]) -> Result<Vec<u8>, IoTagError> {
+    Err(IoTagError::ZstdUnsupported(path.into()))
+}
+
+/// The checksum file of a tag file.
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".sha256");
+    path.with_file_name(file_name)
+}
+
+/// Makes `path` relative to `base` for storing, as `.` when it is `base`
+/// itself; paths outside `base` are kept as is.
+fn strip_base(base: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix(base) {
+        Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Joins a `path` stored by [`strip_base`] back onto `base`.
+fn join_base(base: &Path, path: &Path) -> PathBuf {
+    if path == Path::new(".") {
+        base.to_path_buf()
+    } else {
+        base.join(path)
+    }
+}
+
+/// Replaces `from` in `set` with `into`, returning whether it was there.
+fn retarget(set: &mut HashSet<String>, from: &str, into: &str) -> bool {
+    let changed = set.remove(from);
+    if changed {
+        set.insert(into.to_owned());
+    }
+    changed
+}
+
+fn has_trailing_separator(path: &Path) -> bool {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .last()
+        .is_some_and(|byte| std::path::is_separator(char::from(*byte)))
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(io_path(path)) {
+        Err(cause) if !matches!(cause.kind(), io::ErrorKind::NotFound) => Err(cause),
+        _ => Ok(()),
+    }
+}
+
+/// Expands `$VAR` and `${VAR}` in a path; non-unicode paths are kept as is.
+fn expand_env(path: &Path) -> Result<PathBuf, IoTagError> {
+    let Some(mut rest) = path.to_str() else {
+        return Ok(path.into());
+    };
+
+    let mut expanded = String::with_capacity(rest.len());
+    while let Some(index) = rest.find('$') {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| IoTagError::EnvSyntax(path.into()))?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+
+        let value = std::env::var(name).map_err(|_| IoTagError::Env(name.into()))?;
+        expanded.push_str(&value);
+        rest = after;
+    }
+    expanded.push_str(rest);
+    Ok(expanded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh store in a directory unique to the test.
+    fn scratch_store(name: &str) -> Store {
+        let root =
+            std::env::temp_dir().join(format!("path-tagging-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        Store::new(root)
+    }
+
+    fn includes<const N: usize>(tags: [&str; N]) -> RawTag {
+        RawTag::query(tags.into_iter().map(str::to_owned).collect())
+    }
+
+    /// A resolution of `tags` as if they were all loaded, querying all of
+    /// them.
+    fn graph<const N: usize>(tags: [(&str, RawTag); N]) -> ResolvedTags {
+        let tags = (tags.into_iter())
+            .map(|(key, tag)| (key.to_owned(), tag))
+            .collect::<HashMap<_, _>>();
+        ResolvedTags {
+            raw: RawTag::query(tags.keys().cloned().collect()),
+            tags,
+        }
+    }
+
+    #[test]
+    fn resolve_diamond_includes() {
+        let store = scratch_store("resolve-diamond");
+        store.save("top", &includes(["left", "right"])).unwrap();
+        store.save("left", &includes(["bottom"])).unwrap();
+        store.save("right", &includes(["bottom"])).unwrap();
+        let mut bottom = RawTag::default();
+        bottom.paths_mut().insert(PathBuf::from("/x"));
+        store.save("bottom", &bottom).unwrap();
+
+        let resolved = ResolvedTags::resolve_in(includes(["top"]), &store).unwrap();
+        let names = resolved.tags().keys().cloned().sorted().collect_vec();
+        assert_eq!(names, ["bottom", "left", "right", "top"]);
+        assert_eq!(resolved.union(), HashSet::from([PathBuf::from("/x")]));
+    }
+
+    /// Runs `f`, collecting the messages of the events it emits on this
+    /// thread.
+    #[cfg(feature = "logging")]
+    fn capture_events(f: impl FnOnce()) -> Vec<String> {
+        use std::cell::RefCell;
+
+        struct Capture;
+
+        thread_local! {
+            static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        }
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record<'_>) {
+                EVENTS.with_borrow_mut(|events| events.push(record.args().to_string()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        EVENTS.with_borrow_mut(Vec::clear);
+        f();
+        EVENTS.with_borrow_mut(std::mem::take)
+    }
+
+    #[test]
+    #[cfg(feature = "logging")]
+    fn resolve_emits_events() {
+        let store = scratch_store("resolve-events");
+        store.save("a", &includes(["missing"])).unwrap();
+
+        let events = capture_events(|| {
+            ResolvedTags::resolve_in(includes(["a"]), &store).unwrap();
+        });
+        assert!(events
+            .iter()
+            .any(|event| event.starts_with("Loaded tag a ")));
+        assert!(events.contains(&"Skipping missing tag \"missing\"".to_owned()));
+    }
+
+    #[test]
+    fn resolve_cycle() {
+        let store = scratch_store("resolve-cycle");
+        store.save("a", &includes(["b"])).unwrap();
+        store.save("b", &includes(["a"])).unwrap();
+
+        let result = ResolvedTags::resolve_in(includes(["a"]), &store);
+        assert!(matches!(result, Err(ResolveError::Cyclic { .. })));
+    }
+
+    #[test]
+    fn glob_matches_are_not_saved() {
+        let store = scratch_store("glob-matches");
+        let file = store.root().join("file.txt");
+        std::fs::write(&file, "").unwrap();
+        let mut tag = RawTag::default();
+        let pattern = store.root().join("*.txt");
+        tag.path_globs_mut().insert(pattern.display().to_string());
+        store.save("a", &tag).unwrap();
+
+        let resolved = ResolvedTags::resolve_in(includes(["a"]), &store).unwrap();
+        assert_eq!(resolved.union(), HashSet::from([file.clone()]));
+        let resolved_tag = &resolved.tags()["a"];
+        assert!(resolved_tag.glob_matches().contains(&file));
+        assert_eq!(resolved_tag.paths_len(), 0);
+
+        store.save("a", resolved_tag).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let reloaded = store.load("a").unwrap();
+        assert_eq!(reloaded.paths_len(), 0);
+        assert_eq!(reloaded.path_globs().len(), 1);
+        let resolved = ResolvedTags::resolve_in(includes(["a"]), &store).unwrap();
+        assert!(resolved.union().is_empty());
+    }
+
+    #[test]
+    fn env_expansion_keeps_stored_spelling() {
+        std::env::set_var("PATH_TAGGING_TEST_DIR", "/expanded");
+        let mut store = scratch_store("env-spelling");
+        store.set_expand_env(true);
+        let mut tag = RawTag::default();
+        tag.paths_mut()
+            .insert(PathBuf::from("${PATH_TAGGING_TEST_DIR}/x"));
+        store.save("a", &tag).unwrap();
+
+        assert_eq!(store.load("a").unwrap(), tag);
+        let resolved = ResolvedTags::resolve_in(includes(["a"]), &store).unwrap();
+        assert_eq!(
+            resolved.union(),
+            HashSet::from([PathBuf::from("/expanded/x")])
+        );
+
+        store.save("a", &resolved.tags()["a"]).unwrap();
+        assert_eq!(store.load("a").unwrap(), tag);
+    }
+
+    #[test]
+    fn env_expansion_rejects_unclosed_braces() {
+        let result = expand_env(Path::new("${HOME/x"));
+        assert!(matches!(result, Err(IoTagError::EnvSyntax(_))));
+        let result = expand_env(Path::new("$PATH_TAGGING_TEST_UNSET/x"));
+        assert!(matches!(result, Err(IoTagError::Env(name)) if name == "PATH_TAGGING_TEST_UNSET"));
+        assert_eq!(expand_env(Path::new("a$/b")).unwrap(), Path::new("a$/b"));
+    }
+
+    #[test]
+    fn base_itself_survives_sanitizing() {
+        let mut store = scratch_store("base-itself");
+        let base = store.root().join("project");
+        store.set_base(Some(base.clone()));
+        store.set_sanitize(true);
+        let mut tag = RawTag::default();
+        tag.paths_mut().extend([base.clone(), base.join("x")]);
+        store.save("a", &tag).unwrap();
+
+        let stored = std::fs::read_to_string(store.root().join("a.json")).unwrap();
+        let stored: RawTag = serde_json::from_str(&stored).unwrap();
+        assert_eq!(
+            stored.paths(),
+            &HashSet::from([PathBuf::from("."), PathBuf::from("x")])
+        );
+        assert_eq!(store.load("a").unwrap(), tag);
+    }
+
+    #[test]
+    fn restore_rolls_back_pending_tags() {
+        let mut store = scratch_store("snapshot");
+        let mut saved = RawTag::default();
+        saved.paths_mut().insert(PathBuf::from("/saved"));
+        store.save("a", &saved).unwrap();
+
+        store
+            .tag_mut("a")
+            .unwrap()
+            .paths_mut()
+            .insert(PathBuf::from("/kept"));
+        let snapshot = store.snapshot();
+        store.tag_mut("a").unwrap().paths_mut().clear();
+        store
+            .tag_mut("b")
+            .unwrap()
+            .paths_mut()
+            .insert(PathBuf::from("/dropped"));
+        store.restore(snapshot);
+        store.flush().unwrap();
+
+        saved.paths_mut().insert(PathBuf::from("/kept"));
+        assert_eq!(store.load("a").unwrap(), saved);
+        assert!(!store.resolve("b").exists());
+    }
+
+    #[test]
+    fn pending_tags_load_and_resolve_before_flushing() {
+        let mut store = scratch_store("pending");
+        store.save("a", &includes(["b"])).unwrap();
+        *store.tag_mut("b").unwrap() = RawTag::default().with_paths(["/x"]);
+
+        assert!(!store.resolve("b").exists());
+        assert_eq!(store.names().unwrap(), ["a", "b"]);
+        let resolved = ResolvedTags::from_names_in(["a".to_owned()], &store).unwrap();
+        assert_eq!(resolved.union(), HashSet::from([PathBuf::from("/x")]));
+    }
+
+    #[test]
+    fn append_only_saves_replay_and_compact() {
+        let mut store = scratch_store("append-only");
+        store.set_append_only(true);
+        let mut tag = includes(["b"]);
+        tag.paths_mut().insert(PathBuf::from("/a"));
+        store.save("a", &tag).unwrap();
+        let snapshot = std::fs::read(store.resolve("a")).unwrap();
+
+        tag.paths_mut().remove(Path::new("/a"));
+        tag.paths_mut().insert(PathBuf::from("/b"));
+        store.save("a", &tag).unwrap();
+        assert_eq!(std::fs::read(store.resolve("a")).unwrap(), snapshot);
+        assert!(log_path(&store.resolve("a")).is_file());
+        assert_eq!(store.load("a").unwrap(), tag);
+
+        assert!(store.compact("a").unwrap());
+        assert!(!log_path(&store.resolve("a")).exists());
+        assert_eq!(store.load("a").unwrap(), tag);
+        assert!(!store.compact("a").unwrap());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_tags_load_identically_and_are_smaller() {
+        let mut store = scratch_store("compressed");
+        let mut tag = RawTag::default();
+        tag.paths_mut().extend(
+            (0..10_000).map(|n| PathBuf::from(format!("/home/user/photos/{}/{n}.jpg", n % 97))),
+        );
+        store.save("big", &tag).unwrap();
+        let plain = store.root().join("big.json");
+        let plain_size = std::fs::metadata(&plain).unwrap().len();
+
+        store.set_compress(true);
+        assert_eq!(store.resolve("big"), plain);
+        assert_eq!(store.load("big").unwrap(), tag);
+        store.save("big", &tag).unwrap();
+        let compressed = store.root().join("big.json.zst");
+        assert!(!plain.exists());
+        assert!(std::fs::metadata(&compressed).unwrap().len() < plain_size / 4);
+        assert_eq!(store.load("big").unwrap(), tag);
+        assert_eq!(store.names().unwrap(), ["big"]);
+
+        store.set_compress(false);
+        assert_eq!(store.resolve("big"), compressed);
+        assert_eq!(store.load("big").unwrap(), tag);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn compressed_tags_need_the_zstd_feature() {
+        let mut store = scratch_store("uncompressable");
+        store.set_compress(true);
+        let mut tag = RawTag::default();
+        tag.paths_mut().insert("/a".into());
+        assert!(matches!(
+            store.save("a", &tag),
+            Err(IoTagError::ZstdUnsupported(path)) if path == store.root().join("a.json.zst")
+        ));
+    }
+
+    #[test]
+    fn config_loads_toml_relative_to_its_directory() {
+        let store = scratch_store("config");
+        let path = store.root().join(Config::FILE_NAME);
+        let source = "
+            # defaults for the store
+            store = \"tags\"
+            checksum = true
+            default_tags = [\"inbox\"]
+            name_transform = \"kebab\"
+            ignore_case = true
+            format = \"json\"
+        ";
+        std::fs::write(&path, source).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.store(), &Some(store.root().join("tags")));
+        assert_eq!(config.ignore_case(), &Some(true));
+        assert_eq!(config.format().as_deref(), Some("json"));
+        let mut configured = Store::new(PathBuf::new());
+        config.apply(&mut configured);
+        assert_eq!(configured.root(), &store.root().join("tags"));
+        assert!(configured.checksum());
+        assert_eq!(
+            configured.default_tags(),
+            &HashSet::from(["inbox".to_owned()])
+        );
+        assert_eq!(configured.transform_name("Rust Lang"), "rust-lang");
+
+        std::fs::write(&path, "checksum = \"yes\"").unwrap();
+        assert!(matches!(Config::load(&path), Err(IoTagError::Serde(_))));
+        std::fs::write(&path, "checksum = ").unwrap();
+        assert!(matches!(Config::load(&path), Err(IoTagError::Toml { .. })));
+    }
+
+    #[test]
+    fn are_disjoint_compares_recursive_unions() {
+        let tagged = |paths: &[&str], include: &[&str]| {
+            let mut tag = includes([]);
+            tag.include_tags_mut()
+                .extend(include.iter().map(|key| (*key).to_owned()));
+            tag.paths_mut().extend(paths.iter().map(PathBuf::from));
+            tag
+        };
+        let tags = HashMap::from([
+            ("a".to_owned(), tagged(&["/a"], &["c"])),
+            ("b".to_owned(), tagged(&["/b"], &[])),
+            ("c".to_owned(), tagged(&["/c"], &["a"])),
+            ("d".to_owned(), tagged(&[], &["c"])),
+        ]);
+        let resolved = ResolvedTags {
+            raw: includes(["a", "b", "c", "d"]),
+            tags,
+        };
+
+        assert_eq!(resolved.are_disjoint("a", "b"), Some(true));
+        assert_eq!(resolved.are_disjoint("b", "d"), Some(true));
+        assert_eq!(resolved.are_disjoint("b", "b"), Some(false));
+        assert_eq!(resolved.are_disjoint("a", "d"), Some(false));
+        assert_eq!(resolved.are_disjoint("d", "c"), Some(false));
+        assert_eq!(resolved.are_disjoint("a", "missing"), None);
+        assert_eq!(resolved.are_disjoint("missing", "a"), None);
+    }
+
+    #[test]
+    fn checksum_detects_external_changes() {
+        let mut store = scratch_store("checksum");
+        store.set_checksum(true);
+        let tag = RawTag::default().with_paths(["/a"]);
+        store.save("a", &tag).unwrap();
+        let checksum = store.root().join("a.json.sha256");
+        assert_eq!(
+            std::fs::read_to_string(&checksum).unwrap(),
+            sha256::hex_digest(&std::fs::read(store.root().join("a.json")).unwrap())
+        );
+        assert_eq!(store.load("a").unwrap(), tag);
+
+        std::fs::write(
+            store.root().join("a.json"),
+            r#"{"include_tags":[],"inherited_tags":[],"paths":["/b"]}"#,
+        )
+        .unwrap();
+        assert!(matches!(store.load("a"), Err(IoTagError::Checksum(_))));
+        std::fs::remove_file(&checksum).unwrap();
+        assert!(store.load("a").unwrap().contains_path("/b"));
+
+        store.set_checksum(false);
+        store.save("a", &tag).unwrap();
+        assert!(!checksum.exists());
+    }
+
+    #[test]
+    fn tags_for_path_includes_ancestors() {
+        let resolved = graph([
+            ("leaf", RawTag::default().with_paths(["/x", "/y"])),
+            ("mid", RawTag::default().with_includes(["leaf"])),
+            (
+                "top",
+                RawTag::default().with_includes(["mid"]).with_paths(["/z"]),
+            ),
+            ("other", RawTag::default().with_paths(["/y"])),
+        ]);
+        let keys = |keys: &[&str]| keys.iter().map(|key| (*key).to_owned()).collect();
+        assert_eq!(
+            resolved.tags_for_path(&PathBuf::from("/x")),
+            keys(&["leaf", "mid", "top"])
+        );
+        assert_eq!(
+            resolved.tags_for_path(&PathBuf::from("/y")),
+            keys(&["leaf", "mid", "top", "other"])
+        );
+        assert_eq!(resolved.tags_for_path(&PathBuf::from("/z")), keys(&["top"]));
+        assert!(resolved.tags_for_path(&PathBuf::from("/w")).is_empty());
+    }
+
+    #[test]
+    fn saves_are_byte_identical_and_formatted() {
+        let mut store = scratch_store("formatting");
+        store.set_save_options(SaveOptions::new(4, true));
+        let paths = ["/c", "/a", "/b"];
+        let tag = RawTag::default()
+            .with_paths(paths)
+            .with_includes(["z", "y"]);
+        store.save("a", &tag).unwrap();
+        let first = std::fs::read(store.root().join("a.json")).unwrap();
+        let reordered = RawTag::default()
+            .with_includes(["y", "z"])
+            .with_paths(paths.into_iter().rev());
+        store.save("a", &reordered).unwrap();
+        let second = std::fs::read(store.root().join("a.json")).unwrap();
+        assert_eq!(first, second);
+
+        let text = String::from_utf8(first).unwrap();
+        assert!(text.ends_with("}\n"));
+        assert!(text.contains("\n    \"include_tags\": [\n        \"y\",\n        \"z\"\n    ]"));
+        assert_eq!(
+            RawTag::default()
+                .to_vec_with(&SaveOptions::default())
+                .unwrap(),
+            b"{\n  \"include_tags\": [],\n  \"inherited_tags\": [],\n  \"paths\": []\n}"
+        );
+    }
+
+    #[test]
+    fn merges_path_metadata() {
+        let tags = |tags: &[&str]| tags.iter().map(|tag| (*tag).to_owned()).collect();
+        let mut meta = PathMetadata::new(tags(&["a", "b"]));
+        meta.merge(&PathMetadata::new(tags(&["b", "c"])));
+        assert_eq!(meta.tags(), &tags(&["a", "b", "c"]));
+
+        let store = scratch_store("merge-metadata");
+        let file = store.root().join("file");
+        std::fs::write(&file, "").unwrap();
+        PathMetadata::new(tags(&["d"])).save(&file).unwrap();
+        meta.merge_from_file(&file).unwrap();
+        assert_eq!(meta.tags(), &tags(&["a", "b", "c", "d"]));
+        assert!(meta.merge_from_file(store.root().join("missing")).is_err());
+    }
+
+    #[test]
+    fn load_inherited_merges_directory_metadata() {
+        let store = scratch_store("load-inherited");
+        let dir = store.root().join("dir");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let file = dir.join("sub/file");
+        std::fs::write(&file, "").unwrap();
+        PathMetadata::new(HashSet::from(["outer".to_owned()]))
+            .save(&dir)
+            .unwrap();
+        PathMetadata::new(HashSet::from(["own".to_owned()]))
+            .save(&file)
+            .unwrap();
+
+        let meta = PathMetadata::load_inherited(&file).unwrap();
+        assert_eq!(meta.tags_sorted(), ["outer", "own"]);
+        assert_eq!(PathMetadata::load(&file).unwrap().tags_sorted(), ["own"]);
+        let meta = PathMetadata::load_inherited(dir.join("sub")).unwrap();
+        assert_eq!(meta.tags_sorted(), ["outer"]);
+    }
+
+    #[test]
+    fn membership_predicates() {
+        let tag = RawTag::default()
+            .with_paths(["/a/b"])
+            .with_includes(["inc"])
+            .with_inherits(["inh"]);
+        assert!(tag.contains_path("/a/b"));
+        assert!(tag.contains_path(Path::new("/a/b/")));
+        assert!(!tag.contains_path("/a"));
+        assert!(tag.contains_include("inc"));
+        assert!(!tag.contains_include("inh"));
+        assert!(tag.contains_inherit("inh"));
+        assert!(!tag.contains_inherit("inc"));
+    }
+
+    #[test]
+    fn paths_under_the_base_are_stored_relative() {
+        let mut store = scratch_store("relative-paths");
+        let base = PathBuf::from("/home/someone/project");
+        store.set_base(Some(base.clone()));
+        let tag = RawTag::default().with_paths([base.join("src/main.rs"), "/etc/hosts".into()]);
+        store.save("a", &tag).unwrap();
+        let stored = std::fs::read(store.root().join("a.json")).unwrap();
+        let stored = RawTag::load_from(stored.as_slice()).unwrap();
+        assert_eq!(
+            stored.sorted_paths(),
+            [Path::new("/etc/hosts"), Path::new("src/main.rs")]
+        );
+        assert_eq!(store.load("a").unwrap(), tag);
+
+        let moved = PathBuf::from("/mnt/elsewhere");
+        store.set_base(Some(moved.clone()));
+        assert!(store
+            .load("a")
+            .unwrap()
+            .contains_path(moved.join("src/main.rs")));
+        store.set_base(None);
+        assert!(store.load("a").unwrap().contains_path("src/main.rs"));
+    }
+
+    #[test]
+    fn most_common_paths_ranks_by_tag_count() {
+        let resolved = graph([
+            ("a", RawTag::default().with_paths(["/hub", "/x"])),
+            ("b", RawTag::default().with_paths(["/hub", "/y"])),
+            (
+                "c",
+                RawTag::default().with_includes(["b"]).with_paths(["/x"]),
+            ),
+        ]);
+        assert_eq!(
+            resolved.most_common_paths(),
+            [
+                (PathBuf::from("/hub"), 3),
+                (PathBuf::from("/x"), 2),
+                (PathBuf::from("/y"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_operations_fail() {
+        let store = scratch_store("cancelled");
+        store.save("a", &includes(["b"])).unwrap();
+        let mut b = RawTag::default();
+        b.paths_mut().insert(PathBuf::from("/x"));
+        store.save("b", &b).unwrap();
+
+        let cancel = AtomicBool::new(true);
+        let result = ResolvedTags::resolve_in_cancellable(includes(["a"]), &store, &cancel);
+        assert!(matches!(result, Err(ResolveError::Cancelled)));
+
+        let resolved = ResolvedTags::resolve_in(includes(["a"]), &store).unwrap();
+        assert!(matches!(
+            resolved.union_cancellable(&cancel),
+            Err(ResolveError::Cancelled)
+        ));
+        assert!(matches!(
+            resolved.intersection_cancellable(&cancel),
+            Err(ResolveError::Cancelled)
+        ));
+
+        cancel.store(false, Ordering::Relaxed);
+        assert!(resolved.union_cancellable(&cancel).is_ok());
+    }
+
+    #[test]
+    fn nested_names_are_stored_in_subdirectories() {
+        let store = scratch_store("nested");
+        let mut tag = RawTag::default();
+        tag.paths_mut().insert(PathBuf::from("/src/main.rs"));
+        store.save("lang/rust", &tag).unwrap();
+
+        assert!(store.root().join("lang/rust.json").is_file());
+        assert_eq!(store.load("lang/rust").unwrap().paths(), tag.paths());
+        assert!(matches!(
+            store.save("../escaped", &tag),
+            Err(IoTagError::InvalidName(_))
+        ));
+        assert!(matches!(
+            store.load("lang/../../escaped"),
+            Err(IoTagError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn path_metadata_saves_in_sorted_order() {
+        let store = scratch_store("sorted-metadata");
+        let file = store.root().join("file");
+        std::fs::write(&file, "").unwrap();
+        let names = ["delta", "alpha", "charlie", "bravo"];
+        let meta = PathMetadata::new(names.iter().map(|tag| (*tag).to_owned()).collect());
+        assert_eq!(meta.tags_sorted(), ["alpha", "bravo", "charlie", "delta"]);
+
+        meta.save(&file).unwrap();
+        let first = std::fs::read(PathMetadata::resolve(&file)).unwrap();
+        let meta = PathMetadata::new(names.iter().rev().map(|tag| (*tag).to_owned()).collect());
+        meta.save(&file).unwrap();
+        let second = std::fs::read(PathMetadata::resolve(&file)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, b"alpha\nbravo\ncharlie\ndelta");
+    }
+
+    #[test]
+    fn iterators_visit_each_element_once() {
+        let tag = RawTag::default()
+            .with_paths(["/a", "/b", "/c"])
+            .with_includes(["x", "y"])
+            .with_inherits(["z"]);
+        let paths = tag.iter_paths().sorted().collect_vec();
+        assert_eq!(paths, [Path::new("/a"), Path::new("/b"), Path::new("/c")]);
+        assert_eq!(tag.iter_includes().sorted().collect_vec(), ["x", "y"]);
+        assert_eq!(tag.iter_inherits().collect_vec(), ["z"]);
+        assert_eq!(RawTag::default().iter_paths().count(), 0);
+    }
+
+    #[test]
+    fn parallel_intersection_matches_sequential() {
+        let resolved = graph([
+            ("a", RawTag::default().with_paths(["/x", "/y", "/z"])),
+            ("b", RawTag::default().with_paths(["/y", "/z"])),
+            ("c", includes(["b"]).with_paths(["/x"])),
+            ("d", RawTag::default().with_paths(["/y", "/z", "/w"])),
+        ]);
+        let expected = resolved.intersection();
+        assert_eq!(
+            expected,
+            HashSet::from([PathBuf::from("/y"), PathBuf::from("/z")])
+        );
+        for threads in [1, 2, 4, 8] {
+            let threads = NonZeroUsize::new(threads).unwrap();
+            assert_eq!(resolved.intersection_parallel(threads), expected);
+        }
+    }
+
+    #[test]
+    fn remove_path_everywhere_detaches_from_every_tag() {
+        let store = scratch_store("detach");
+        let file = store.root().join("file");
+        std::fs::write(&file, "").unwrap();
+        let other = PathBuf::from("/other");
+        store
+            .save("a", &RawTag::default().with_paths([&file, &other]))
+            .unwrap();
+        store
+            .save("b", &RawTag::default().with_paths([&file]))
+            .unwrap();
+        store
+            .save("c", &RawTag::default().with_paths([&other]))
+            .unwrap();
+        PathMetadata::new(HashSet::from(["a".to_owned(), "b".to_owned()]))
+            .save(&file)
+            .unwrap();
+
+        assert_eq!(store.remove_path_everywhere(&file).unwrap(), ["a", "b"]);
+        assert_eq!(store.load("a").unwrap().paths(), &HashSet::from([other]));
+        assert!(!store.resolve("b").exists());
+        assert!(!PathMetadata::resolve(&file).exists());
+        assert!(store.remove_path_everywhere(&file).unwrap().is_empty());
+    }
+
+    #[test]
+    fn into_parts_moves_out_the_resolution() {
+        let store = scratch_store("into-parts");
+        store.save("a", &includes(["b"])).unwrap();
+        let b = RawTag::default().with_paths(["/x"]);
+        store.save("b", &b).unwrap();
+
+        let resolved = ResolvedTags::resolve_in(includes(["a"]), &store).unwrap();
+        let expected = resolved.tags().clone();
+        let (raw, tags) = resolved.into_parts();
+        assert_eq!(raw.include_tags(), includes(["a"]).include_tags());
+        assert_eq!(tags, expected);
+        assert_eq!(tags["b"].paths(), b.paths());
+        assert!(tags.contains_key("a"));
+    }
+
+    #[test]
+    fn path_metadata_diff_splits_added_and_removed() {
+        let tags = |tags: &[&str]| tags.iter().map(|tag| (*tag).to_owned()).collect();
+        let new = PathMetadata::new(tags(&["a", "b", "c"]));
+        let old = PathMetadata::new(tags(&["b", "c", "d"]));
+        assert_eq!(new.diff(&old), (tags(&["a"]), tags(&["d"])));
+        assert_eq!(old.diff(&new), (tags(&["d"]), tags(&["a"])));
+        assert_eq!(new.diff(&new), (HashSet::new(), HashSet::new()));
+    }
+
+    #[test]
+    fn closure_covers_every_loaded_and_missing_tag() {
+        let store = scratch_store("closure");
+        store
+            .save("top", &includes(["left"]).with_inherits(["style"]))
+            .unwrap();
+        store
+            .save("left", &includes(["bottom", "missing"]))
+            .unwrap();
+        store
+            .save("style", &RawTag::default().with_paths(["/s"]))
+            .unwrap();
+        store
+            .save("bottom", &RawTag::default().with_paths(["/x"]))
+            .unwrap();
+
+        let resolved = ResolvedTags::resolve_in(includes(["top"]), &store).unwrap();
+        let mut expected = resolved.tags().keys().cloned().collect::<HashSet<_>>();
+        assert_eq!(expected.len(), 4);
+        expected.insert("missing".to_owned());
+        assert_eq!(resolved.closure(), expected);
+    }
+
+    #[test]
+    fn normalized_tags_serialize_identically() {
+        let mut spelled = RawTag::default()
+            .with_paths(["/a/./b/", "/c//d"])
+            .with_includes(["y", "x"]);
+        let mut canonical = RawTag::default()
+            .with_includes(["x", "y"])
+            .with_paths(["/c/d", "/a/b"]);
+        assert_eq!(spelled, canonical);
+
+        spelled.normalize();
+        canonical.normalize();
+        assert_eq!(spelled, canonical);
+        let options = SaveOptions::default();
+        assert_eq!(
+            spelled.to_vec_with(&options).unwrap(),
+            canonical.to_vec_with(&options).unwrap()
+        );
+    }
+
+    #[test]
+    fn validate_reports_dangling_references() {
+        let store = scratch_store("validate");
+        store
+            .save("top", &includes(["left", "typo"]).with_inherits(["gone"]))
+            .unwrap();
+        store
+            .save("left", &RawTag::default().with_paths(["/x"]))
+            .unwrap();
+
+        let resolved = ResolvedTags::resolve_in(includes(["top", "absent"]), &store).unwrap();
+        assert_eq!(
+            resolved.validate(),
+            [
+                BrokenRef::new(None, "absent".to_owned(), RefKind::Include),
+                BrokenRef::new(Some("top".to_owned()), "gone".to_owned(), RefKind::Inherit),
+                BrokenRef::new(Some("top".to_owned()), "typo".to_owned(), RefKind::Include),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_all_under_finds_every_sidecar() {
+        let store = scratch_store("load-all-under");
+        let root = store.root();
+        std::fs::create_dir_all(root.join("dir/deep")).unwrap();
+        for file in ["a", "dir/deep/b"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let tags = |tag: &str| PathMetadata::new(HashSet::from([tag.to_owned()]));
+        tags("a").save(root.join("a")).unwrap();
+        tags("b").save(root.join("dir/deep/b")).unwrap();
+        tags("dir").save(root.join("dir")).unwrap();
+        std::fs::write(root.join("gone.tag.list"), "gone").unwrap();
+        std::fs::write(root.join("dir/bad.tag.list"), [0xff]).unwrap();
+
+        let (found, failed): (Vec<_>, Vec<_>) =
+            PathMetadata::load_all_under(root).partition_result();
+        let found = found
+            .into_iter()
+            .map(|(path, meta)| (path, meta.tags_sorted().into_iter().join(",")))
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            found,
+            [
+                (root.join("a"), "a".to_owned()),
+                (root.join("dir"), "dir".to_owned()),
+                (root.join("dir/deep/b"), "b".to_owned()),
+                (root.join("gone"), "gone".to_owned()),
+            ]
+        );
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, root.join("dir/bad.tag.list"));
+    }
+
+    #[test]
+    fn io_errors_name_the_operation_and_path() {
+        let store = scratch_store("io-context");
+        let error = store.load("missing").unwrap_err();
+        let path = store.root().join("missing.json");
+        assert!(
+            matches!(&error, IoTagError::Io { operation: IoOperation::Read, path: at, source }
+                if *at == path && source.kind() == io::ErrorKind::NotFound),
+            "{error:?}"
+        );
+        let message = error.to_string();
+        assert!(message.starts_with(&format!("failed to read {}: ", path.display())));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn query_all_resolves_inherited_tags_too() {
+        let store = scratch_store("query-all");
+        store
+            .save("a", &RawTag::default().with_paths(["/x"]))
+            .unwrap();
+        store
+            .save("style", &RawTag::default().with_inherits(["theme"]))
+            .unwrap();
+        store
+            .save("theme", &RawTag::default().with_paths(["/t"]))
+            .unwrap();
+
+        let query = RawTag::query_all(
+            HashSet::from(["a".to_owned()]),
+            HashSet::from(["style".to_owned()]),
+        );
+        assert_eq!(query.include_tags(), includes(["a"]).include_tags());
+        assert_eq!(query.inherited_tags_len(), 1);
+
+        let resolved = ResolvedTags::resolve_in(query, &store).unwrap();
+        let names = resolved.tags().keys().cloned().sorted().collect_vec();
+        assert_eq!(names, ["a", "style", "theme"]);
+        assert_eq!(resolved.union(), HashSet::from([PathBuf::from("/x")]));
+    }
+
+    #[test]
+    fn path_count_by_tag_counts_recursive_unions() {
+        let resolved = graph([
+            ("leaf", RawTag::default().with_paths(["/a", "/b"])),
+            ("other", RawTag::default().with_paths(["/b", "/c"])),
+            ("mid", includes(["leaf", "other"]).with_paths(["/d"])),
+            ("top", includes(["mid", "missing"]).with_paths(["/a"])),
+        ]);
+        let counts = resolved.path_count_by_tag();
+        assert_eq!(
+            counts,
+            HashMap::from([
+                ("leaf".to_owned(), 2),
+                ("other".to_owned(), 2),
+                ("mid".to_owned(), 4),
+                ("top".to_owned(), 4),
+            ])
+        );
+    }
+
+    #[test]
+    fn path_metadata_clear_returns_the_previous_tags() {
+        let previous = HashSet::from(["a".to_owned(), "b".to_owned()]);
+        let mut meta = PathMetadata::new(previous.clone());
+        assert_eq!(meta.clear(), previous);
+        assert!(meta.is_empty());
+        assert!(meta.clear().is_empty());
+    }
+
+    #[test]
+    fn keep_empty_retains_tags_without_paths() {
+        let mut store = scratch_store("keep-empty");
+        let linked = includes(["other"]);
+        store.save("linked", &linked).unwrap();
+        store.save("linked", &RawTag::default()).unwrap();
+        assert!(!store.resolve("linked").exists());
+
+        store.set_keep_empty(true);
+        store.save("linked", &linked).unwrap();
+        let mut emptied = linked.clone();
+        emptied.paths_mut().clear();
+        store.save("linked", &emptied).unwrap();
+        assert_eq!(store.load("linked").unwrap(), linked);
+        store.save("linked", &RawTag::default()).unwrap();
+        assert!(store.resolve("linked").is_file());
+        assert!(store.load("linked").unwrap().is_empty());
+    }
+
+    #[test]
+    fn subgraph_keeps_the_closure_of_the_roots() {
+        let resolved = graph([
+            ("a", includes(["b"]).with_inherits(["c"])),
+            ("b", RawTag::default().with_paths(["/b"])),
+            ("c", includes(["d"])),
+            ("d", RawTag::default().with_paths(["/d"])),
+            ("e", includes(["b"]).with_paths(["/e"])),
+        ]);
+        let roots = HashSet::from(["a".to_owned(), "missing".to_owned()]);
+        let subgraph = resolved.subgraph(&roots);
+        let names = subgraph.tags().keys().cloned().sorted().collect_vec();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        assert_eq!(subgraph.tags()["c"], resolved.tags()["c"]);
+        assert_eq!(subgraph.raw().include_tags(), &roots);
+        assert_eq!(subgraph.union(), HashSet::from([PathBuf::from("/b")]));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_paths_are_spelled_verbatim() {
+        let name = "d".repeat(100);
+        let path = PathBuf::from(format!(r"C:\{name}\..\{name}\.\{name}\{name}"));
+        assert_eq!(
+            io_path(&path),
+            Path::new(&format!(r"\\?\C:\{name}\{name}\{name}"))
+        );
+        let path = PathBuf::from(format!(r"\\server\share\{name}\{name}\{name}"));
+        assert_eq!(
+            io_path(&path),
+            Path::new(&format!(r"\\?\UNC\server\share\{name}\{name}\{name}"))
+        );
+        assert!(matches!(io_path(Path::new(r"C:\short")), Cow::Borrowed(_)));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_paths_are_saved_and_loaded() {
+        let store = scratch_store("long-paths");
+        let mut root = store.root().to_path_buf();
+        while root.as_os_str().len() < 300 {
+            root.push("d".repeat(50));
+        }
+        let store = Store::new(root.join("tags"));
+        let file = root.join("file");
+        std::fs::create_dir_all(io_path(&root)).unwrap();
+        std::fs::write(io_path(&file), "").unwrap();
+
+        let tag = RawTag::default().with_paths([&file]);
+        store.save("nested/tag", &tag).unwrap();
+        assert_eq!(store.load("nested/tag").unwrap(), tag);
+        PathMetadata::new(HashSet::from(["tag".to_owned()]))
+            .save(&file)
+            .unwrap();
+        assert_eq!(PathMetadata::load(&file).unwrap().tags_sorted(), ["tag"]);
+    }
+
+    #[test]
+    fn retain_paths_counts_the_removed_paths() {
+        let mut tag = RawTag::default().with_paths(["/keep/a", "/keep/b/c", "/drop/a", "/other"]);
+        assert_eq!(tag.retain_paths(|path| path.starts_with("/keep")), 2);
+        assert_eq!(
+            tag.sorted_paths(),
+            [Path::new("/keep/a"), Path::new("/keep/b/c")]
+        );
+        assert_eq!(tag.retain_paths(|_| true), 0);
+        assert_eq!(tag.retain_paths(|_| false), 2);
+        assert!(tag.is_empty());
+    }
+
+    #[test]
+    fn resolve_from_store_loads_from_the_given_root() {
+        let store = scratch_store("from-store");
+        store.save("top", &includes(["leaf"])).unwrap();
+        store
+            .save("leaf", &RawTag::default().with_paths(["/x"]))
+            .unwrap();
+
+        let resolved = ResolvedTags::resolve_from_store(includes(["top"]), store.root()).unwrap();
+        let names = resolved.tags().keys().cloned().sorted().collect_vec();
+        assert_eq!(names, ["leaf", "top"]);
+        assert_eq!(resolved.union(), HashSet::from([PathBuf::from("/x")]));
+
+        let elsewhere = scratch_store("from-store-empty");
+        let resolved =
+            ResolvedTags::resolve_from_store(includes(["top"]), elsewhere.root()).unwrap();
+        assert!(resolved.tags().is_empty());
+    }
+
+    #[test]
+    fn all_tags_within_grows_with_depth() {
+        let resolved = graph([
+            ("a", RawTag::default().with_inherits(["b"])),
+            ("b", RawTag::default().with_inherits(["c"])),
+            ("c", RawTag::default().with_inherits(["d"])),
+        ]);
+        let resolved = resolved.subgraph(&HashSet::from(["a".to_owned()]));
+        let within = |depth| {
+            resolved
+                .all_tags_within(depth)
+                .into_iter()
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(within(0), ["a"]);
+        assert_eq!(within(1), ["a", "b"]);
+        assert_eq!(within(2), ["a", "b", "c"]);
+        assert_eq!(within(3), ["a", "b", "c", "d"]);
+        assert_eq!(within(10), within(3));
+        assert_eq!(
+            resolved.all_tags().into_iter().sorted().collect_vec(),
+            within(3)
+        );
+    }
+
+    #[test]
+    fn is_referenced_by_finds_includes_and_inherits() {
+        let store = scratch_store("referenced-by");
+        store
+            .save("base", &RawTag::default().with_paths(["/x"]))
+            .unwrap();
+        store.save("including", &includes(["base"])).unwrap();
+        store
+            .save("inheriting", &RawTag::default().with_inherits(["base"]))
+            .unwrap();
+        store.save("unrelated", &includes(["other"])).unwrap();
+
+        let referencing = RawTag::is_referenced_by("base", store.root()).unwrap();
+        assert_eq!(referencing, ["including", "inheriting"]);
+        assert_eq!(store.referencing("base").unwrap(), referencing);
+        assert!(store.referencing("unrelated").unwrap().is_empty());
+    }
+
+    #[test]
+    fn name_transforms_normalize_names() {
+        let name = "Lang/Rust  Lang__2-x-";
+        assert_eq!(NameTransform::None.apply(name), name);
+        assert_eq!(
+            NameTransform::Lowercase.apply(name),
+            "lang/rust  lang__2-x-"
+        );
+        assert_eq!(NameTransform::Kebab.apply(name), "lang/rust-lang-2-x");
+    }
+
+    #[test]
+    fn paths_only_in_excludes_other_queried_tags() {
+        let resolved = graph([
+            ("a", RawTag::default().with_paths(["/only-a", "/shared"])),
+            ("b", includes(["c"]).with_paths(["/shared"])),
+            ("c", RawTag::default().with_paths(["/only-b"])),
+        ]);
+        let resolved = ResolvedTags {
+            raw: includes(["a", "b"]),
+            ..resolved
+        };
+        assert_eq!(
+            resolved.paths_only_in("a"),
+            HashSet::from([PathBuf::from("/only-a")])
+        );
+        assert_eq!(
+            resolved.paths_only_in("b"),
+            HashSet::from([PathBuf::from("/only-b")])
+        );
+        assert!(resolved.paths_only_in("missing").is_empty());
+    }
+
+    #[test]
+    fn round_trips_through_memory() {
+        let tag = RawTag::default()
+            .with_paths(["/a", "/b"])
+            .with_includes(["x"])
+            .with_inherits(["y"]);
+        let mut bytes = Vec::new();
+        tag.save_to(&mut bytes, &SaveOptions::default()).unwrap();
+        assert_eq!(RawTag::load_from(bytes.as_slice()).unwrap(), tag);
+        assert!(RawTag::load_from(&b"{"[..]).is_err());
+
+        let meta = PathMetadata::new(HashSet::from(["b".to_owned(), "a".to_owned()]));
+        let mut bytes = Vec::new();
+        meta.save_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"a\nb");
+        assert_eq!(PathMetadata::load_from(bytes.as_slice()).unwrap(), meta);
+        let json = PathMetadata::load_from(&br#"{"tags": ["a", "b"]}"#[..]).unwrap();
+        assert_eq!(json, meta);
+        let json = PathMetadata::load_from(&br#"["a", "b"]"#[..]).unwrap();
+        assert_eq!(json, meta);
+    }
+
+    #[test]
+    fn merge_resolved_unions_overlapping_tags() {
+        let first = graph([
+            ("shared", includes(["left"]).with_paths(["/a"])),
+            ("left", RawTag::default().with_paths(["/l"])),
+        ]);
+        let second = graph([
+            ("shared", RawTag::default().with_paths(["/b"])),
+            ("right", RawTag::default().with_paths(["/r"])),
+        ]);
+        let merged = first.merge_resolved(second);
+        let names = merged.tags().keys().cloned().sorted().collect_vec();
+        assert_eq!(names, ["left", "right", "shared"]);
+        assert_eq!(merged.raw().include_tags().len(), 3);
+        assert_eq!(
+            merged.tags()["shared"],
+            includes(["left"]).with_paths(["/a", "/b"])
+        );
+        let union = merged.union().into_iter().sorted().collect_vec();
+        assert_eq!(union, ["/a", "/b", "/l", "/r"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn lock_excludes_until_released() {
+        let store = scratch_store("lock");
+        let lock = store.lock(false).unwrap();
+        let path = store.root().join(Store::LOCK_FILE_NAME);
+        assert!(path.is_file());
+        assert!(matches!(store.lock(false), Err(IoTagError::Locked(at)) if at == path));
+
+        drop(lock);
+        assert!(!path.exists());
+        drop(store.lock(false).unwrap());
+    }
+
+    #[test]
+    fn path_metadata_resolves_files_dirs_and_missing_paths() {
+        let store = scratch_store("resolve-metadata");
+        let root = store.root();
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::write(root.join("file"), "").unwrap();
+
+        let resolve = |path: &str| PathMetadata::resolve(root.join(path));
+        assert_eq!(resolve("file"), root.join("file.tag.list"));
+        assert_eq!(resolve("dir"), root.join("dir/.tag.list"));
+        assert_eq!(resolve("new"), root.join("new.tag.list"));
+        assert_eq!(resolve("new/"), root.join("new/.tag.list"));
+        assert_eq!(resolve("missing/new"), root.join("missing/new.tag.list"));
+        assert_eq!(
+            PathMetadata::resolve_as(root.join("new"), PathKind::Dir),
+            root.join("new/.tag.list")
+        );
+        assert_eq!(
+            PathMetadata::owner_of(root.join("new.tag.list")),
+            Some(root.join("new"))
+        );
+        assert_eq!(
+            PathMetadata::owner_of(root.join("dir/.tag.list")),
+            Some(root.join("dir"))
+        );
+        assert_eq!(PathMetadata::owner_of(root.join("other.txt")), None);
+    }
+
+    #[test]
+    fn rename_included_retargets_includes_and_inherits() {
+        let mut tag = includes(["old", "kept"]).with_inherits(["other"]);
+        assert!(tag.rename_included("old", "new"));
+        assert_eq!(tag, includes(["new", "kept"]).with_inherits(["other"]));
+
+        assert!(tag.rename_included("other", "renamed"));
+        assert_eq!(tag, includes(["new", "kept"]).with_inherits(["renamed"]));
+
+        let before = tag.clone();
+        assert!(!tag.rename_included("absent", "new"));
+        assert_eq!(tag, before);
+    }
+
+    #[test]
+    fn tag_names_yields_the_resolved_keys() {
+        let store = scratch_store("tag-names");
+        store.save("a", &includes(["b"])).unwrap();
+        store
+            .save("b", &RawTag::default().with_paths(["/x"]))
+            .unwrap();
+
+        let resolved = ResolvedTags::resolve_in(includes(["a", "missing"]), &store).unwrap();
+        assert_eq!(resolved.tag_names().sorted().collect_vec(), ["a", "b"]);
+        assert_eq!(
+            resolved.tag_names().collect::<HashSet<_>>(),
+            resolved.tags().keys().collect()
+        );
+    }
+
+    #[test]
+    fn merge_in_place_reports_what_it_added() {
+        let mut tag = includes(["a"]).with_paths(["/x"]);
+        let other = includes(["a", "b", "c"])
+            .with_inherits(["d"])
+            .with_paths(["/x", "/y"]);
+        let report = tag.merge_in_place(&other);
+        assert_eq!(report.include_tags(), 2);
+        assert_eq!(report.inherited_tags(), 1);
+        assert_eq!(report.paths(), 1);
+        assert_eq!(report.path_globs(), 0);
+        assert_eq!(tag, other);
+
+        assert_eq!(tag.merge_in_place(&other), MergeReport::default());
+    }
+
+    #[test]
+    fn stats_describe_the_graph() {
+        let resolved = graph([
+            ("top", includes(["mid", "typo"]).with_paths(["/a"])),
+            ("mid", includes(["leaf"]).with_inherits(["style"])),
+            ("leaf", RawTag::default().with_paths(["/a", "/b"])),
+            ("style", RawTag::default().with_paths(["/s"])),
+        ]);
+        let resolved = resolved.subgraph(&HashSet::from(["top".to_owned()]));
+        let stats = resolved.stats();
+        assert_eq!(stats.tags(), 4);
+        assert_eq!(stats.include_edges(), 3);
+        assert_eq!(stats.inherit_edges(), 1);
+        assert_eq!(stats.max_depth(), 3);
+        assert_eq!(stats.paths(), 4);
+        assert_eq!(stats.distinct_paths(), 3);
+        assert_eq!(
+            stats.broken_refs(),
+            [BrokenRef::new(
+                Some("top".to_owned()),
+                "typo".to_owned(),
+                RefKind::Include
+            )]
+        );
+    }
+
+    #[test]
+    fn functional_constructors_match_new() {
+        let strings = |names: &[&str]| names.iter().map(|name| (*name).to_owned()).collect();
+        let expected = RawTag::new(
+            strings(&["a", "b"]),
+            strings(&["c"]),
+            HashSet::from([PathBuf::from("/x"), PathBuf::from("/y")]),
+        );
+        let built = RawTag::default()
+            .with_paths([Path::new("/x")])
+            .with_includes(["a".to_owned()])
+            .with_inherits(["c"])
+            .with_paths([PathBuf::from("/y")])
+            .with_includes(["b"]);
+        assert_eq!(built, expected);
+        assert_eq!(
+            RawTag::default().with_paths(Vec::<PathBuf>::new()),
+            RawTag::default()
+        );
+    }
+
+    #[test]
+    fn reachable_from_records_the_shortest_chain() {
+        let resolved = graph([
+            ("top", includes(["mid", "side"]).with_paths(["/own"])),
+            ("mid", includes(["leaf"])),
+            ("side", RawTag::default().with_paths(["/side"])),
+            ("leaf", RawTag::default().with_paths(["/deep", "/side"])),
+        ]);
+        let reachable = resolved.reachable_from("top");
+        let chain = |path: &str| {
+            reachable[Path::new(path)]
+                .iter()
+                .map(String::as_str)
+                .collect_vec()
+        };
+        assert_eq!(reachable.len(), 3);
+        assert_eq!(chain("/own"), ["top"]);
+        assert_eq!(chain("/side"), ["top", "side"]);
+        assert_eq!(chain("/deep"), ["top", "mid", "leaf"]);
+        assert!(resolved.reachable_from("missing").is_empty());
+    }
+
+    #[test]
+    fn sorted_getters_sort_each_field() {
+        let tag = RawTag::default()
+            .with_paths(["/c", "/a", "/b"])
+            .with_includes(["z", "x", "y"])
+            .with_inherits(["2", "10", "1"]);
+        assert_eq!(
+            tag.sorted_paths(),
+            [Path::new("/a"), Path::new("/b"), Path::new("/c")]
+        );
+        assert_eq!(tag.sorted_include_tags(), ["x", "y", "z"]);
+        assert_eq!(tag.sorted_inherited_tags(), ["1", "10", "2"]);
+    }
+
+    #[test]
+    fn intersection_of_matches_the_query_intersection() {
+        let resolved = graph([
+            ("a", RawTag::default().with_paths(["/x", "/y"])),
+            ("b", includes(["c"]).with_paths(["/x"])),
+            ("c", RawTag::default().with_paths(["/y"])),
+        ]);
+        let all = resolved.raw().include_tags().clone();
+        assert_eq!(resolved.intersection_of(&all), resolved.intersection());
+        assert_eq!(
+            resolved.intersection(),
+            HashSet::from([PathBuf::from("/y")])
+        );
+
+        let subset = HashSet::from(["a".to_owned(), "b".to_owned()]);
+        assert_eq!(
+            resolved.intersection_of(&subset),
+            HashSet::from([PathBuf::from("/x"), PathBuf::from("/y")])
+        );
+        let missing = HashSet::from(["a".to_owned(), "missing".to_owned()]);
+        assert!(resolved.intersection_of(&missing).is_empty());
+    }
+
+    #[test]
+    fn path_metadata_detects_the_sidecar_format() {
+        let store = scratch_store("sidecar-format");
+        let file = store.root().join("file");
+        std::fs::write(&file, "").unwrap();
+        let sidecar = PathMetadata::resolve(&file);
+        let expected = PathMetadata::new(HashSet::from(["a".to_owned(), "b".to_owned()]));
+        for contents in [
+            "a\nb",
+            "a\r\nb\n",
+            "[\"a\", \"b\"]",
+            "{\"tags\": [\"b\", \"a\"]}",
+        ] {
+            std::fs::write(&sidecar, contents).unwrap();
+            assert_eq!(PathMetadata::load(&file).unwrap(), expected, "{contents:?}");
+        }
+
+        std::fs::write(&sidecar, "[draft]\nb").unwrap();
+        let meta = PathMetadata::load(&file).unwrap();
+        assert_eq!(meta.tags_sorted(), ["[draft]", "b"]);
+    }
+
+    #[test]
+    fn length_accessors_count_each_field() {
+        let tag = RawTag::default()
+            .with_paths(["/a", "/b", "/c"])
+            .with_includes(["x", "y"])
+            .with_inherits(["z"]);
+        assert_eq!(tag.paths_len(), tag.paths().len());
+        assert_eq!(
+            (
+                tag.paths_len(),
+                tag.include_tags_len(),
+                tag.inherited_tags_len()
+            ),
+            (3, 2, 1)
+        );
+        let empty = RawTag::default();
+        assert_eq!(
+            (
+                empty.paths_len(),
+                empty.include_tags_len(),
+                empty.inherited_tags_len()
+            ),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn from_names_queries_every_name() {
+        let store = scratch_store("from-names");
+        store
+            .save("a", &RawTag::default().with_paths(["/x", "/y"]))
+            .unwrap();
+        store
+            .save("b", &RawTag::default().with_paths(["/y"]))
+            .unwrap();
+
+        let names = ["a", "b"].map(str::to_owned);
+        let resolved = ResolvedTags::from_names_in(names.clone(), &store).unwrap();
+        assert_eq!(resolved.raw(), &includes(["a", "b"]));
+        assert_eq!(
+            resolved.intersection(),
+            HashSet::from([PathBuf::from("/y")])
+        );
+
+        let beside_exe = ResolvedTags::from_names(names).unwrap();
+        assert_eq!(beside_exe.raw(), &includes(["a", "b"]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn convert_relinks_aliases() {
+        let mut store = scratch_store("convert-alias");
+        store.set_checksum(true);
+        let tag = RawTag::default().with_paths(["/x"]);
+        store.save("real", &tag).unwrap();
+        std::os::unix::fs::symlink("real.json", store.root().join("alias.json")).unwrap();
+
+        assert!(store.convert("alias", TagFormat::Toml).unwrap());
+        let alias = store.root().join("alias.toml");
+        assert_eq!(std::fs::read_link(&alias).unwrap(), Path::new("real.toml"));
+        assert!(!store.root().join("alias.json").exists());
+        assert!(!store.root().join("real.json").exists());
+        assert!(!store.root().join("real.json.sha256").exists());
+        assert!(store.root().join("real.toml.sha256").exists());
+        assert!(!store.convert("real", TagFormat::Toml).unwrap());
+        assert_eq!(store.load("alias").unwrap(), tag);
+        assert_eq!(store.load("real").unwrap(), tag);
     }
 }
@@ -1,31 +1,344 @@
-use std::collections::HashSet;
-use std::io;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use clap::Parser;
 use itertools::Itertools;
-use thiserror::Error;
 
-use path_tagging::{IoTagError, PathMetadata, RawTag, ResolvedTags};
+use path_tagging::{
+    Config, IoTagError, PathMetadata, Profile, Query, RawTag, ResolveError, ResolvedTags, Store,
+    TagFormat, TagIgnore,
+};
 
-fn main() {
-    let args = Arguments::parse();
-    args.subcommand.execute();
-}
+mod case_fold;
+
+use case_fold::case_fold;
+
+static LOGGER: Logger = Logger {
+    color: AtomicBool::new(false),
+};
 
-#[derive(Debug, Error)]
-enum Error {
-    #[error("I/O Error")]
-    Io(#[from] io::Error),
-    #[error("Deserialization Error")]
-    De(#[from] serde_json::Error),
+fn main() -> ExitCode {
+    let args = Arguments::parse();
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(args.level_filter());
+    }
+    let Some((stores, config)) = args.stores() else {
+        return ExitCode::from(2);
+    };
+    let Some(defaults) = Defaults::new(&args, &config) else {
+        return ExitCode::from(2);
+    };
+    let color = match defaults.color {
+        ColorChoice::Auto => io::stderr().is_terminal(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    LOGGER.color.store(color, Ordering::Relaxed);
+    let globals = Globals {
+        follow_symlinks: args.follow_symlinks,
+        assume_yes: args.assume_yes,
+        strict: args.strict,
+        format: defaults.format,
+        ignore_case: defaults.ignore_case,
+    };
+    let _locks = if args.exclusive {
+        let locks = stores
+            .iter()
+            .map(|store| store.lock(!args.no_wait))
+            .collect::<Result<Vec<_>, _>>();
+        match locks {
+            Ok(locks) => locks,
+            Err(cause) => {
+                log::error!("Unable to lock the store: {cause}");
+                return ExitCode::from(2);
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let code = args.subcommand.execute(&stores, globals);
+    if args.profile {
+        let profile = Profile::global();
+        eprintln!(
+            "[PROFILE] {} file(s) read; loading {:?}, resolution {:?}, set operations {:?}",
+            profile.files_read(),
+            profile.load_time(),
+            profile.resolve_time(),
+            profile.set_time(),
+        );
+    }
+    code
 }
 
 #[derive(Debug, Parser)]
 struct Arguments {
     #[command(subcommand)]
     subcommand: Subcommand,
+
+    /// Only report errors.
+    ///
+    /// Warnings about individual paths or tags that can't be loaded or saved
+    /// are suppressed.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Report more details; repeat for even more.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// The directory containing the tag files.
+    ///
+    /// Defaults to the `.tags` directory beside this executable. May be
+    /// repeated for `get` to search across several stores at once.
+    #[arg(long, global = true, value_name = "DIR")]
+    store: Vec<PathBuf>,
+
+    /// Ignore configuration files.
+    ///
+    /// Otherwise, defaults are read from `path-tagging.toml` in the current
+    /// directory, or else in the store directory.
+    #[arg(long, global = true)]
+    no_config: bool,
+
+    /// When to color reports on the standard error.
+    ///
+    /// Defaults to coloring them when it is a terminal.
+    #[arg(long, global = true, value_enum, value_name = "WHEN")]
+    color: Option<ColorChoice>,
+
+    /// Resolve symbolic links in given paths.
+    ///
+    /// A link and its target are otherwise different paths. Paths that don't
+    /// exist are kept as given, past their deepest existing directory.
+    #[arg(long, global = true)]
+    follow_symlinks: bool,
+
+    /// Proceed without asking for confirmation.
+    ///
+    /// Commands removing data otherwise ask first when the standard input is
+    /// a terminal, and proceed without asking when it isn't.
+    #[arg(short = 'y', long, global = true)]
+    assume_yes: bool,
+
+    /// Hold a lock on the whole store while the command runs.
+    ///
+    /// Other invocations with this option wait for it to be released.
+    #[arg(long, global = true)]
+    exclusive: bool,
+
+    /// Fail instead of waiting when the store is already locked.
+    #[arg(long, global = true, requires = "exclusive")]
+    no_wait: bool,
+
+    /// Report the time spent in store operations afterwards.
+    ///
+    /// Resolution includes the loading it does.
+    #[arg(long, global = true)]
+    profile: bool,
+
+    /// Fail when any tag or path metadata can't be loaded.
+    ///
+    /// Everything is then loaded before anything is written, so failing leaves
+    /// the store untouched. Otherwise, what can't be loaded is warned about and
+    /// skipped, leaving results partial.
+    #[arg(long, global = true)]
+    strict: bool,
+}
+
+/// Global options affecting how subcommands run.
+#[derive(Clone, Copy, Debug, Default)]
+struct Globals {
+    follow_symlinks: bool,
+    assume_yes: bool,
+    strict: bool,
+    /// The format of displayed results unless given.
+    format: Format,
+    /// Whether tag names match regardless of case unless told otherwise.
+    ignore_case: bool,
+}
+
+/// Defaults of the command line taken from the configuration file, which
+/// options given override.
+#[derive(Clone, Copy, Debug)]
+struct Defaults {
+    format: Format,
+    ignore_case: bool,
+    color: ColorChoice,
+}
+
+/// When to color reports.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+enum ColorChoice {
+    /// Color them when the standard error is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Writes log records to the standard error.
+struct Logger {
+    /// Whether to color the level of each record.
+    color: AtomicBool,
+}
+
+/// A failure of a command that was already reported, making it exit with 1.
+#[derive(Debug)]
+struct Reported;
+
+/// Formats of displayed results.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+enum Format {
+    /// One result per line.
+    #[default]
+    Lines,
+    /// Results terminated by NUL characters, such as for `xargs -0`.
+    Nul,
+    /// A JSON array of strings.
+    Json,
+}
+
+/// Writes results in some format.
+trait OutputWriter {
+    fn write(&mut self, item: &str) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+struct LinesWriter<W>(W);
+
+struct NulWriter<W>(W);
+
+struct JsonWriter<W> {
+    inner: W,
+    started: bool,
+}
+
+/// How the `get` command displays the paths found.
+#[derive(Debug, clap::Args)]
+struct PathDisplay {
+    /// Display at most this many paths.
+    ///
+    /// The first paths in sorted order are displayed and the number of
+    /// omitted paths is reported afterwards.
+    #[arg(long, value_name = "N")]
+    max_results: Option<usize>,
+
+    /// The order to display paths in.
+    #[arg(long, value_enum, default_value_t)]
+    sort: SortKey,
+
+    /// The format to display paths in.
+    ///
+    /// Defaults to the configured format, or else `lines`.
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Write the paths to this file instead of the standard output.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Stream each path as a JSON object such as `{"path": "..."}` on its own
+    /// line, in no particular order.
+    #[arg(long, conflicts_with_all = ["sort", "format", "template"])]
+    json_lines: bool,
+
+    /// Display paths relative to this directory.
+    ///
+    /// Paths outside of it are displayed as is.
+    #[arg(long, value_name = "BASE")]
+    relative: Option<PathBuf>,
+
+    /// Display each path through a template such as `{dir}: {basename}`.
+    ///
+    /// `{path}` is replaced by the path, `{basename}` by its last component
+    /// and `{dir}` by the rest; `{{` and `}}` stand for braces.
+    #[arg(long)]
+    template: Option<Template>,
+
+    /// Only display paths that exist, leaving the store untouched.
+    ///
+    /// See the gc command to remove missing paths from the store instead.
+    #[arg(long)]
+    existing_only: bool,
+
+    /// Only display paths that are files, skipping the missing ones.
+    #[arg(long, conflicts_with = "dirs_only")]
+    files_only: bool,
+
+    /// Only display paths that are directories, skipping the missing ones.
+    #[arg(long)]
+    dirs_only: bool,
+
+    /// Display only the first path in sorted order among those referring to
+    /// the same file.
+    #[arg(long, value_enum, value_name = "KEY")]
+    dedup_by: Option<DedupKey>,
+
+    /// Skip the paths that can't be inspected when deduplicating, instead of
+    /// displaying them.
+    #[arg(long, requires = "dedup_by")]
+    drop_missing: bool,
+}
+
+/// How paths referring to the same file are recognized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+enum DedupKey {
+    /// By device and inode number, collapsing hard links; Unix only.
+    Inode,
+}
+
+/// A template displaying a path, parsed from `--template`.
+#[derive(Clone, Debug)]
+struct Template(Vec<Piece>);
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Literal(String),
+    Path,
+    Basename,
+    Dir,
+}
+
+/// Orders of displayed paths.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+enum SortKey {
+    /// By path.
+    #[default]
+    Name,
+    /// By modification time, oldest first; paths that can't be inspected last.
+    Mtime,
+    /// By file size, smallest first; paths that can't be inspected last.
+    Size,
+    /// In no particular order, which is the fastest.
+    None,
+}
+
+/// How the `list` command displays the tags found.
+#[derive(Debug, clap::Args)]
+struct TagDisplay {
+    /// Display the tags of each path separately.
+    ///
+    /// Each path is displayed followed by its tags, indented.
+    #[arg(short, long, conflicts_with = "format")]
+    grouped: bool,
+
+    /// The format to display tags in.
+    ///
+    /// Defaults to the configured format, or else `lines`.
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Write the tags to this file instead of the standard output.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -36,6 +349,49 @@ enum Subcommand {
     /// none are found.
     Get {
         /// The tags that paths must have.
+        #[arg(required_unless_present = "query", conflicts_with = "query")]
+        tags: Vec<String>,
+
+        /// Display the paths matching a query instead, such as
+        /// `a AND (b OR c) NOT d`.
+        #[arg(long, value_name = "EXPR")]
+        query: Option<Query>,
+
+        /// Match tags regardless of case.
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Match tags by case even if configured to ignore it.
+        #[arg(long, conflicts_with = "ignore_case")]
+        case_sensitive: bool,
+
+        #[command(flatten)]
+        display: PathDisplay,
+
+        /// The number of threads to search with.
+        ///
+        /// Defaults to the available parallelism.
+        #[arg(long, value_name = "N")]
+        threads: Option<NonZeroUsize>,
+
+        /// Display how many of the found paths each queried tag contains
+        /// instead of the paths, most first.
+        ///
+        /// The counts are written in the chosen format, after filtering the
+        /// paths found.
+        #[arg(
+            long,
+            conflicts_with_all = ["json_lines", "template", "relative", "sort", "max_results"]
+        )]
+        count_by_tag: bool,
+    },
+
+    /// Ranks paths by how many of the given tags contain them.
+    ///
+    /// Each path contained in any of the given tags is displayed after the
+    /// number of those tags containing it, most shared first.
+    Popular {
+        /// The tags to rank paths from.
         #[arg(required = true)]
         tags: Vec<String>,
     },
@@ -50,195 +406,2305 @@ enum Subcommand {
         /// On most Unix platforms, the separator is `:` and on Windows it is
         /// `;`.
         paths: Paths,
+
+        /// Include the tags of the directories containing the paths.
+        #[arg(short, long)]
+        directory_defaults: bool,
+
+        /// Display the names the tags are stored as, matching them regardless of
+        /// case.
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Display the tags as listed even if configured to ignore case.
+        #[arg(long, conflicts_with = "ignore_case")]
+        case_sensitive: bool,
+
+        /// Follow at most this many levels of inherited tags.
+        ///
+        /// With `0`, only the tags the paths list themselves are displayed.
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+
+        #[command(flatten)]
+        display: TagDisplay,
     },
 
     /// Tag paths.
     ///
     /// Adds tags to the given paths.
     Tag {
-        /// The paths to tag.
+        /// The paths to tag followed by the tags to add to them.
+        ///
+        /// On most Unix platforms, the path separator is `:` and on Windows it
+        /// is `;`. With `-` instead of paths, they are read from the standard
+        /// input, one per line or NUL-terminated. With `--from`, every argument
+        /// is a tag to add instead.
+        #[arg(value_name = "PATHS> <TAGS", required = true)]
+        args: Vec<String>,
+
+        /// Tag the paths of this tag, including those of its includes, instead
+        /// of given paths.
+        #[arg(long, value_name = "TAG")]
+        from: Option<String>,
+
+        /// Tag everything under the given directories too.
+        ///
+        /// What the `.tagignore` files in each directory and below it exclude
+        /// is skipped, much like `.gitignore` files, and so are metadata files.
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Tag paths excluded by `.tagignore` files too.
+        #[arg(long, requires = "recursive")]
+        no_ignore: bool,
+
+        /// Only update the tag files, leaving path metadata untouched.
+        #[arg(long)]
+        no_metadata: bool,
+    },
+
+    /// Untag paths.
+    ///
+    /// Removes tags from the given paths.
+    Untag {
+        /// The paths to untag followed by the tags to remove from them.
+        ///
+        /// On most Unix platforms, the path separator is `:` and on Windows it
+        /// is `;`. With `-` instead of paths, they are read from the standard
+        /// input, one per line or NUL-terminated. With `--from-query`, every
+        /// argument is a tag to remove instead.
+        #[arg(value_name = "PATHS> <TAGS", required = true)]
+        args: Vec<String>,
+
+        /// Untag the paths matching a query, such as `published`, instead of
+        /// given paths.
+        #[arg(long, value_name = "EXPR")]
+        from_query: Option<Query>,
+
+        /// Only update the tag files, leaving path metadata untouched.
+        #[arg(long)]
+        no_metadata: bool,
+    },
+
+    /// Rename a tag.
+    ///
+    /// Tags including or inherited by the tag and the metadata of its paths are
+    /// updated accordingly.
+    Rename {
+        /// The tag to rename.
+        from: String,
+
+        /// The new name of the tag.
+        to: String,
+
+        /// Fold the tag into the new name if it already exists.
+        ///
+        /// Otherwise, renaming onto an existing tag fails.
+        #[arg(long)]
+        merge: bool,
+    },
+
+    /// Move the paths of a tag onto another.
+    ///
+    /// The includes and inherits of both tags are kept, unlike renaming; only
+    /// the paths and their metadata change.
+    Retag {
+        /// The tag to take the paths from.
+        from: String,
+
+        /// The tag to give the paths to.
+        to: String,
+    },
+
+    /// Make a tag include another.
+    ///
+    /// The paths of the included tag then belong to the including one too. The
+    /// included tag must exist and must not already reach the including one.
+    Link {
+        /// The tag to include into.
+        tag: String,
+
+        /// The tag to include.
+        include: String,
+
+        /// Also add the including tag to the metadata of every path of the
+        /// included one, including those of its includes.
+        #[arg(long)]
+        apply_to_existing: bool,
+    },
+
+    /// Detach paths from every tag.
+    ///
+    /// Removes the paths from all tags, including ones their metadata doesn't
+    /// list, and clears their metadata.
+    Detach {
+        /// The paths to detach.
         ///
         /// On most Unix platforms, the separator is `:` and on Windows it is
         /// `;`.
         paths: Paths,
+    },
 
-        /// The tags to add to the given paths.
+    /// Create tags without adding paths.
+    ///
+    /// Touched tags are kept even while they have no paths nor includes.
+    Touch {
+        /// The tags to create.
         #[arg(required = true)]
         tags: Vec<String>,
     },
 
-    /// Untag paths.
-    ///
-    /// Removes tags from the given paths.
-    Untag {
-        /// The paths to untag.
-        ///
-        /// On most Unix platforms, the separator is `:` and on Windows it is
-        /// `;`.
-        paths: Paths,
+    /// Clear all the tags for the given paths.
+    Clear {
+        /// The paths to clear tags.
+        ///
+        /// On most Unix platforms, the separator is `:` and on Windows it is
+        /// `;`.
+        paths: Paths,
+
+        /// Display the tags each path would lose and the paths each tag would
+        /// lose, without clearing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Checks whether a tag exists or whether any path has all the given tags.
+    ///
+    /// Displays nothing; exits with 0 when found, 1 when not found, and 2 on
+    /// errors. Use `--verbose` for details.
+    Exists {
+        /// The tag whose file must exist.
+        #[arg(long, conflicts_with = "query", required_unless_present = "query")]
+        tag: Option<String>,
+
+        /// The tags that some path must all have.
+        #[arg(long, num_args = 1..)]
+        query: Vec<String>,
+    },
+
+    /// Removes orphaned path metadata under a directory.
+    ///
+    /// Metadata files whose path no longer exists are displayed and removed.
+    /// Warns about remaining metadata listing tags that don't contain its path.
+    CleanSidecars {
+        /// The directory to search metadata files in.
+        dir: PathBuf,
+
+        /// Display orphaned metadata files without removing them.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Search paths excluded by `.tagignore` files too.
+        ///
+        /// Otherwise, what the `.tagignore` files in the directory and below
+        /// it exclude is skipped, much like `.gitignore` files.
+        #[arg(long)]
+        no_ignore: bool,
+    },
+
+    /// Removes paths that no longer exist from every tag.
+    ///
+    /// Each removed path is displayed after the tag it was removed from.
+    Gc {
+        /// Only consider the paths under this directory, keeping all others.
+        ///
+        /// Paths excluded by the `.tagignore` files in the directory and below
+        /// it are kept too.
+        #[arg(long, value_name = "DIR")]
+        under: Option<PathBuf>,
+
+        /// Display the missing paths without removing them.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Consider paths excluded by `.tagignore` files too.
+        #[arg(long, requires = "under")]
+        no_ignore: bool,
+    },
+
+    /// Fold the operation logs of tags into their files.
+    ///
+    /// Logs are written when the store is configured as append-only.
+    Compact {
+        /// The tags to compact; all of them if none are given.
+        tags: Vec<String>,
+    },
+
+    /// Rewrite every tag of the store in another format.
+    ///
+    /// Each tag is written to its new file before the old one is removed, and
+    /// each conversion is displayed. Tag files that are symbolic links to
+    /// others are linked to their converted files instead. Set `store_format`
+    /// in the configuration to keep saving tags in the new format.
+    Convert {
+        /// The format to convert to: `json` or `toml`.
+        #[arg(long)]
+        to: TagFormat,
+
+        /// Display the conversions without making them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Merge tags whose names differ only by case.
+    ///
+    /// Each group of such tags is merged into the name that is already case
+    /// folded, such as `rust` for `Rust` and `RUST`, or else the first in
+    /// sorted order. References and path metadata are updated accordingly, and
+    /// each merge is displayed.
+    DedupCase {
+        /// Display the merges without making them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Explain why a tag contains a path.
+    ///
+    /// Displays the chain of includes from the tag to the one declaring the
+    /// path; exits with 1 if the tag doesn't contain it.
+    Why {
+        /// The tag to start from.
+        tag: String,
+
+        /// The path to look for.
+        path: PathBuf,
+    },
+
+    /// Display figures about the tags.
+    ///
+    /// Counts the tags loaded resolving the given tags, their edges and paths,
+    /// and displays their references to missing tags.
+    Stats {
+        /// The tags to start from; all of them if none are given.
+        tags: Vec<String>,
+    },
+
+    /// Display the details of a tag.
+    ///
+    /// Shows its direct and recursive path counts, what it includes and
+    /// inherits, which tags refer to it and whether it is part of a cycle.
+    Info {
+        /// The tag to describe.
+        tag: String,
+
+        /// Display the details as a JSON object.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Verify the checksums of every tag in the store.
+    ///
+    /// Displays the tags that fail to load or whose checksum doesn't match;
+    /// exits with 1 if there are any. Tags without checksums are only parsed.
+    Verify,
+
+    /// Check the store for references to missing tags.
+    ///
+    /// Displays each include or inherit naming a tag that doesn't exist;
+    /// exits with 1 if there are any.
+    Doctor,
+
+    /// Check that the store works end to end.
+    ///
+    /// Tags a temporary file with a temporary tag, queries it, untags it and
+    /// cleans up, reporting the first step that fails; exits with 1 if any.
+    SelfTest,
+
+    /// Time operations on a synthetic store.
+    ///
+    /// Builds a temporary store configured like this one, with the given
+    /// numbers of tags and files, then displays how long resolving, getting,
+    /// listing and collecting take, and cleans up.
+    #[command(hide = true)]
+    Benchmark {
+        /// The number of tags to create.
+        #[arg(long, default_value_t = 100)]
+        tags: usize,
+
+        /// The number of files to tag, each with two of the tags.
+        #[arg(long, default_value_t = 1000)]
+        paths: usize,
+
+        /// Keep the tags in memory instead of saving them to the temporary
+        /// store, to tell the time spent on tag files apart.
+        #[arg(long)]
+        in_memory: bool,
+    },
+}
+
+impl Arguments {
+    /// Sets up the stores from the configuration files and command line,
+    /// along with the configuration of the first store.
+    fn stores(&self) -> Option<(Vec<Store>, Config)> {
+        if self.store.is_empty() {
+            let store = match Store::beside_exe() {
+                Ok(store) => store,
+                Err(cause) => {
+                    log::error!("Unable to access this executable's directory: {cause}");
+                    return None;
+                }
+            };
+            let (store, config) = self.configure(store, None)?;
+            return Some((vec![store], config));
+        }
+        let mut stores = Vec::new();
+        let mut first = None;
+        for root in &self.store {
+            let (store, config) = self.configure(Store::new(root.clone()), Some(root))?;
+            stores.push(store);
+            first.get_or_insert(config);
+        }
+        Some((stores, first.unwrap_or_default()))
+    }
+
+    /// Applies the configuration file to `store`, unless disabled, keeping
+    /// the root given on the command line if any.
+    fn configure(&self, mut store: Store, root: Option<&PathBuf>) -> Option<(Store, Config)> {
+        let mut config = Config::default();
+        if !self.no_config {
+            match Config::find(&store) {
+                Ok(Some(found)) => {
+                    found.apply(&mut store);
+                    config = found;
+                }
+                Ok(None) => {}
+                Err(cause) => {
+                    log::error!("Unable to load configuration: {cause}");
+                    return None;
+                }
+            }
+        }
+        if let Some(root) = root {
+            store.set_root(root.clone());
+        }
+        Some((store, config))
+    }
+
+    fn level_filter(&self) -> log::LevelFilter {
+        if self.quiet {
+            return log::LevelFilter::Error;
+        }
+        match self.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+}
+
+impl Format {
+    fn writer<'a, W: Write + 'a>(self, inner: W) -> Box<dyn OutputWriter + 'a> {
+        match self {
+            Self::Lines => Box::new(LinesWriter(inner)),
+            Self::Nul => Box::new(NulWriter(inner)),
+            Self::Json => Box::new(JsonWriter {
+                inner,
+                started: false,
+            }),
+        }
+    }
+}
+
+impl Defaults {
+    /// Takes the defaults from `config` but for those given as options;
+    /// `None` if a configured value is invalid.
+    fn new(args: &Arguments, config: &Config) -> Option<Self> {
+        fn parse<T: clap::ValueEnum>(key: &str, value: Option<&String>) -> Option<T> {
+            let value = value?;
+            let parsed = T::from_str(value, true);
+            if let Err(cause) = &parsed {
+                log::error!("Invalid {key} {value:?} in the configuration: {cause}");
+            }
+            parsed.ok()
+        }
+
+        let format = match config.format() {
+            Some(_) => parse("format", config.format().as_ref())?,
+            None => Format::default(),
+        };
+        let color = match (args.color, config.color()) {
+            (Some(color), _) => color,
+            (None, Some(_)) => parse("color", config.color().as_ref())?,
+            (None, None) => ColorChoice::default(),
+        };
+        Some(Self {
+            format,
+            ignore_case: config.ignore_case().unwrap_or(false),
+            color,
+        })
+    }
+}
+
+impl PathDisplay {
+    fn write(self, mut paths: HashSet<PathBuf>) -> Result<(), Reported> {
+        self.filter(&mut paths, &mut HashSet::new());
+        let base = self.base()?;
+        let relativize = |path: PathBuf| relativize(base.as_deref(), path);
+
+        let total = paths.len();
+        let max = self.max_results.unwrap_or(usize::MAX);
+        let paths = match self.sort {
+            SortKey::Name => {
+                let paths = paths.into_iter().map(relativize);
+                if self.max_results.is_some() {
+                    smallest(paths, max)
+                } else {
+                    paths.sorted().collect()
+                }
+            }
+            SortKey::None => paths.into_iter().take(max).map(relativize).collect(),
+            SortKey::Mtime | SortKey::Size => {
+                let keyed = paths.into_iter().map(|path| {
+                    let key = std::fs::metadata(&path).and_then(|metadata| {
+                        Ok(match self.sort {
+                            SortKey::Mtime => metadata
+                                .modified()?
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_nanos(),
+                            _ => u128::from(metadata.len()),
+                        })
+                    });
+                    match key {
+                        Ok(key) => (false, key, path),
+                        Err(cause) => {
+                            log::warn!("Unable to inspect {}: {cause}", path.display());
+                            (true, 0, path)
+                        }
+                    }
+                });
+                let keyed = if self.max_results.is_some() {
+                    smallest(keyed, max)
+                } else {
+                    keyed.sorted().collect()
+                };
+                keyed
+                    .into_iter()
+                    .map(|(_, _, path)| relativize(path))
+                    .collect()
+            }
+        };
+        let omitted = total - paths.len();
+        let result = match &self.template {
+            Some(template) => write_output(
+                self.format.unwrap_or_default(),
+                self.output.as_deref(),
+                paths.iter().map(|path| template.render(path)),
+            ),
+            None => write_output(
+                self.format.unwrap_or_default(),
+                self.output.as_deref(),
+                paths.iter().map(|path| path.display()),
+            ),
+        };
+        if omitted > 0 {
+            eprintln!("... {omitted} more path(s) omitted");
+        }
+        result
+    }
+
+    /// Writes each of the paths found in every batch of `found` as a JSON
+    /// line as soon as its batch is, skipping those already written.
+    fn stream(
+        self,
+        found: impl IntoIterator<Item = Result<HashSet<PathBuf>, Reported>>,
+    ) -> Result<(), Reported> {
+        let base = self.base()?;
+        let max = self.max_results.unwrap_or(usize::MAX);
+        let mut seen = HashSet::new();
+        let mut inodes = HashSet::new();
+        let mut written = 0;
+        let mut omitted = 0;
+        let mut failed = false;
+        let result = with_output(self.output.as_deref(), |writer| {
+            for batch in found {
+                let Ok(mut batch) = batch else {
+                    failed = true;
+                    return Err(io::Error::other("search failed"));
+                };
+                batch.retain(|path| seen.insert(path.clone()));
+                self.filter(&mut batch, &mut inodes);
+                for path in batch {
+                    if written == max {
+                        omitted += 1;
+                        continue;
+                    }
+                    let path = relativize(base.as_deref(), path);
+                    serde_json::to_writer(
+                        &mut *writer,
+                        &serde_json::json!({ "path": path.to_string_lossy() }),
+                    )?;
+                    writer.write_all(b"\n")?;
+                    writer.flush()?;
+                    written += 1;
+                }
+            }
+            Ok(())
+        });
+        if omitted > 0 {
+            eprintln!("... {omitted} more path(s) omitted");
+        }
+        match result {
+            Err(_) if failed => Err(Reported),
+            result => result.map_err(|cause| {
+                log::error!("Unable to write results: {cause}");
+                Reported
+            }),
+        }
+    }
+
+    /// Drops the paths excluded by the filters, deduplicating by inode
+    /// against those in `inodes` as well.
+    fn filter(&self, paths: &mut HashSet<PathBuf>, inodes: &mut HashSet<(u64, u64)>) {
+        if self.existing_only {
+            paths.retain(|path| {
+                let exists = path.exists();
+                if !exists {
+                    log::debug!("Skipping missing path {}", path.display());
+                }
+                exists
+            });
+        }
+        if self.files_only {
+            paths.retain(|path| path.is_file());
+        } else if self.dirs_only {
+            paths.retain(|path| path.is_dir());
+        }
+        if let Some(DedupKey::Inode) = self.dedup_by {
+            *paths = dedup_by_inode(std::mem::take(paths), self.drop_missing, inodes);
+        }
+    }
+
+    /// The absolute directory paths are written relative to, if any.
+    fn base(&self) -> Result<Option<PathBuf>, Reported> {
+        match self.relative.as_deref().map(std::path::absolute) {
+            Some(Ok(base)) => Ok(Some(base)),
+            Some(Err(cause)) => {
+                log::error!("Unable to resolve the base directory: {cause}");
+                Err(Reported)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Template {
+    fn render(&self, path: &Path) -> String {
+        let mut rendered = String::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Literal(literal) => rendered.push_str(literal),
+                Piece::Path => rendered.push_str(&path.to_string_lossy()),
+                Piece::Basename => {
+                    rendered.push_str(&path.file_name().unwrap_or_default().to_string_lossy());
+                }
+                Piece::Dir => {
+                    rendered.push_str(&path.parent().map(Path::to_string_lossy).unwrap_or_default())
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err("unclosed `{`".to_owned());
+                    };
+                    let piece = match &rest[..end] {
+                        "path" => Piece::Path,
+                        "basename" => Piece::Basename,
+                        "dir" => Piece::Dir,
+                        name => return Err(format!("unknown placeholder {{{name}}}")),
+                    };
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(piece);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err("unmatched `}`, write `}}` for a brace".to_owned()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Self(pieces))
+    }
+}
+
+impl<W: Write> OutputWriter for LinesWriter<W> {
+    #[inline]
+    fn write(&mut self, item: &str) -> io::Result<()> {
+        writeln!(self.0, "{item}")
+    }
+
+    #[inline]
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> OutputWriter for NulWriter<W> {
+    #[inline]
+    fn write(&mut self, item: &str) -> io::Result<()> {
+        write!(self.0, "{item}\0")
+    }
+
+    #[inline]
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> OutputWriter for JsonWriter<W> {
+    fn write(&mut self, item: &str) -> io::Result<()> {
+        self.inner
+            .write_all(if self.started { b"," } else { b"[" })?;
+        self.started = true;
+        serde_json::to_writer(&mut self.inner, item)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.inner.write_all(b"[")?;
+        }
+        self.inner.write_all(b"]\n")?;
+        self.inner.flush()
+    }
+}
+
+impl log::Log for Logger {
+    #[inline]
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    #[inline]
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if self.color.load(Ordering::Relaxed) {
+            let code = match record.level() {
+                log::Level::Error => 31,
+                log::Level::Warn => 33,
+                log::Level::Info => 32,
+                log::Level::Debug => 34,
+                log::Level::Trace => 35,
+            };
+            eprintln!("\x1b[{code}m[{}]\x1b[0m {}", record.level(), record.args());
+        } else {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    #[inline]
+    fn flush(&self) {}
+}
+
+impl Subcommand {
+    fn execute(mut self, stores: &[Store], globals: Globals) -> ExitCode {
+        let [store, ..] = stores else {
+            return ExitCode::from(2);
+        };
+        if stores.len() > 1 && !matches!(self, Self::Get { .. }) {
+            log::error!("Only the get command supports multiple stores");
+            return ExitCode::from(2);
+        }
+        if globals.follow_symlinks {
+            match &mut self {
+                Self::List { paths, .. } | Self::Detach { paths } | Self::Clear { paths, .. } => {
+                    paths.follow_symlinks();
+                }
+                Self::Why { path, .. } => *path = follow_symlinks(path),
+                _ => {}
+            }
+        }
+        self.transform_names(store);
+
+        let strict = globals.strict;
+        let result = match self {
+            Self::Get {
+                tags,
+                query,
+                ignore_case,
+                case_sensitive,
+                mut display,
+                threads,
+                count_by_tag,
+            } => {
+                display.format.get_or_insert(globals.format);
+                Self::execute_get(
+                    stores,
+                    tags,
+                    query,
+                    ignore_case || (globals.ignore_case && !case_sensitive),
+                    display,
+                    threads,
+                    count_by_tag,
+                )
+            }
+            Self::Popular { tags } => Self::execute_popular(store, tags),
+            Self::List {
+                paths,
+                directory_defaults,
+                ignore_case,
+                case_sensitive,
+                depth,
+                mut display,
+            } => {
+                display.format.get_or_insert(globals.format);
+                Self::execute_list(
+                    store,
+                    paths,
+                    directory_defaults,
+                    ignore_case || (globals.ignore_case && !case_sensitive),
+                    depth,
+                    display,
+                    strict,
+                )
+            }
+            Self::Tag {
+                args,
+                from,
+                recursive,
+                no_ignore,
+                no_metadata,
+            } => {
+                let Some((mut paths, tags)) =
+                    Self::tag_args(store, args, from.map(Query::Tag), globals)
+                else {
+                    return ExitCode::from(2);
+                };
+                if recursive {
+                    if let Err((dir, cause)) = paths.recurse(store, !no_ignore) {
+                        log::error!("Unable to walk {}: {cause}", dir.display());
+                        return ExitCode::from(2);
+                    }
+                }
+                Self::execute_tag(store, paths, tags, no_metadata, strict)
+            }
+            Self::Untag {
+                args,
+                from_query,
+                no_metadata,
+            } => {
+                let Some((paths, tags)) = Self::tag_args(store, args, from_query, globals) else {
+                    return ExitCode::from(2);
+                };
+                Self::execute_untag(store, paths, tags, no_metadata, strict)
+            }
+            Self::Rename { from, to, merge } => Self::execute_rename(store, &from, &to, merge),
+            Self::Retag { from, to } => Self::execute_retag(store, &from, &to, globals),
+            Self::Link {
+                tag,
+                include,
+                apply_to_existing,
+            } => Self::execute_link(store, &tag, include, apply_to_existing, strict),
+            Self::Detach { paths } => Self::execute_detach(store, paths, globals),
+            Self::Touch { tags } => Self::execute_touch(store, tags, strict),
+            Self::Clear { paths, dry_run } => Self::execute_clear(store, paths, dry_run, globals),
+            Self::Exists { tag, query } => return Self::execute_exists(store, tag, query),
+            Self::Compact { tags } => Self::execute_compact(store, tags),
+            Self::Convert { to, dry_run } => Self::execute_convert(store, to, dry_run),
+            Self::DedupCase { dry_run } => Self::execute_dedup_case(store, dry_run, globals),
+            Self::Stats { tags } => Self::execute_stats(store, tags),
+            Self::Why { tag, path } => return Self::execute_why(store, &tag, &path),
+            Self::Gc {
+                under,
+                dry_run,
+                no_ignore,
+            } => Self::execute_gc(store, under.as_deref(), dry_run, !no_ignore, globals),
+            Self::Info { tag, json } => return Self::execute_info(store, &tag, json),
+            Self::Verify => return Self::execute_verify(store),
+            Self::Doctor => return Self::execute_doctor(store),
+            Self::SelfTest => return Self::execute_self_test(store),
+            Self::Benchmark {
+                tags,
+                paths,
+                in_memory,
+            } => return Self::execute_benchmark(store, tags, paths, in_memory),
+            Self::CleanSidecars {
+                dir,
+                dry_run,
+                no_ignore,
+            } => Self::execute_clean_sidecars(store, &dir, dry_run, !no_ignore, globals),
+        };
+        match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(Reported) => ExitCode::FAILURE,
+        }
+    }
+
+    /// Normalizes the given tag names with the name transform of `store`.
+    ///
+    /// The tags given to the tag and untag commands are mixed with their
+    /// paths, so they are normalized once separated.
+    fn transform_names(&mut self, store: &Store) {
+        let transform = |name: &mut String| *name = store.transform_name(name);
+        match self {
+            Self::Get { tags, query, .. } => {
+                tags.iter_mut().for_each(transform);
+                if let Some(query) = query {
+                    transform_query(query, store);
+                }
+            }
+            Self::Popular { tags }
+            | Self::Touch { tags }
+            | Self::Stats { tags }
+            | Self::Compact { tags } => {
+                tags.iter_mut().for_each(transform);
+            }
+            Self::Tag { from, .. } => from.iter_mut().for_each(transform),
+            Self::Untag { from_query, .. } => {
+                if let Some(query) = from_query {
+                    transform_query(query, store);
+                }
+            }
+            Self::Rename { from, to, .. } | Self::Retag { from, to } => {
+                transform(from);
+                transform(to);
+            }
+            Self::Exists { tag, query } => {
+                tag.iter_mut().for_each(transform);
+                query.iter_mut().for_each(transform);
+            }
+            Self::Info { tag, .. } | Self::Why { tag, .. } => transform(tag),
+            Self::Link { tag, include, .. } => {
+                transform(tag);
+                transform(include);
+            }
+            Self::List { .. }
+            | Self::Detach { .. }
+            | Self::Clear { .. }
+            | Self::CleanSidecars { .. }
+            | Self::Gc { .. }
+            | Self::DedupCase { .. }
+            | Self::Convert { .. }
+            | Self::Verify
+            | Self::Doctor
+            | Self::SelfTest
+            | Self::Benchmark { .. } => {}
+        }
+    }
+
+    fn execute_get(
+        stores: &[Store],
+        tags: Vec<String>,
+        query: Option<Query>,
+        ignore_case: bool,
+        display: PathDisplay,
+        threads: Option<NonZeroUsize>,
+        count_by_tag: bool,
+    ) -> Result<(), Reported> {
+        let threads = threads
+            .or_else(|| std::thread::available_parallelism().ok())
+            .unwrap_or(NonZeroUsize::MIN);
+        let search = |store: &Store| {
+            let query = if ignore_case {
+                let names = store_names(store)?;
+                query
+                    .clone()
+                    .or_else(|| {
+                        let tags = tags.iter().cloned().map(Query::Tag);
+                        tags.reduce(|lhs, rhs| Query::And(Box::new(lhs), Box::new(rhs)))
+                    })
+                    .map(|query| fold_case(&query, &names))
+            } else {
+                query.clone()
+            };
+            let raw = match &query {
+                Some(query) => query.to_raw(),
+                None => RawTag::query(HashSet::from_iter(tags.iter().cloned())),
+            };
+            let resolved = ResolvedTags::resolve_in(raw, store).map_err(|cause| {
+                log::error!("Unable to search by tag: {cause}");
+                Reported
+            })?;
+            let found = match &query {
+                Some(query) => query.evaluate(&resolved),
+                None => resolved.intersection_parallel(threads),
+            };
+            Ok((query, resolved, found))
+        };
+        if display.json_lines {
+            return display.stream(
+                stores
+                    .iter()
+                    .map(|store| search(store).map(|(_, _, found)| found)),
+            );
+        }
+
+        let mut paths = HashSet::new();
+        let mut tag_paths = HashMap::<String, HashSet<PathBuf>>::new();
+        for store in stores {
+            let (query, resolved, found) = search(store)?;
+            paths.extend(found);
+            if count_by_tag {
+                let names = match &query {
+                    Some(query) => query.tags(),
+                    None => tags.iter().cloned().collect(),
+                };
+                for name in names {
+                    let union = (resolved.tags().get(&name))
+                        .map(|tag| ResolvedTags::union_at(resolved.tags(), tag));
+                    tag_paths
+                        .entry(name)
+                        .or_default()
+                        .extend(union.unwrap_or_default());
+                }
+            }
+        }
+
+        if count_by_tag {
+            display.filter(&mut paths, &mut HashSet::new());
+            let counts = tag_paths
+                .into_iter()
+                .map(|(name, tag)| {
+                    (
+                        tag.iter().filter(|path| paths.contains(*path)).count(),
+                        name,
+                    )
+                })
+                .sorted_by(|(lhs_count, lhs), (rhs_count, rhs)| {
+                    rhs_count.cmp(lhs_count).then_with(|| lhs.cmp(rhs))
+                });
+            return write_output(
+                display.format.unwrap_or_default(),
+                display.output.as_deref(),
+                counts.map(|(count, name)| format!("{name}\t{count}")),
+            );
+        }
+        display.write(paths)
+    }
+
+    fn execute_popular(store: &Store, query: Vec<String>) -> Result<(), Reported> {
+        let tags = ResolvedTags::from_names_in(query, store).map_err(|cause| {
+            log::error!("Unable to rank paths by tag: {cause}");
+            Reported
+        })?;
+        for (path, count) in tags.most_common_paths() {
+            println!("{count}\t{}", path.display());
+        }
+        Ok(())
+    }
+
+    fn execute_list(
+        store: &Store,
+        paths: Paths,
+        directory_defaults: bool,
+        ignore_case: bool,
+        depth: Option<usize>,
+        display: TagDisplay,
+        strict: bool,
+    ) -> Result<(), Reported> {
+        let expand = |tags: &ResolvedTags| match depth {
+            Some(depth) => tags.all_tags_within(depth),
+            None => tags.all_tags(),
+        };
+        let names = if ignore_case {
+            Some(store_names(store)?)
+        } else {
+            None
+        };
+        let mut metas = Vec::new();
+        for path in paths {
+            let meta = if directory_defaults {
+                load_inherited_meta(&path, strict)?
+            } else {
+                load_meta(&path, strict)?
+            };
+            let Some(mut meta) = meta else {
+                continue;
+            };
+            if let Some(names) = &names {
+                *meta.tags_mut() = (meta.tags().iter())
+                    .flat_map(|tag| matching_names(names, tag))
+                    .collect();
+            }
+            metas.push((path, meta));
+        }
+
+        if display.grouped {
+            let mut failed = false;
+            let result = with_output(display.output.as_deref(), |out| {
+                for (path, meta) in metas {
+                    match ResolvedTags::from_names_in(meta.tags().clone(), store) {
+                        Ok(tag) => {
+                            writeln!(out, "{}", path.display())?;
+                            for tag in expand(&tag).into_iter().sorted() {
+                                writeln!(out, "\t{tag}")?;
+                            }
+                        }
+                        Err(cause) => {
+                            failed = true;
+                            log::error!("Unable list tags of path {}: {cause}", path.display());
+                        }
+                    }
+                }
+                Ok(())
+            });
+            if let Err(cause) = result {
+                log::error!("Unable to write results: {cause}");
+                return Err(Reported);
+            }
+            return if failed { Err(Reported) } else { Ok(()) };
+        }
+
+        let tags = metas.into_iter().flat_map(|(_, meta)| meta.tags().clone());
+        let tag =
+            ResolvedTags::resolve_in(RawTag::query(tags.collect()), store).map_err(|cause| {
+                log::error!("Unable list tags: {cause}");
+                Reported
+            })?;
+        let mut tags = Vec::from_iter(expand(&tag));
+        tags.sort();
+        write_output(
+            display.format.unwrap_or_default(),
+            display.output.as_deref(),
+            tags,
+        )
+    }
+
+    /// Splits the arguments of the tag and untag commands into paths and
+    /// normalized tags, taking the paths matching `from` instead if any.
+    fn tag_args(
+        store: &Store,
+        mut args: Vec<String>,
+        from: Option<Query>,
+        globals: Globals,
+    ) -> Option<(Paths, Vec<String>)> {
+        let mut paths = if let Some(from) = from {
+            match ResolvedTags::resolve_in(from.to_raw(), store) {
+                Ok(tags) => Paths::from_iter(from.evaluate(&tags)),
+                Err(cause) => {
+                    log::error!("Unable to search by tag: {cause}");
+                    return None;
+                }
+            }
+        } else {
+            if args.len() < 2 {
+                log::error!("Expected paths followed by at least one tag");
+                return None;
+            }
+            let paths = args.remove(0);
+            let result = if paths == "-" {
+                Paths::from_reader(io::stdin().lock()).map_err(|cause| {
+                    log::error!("Unable to read paths from the standard input: {cause}");
+                })
+            } else {
+                paths.parse().map_err(|cause| {
+                    log::error!("Unable to resolve paths {paths:?}: {cause}");
+                })
+            };
+            result.ok()?
+        };
+        if globals.follow_symlinks {
+            paths.follow_symlinks();
+        }
+        for tag in &mut args {
+            *tag = store.transform_name(tag);
+        }
+        Some((paths, args))
+    }
+
+    fn execute_tag(
+        store: &Store,
+        paths: Paths,
+        mut tags: Vec<String>,
+        no_metadata: bool,
+        strict: bool,
+    ) -> Result<(), Reported> {
+        for tag in store.default_tags().iter().sorted() {
+            let tag = store.transform_name(tag);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        let paths = paths.collect_vec();
+        let loaded = load_tags(store, &tags, strict)?;
+        let metas = if no_metadata {
+            Vec::new()
+        } else {
+            load_metas(paths.iter().cloned(), strict)?
+        };
+
+        for (key, mut tag) in loaded {
+            let len = tag.paths_len();
+            tag.paths_mut().extend(paths.iter().cloned());
+            if tag.paths_len() == len {
+                log::debug!("Tag {key:?} already contains every path");
+                continue;
+            }
+            save_tag(store, key, &tag);
+        }
+
+        let mut progress = Progress::new(metas.len());
+        for (path, mut meta) in metas {
+            progress.advance();
+            let len = meta.tags().len();
+            meta.tags_mut().extend(tags.iter().cloned());
+            if meta.tags().len() == len {
+                log::debug!("Path {} already has every tag", path.display());
+                continue;
+            }
+            save_meta(path, &meta);
+        }
+        Ok(())
+    }
+
+    fn execute_untag(
+        store: &Store,
+        paths: Paths,
+        tags: Vec<String>,
+        no_metadata: bool,
+        strict: bool,
+    ) -> Result<(), Reported> {
+        let paths = paths.collect_vec();
+        let loaded = load_tags(store, &tags, strict)?;
+        let metas = if no_metadata {
+            Vec::new()
+        } else {
+            load_metas(paths.iter().cloned(), strict)?
+        };
+
+        for (key, mut tag) in loaded {
+            let len = tag.paths_len();
+            for path in &paths {
+                tag.paths_mut().remove(path);
+            }
+            if tag.paths_len() == len {
+                log::debug!("Tag {key:?} contains none of the paths");
+                continue;
+            }
+            save_tag(store, key, &tag);
+        }
+
+        let mut progress = Progress::new(metas.len());
+        for (path, mut meta) in metas {
+            progress.advance();
+            let len = meta.tags().len();
+            for tag in &tags {
+                meta.tags_mut().remove(tag);
+            }
+            if meta.tags().len() == len {
+                log::debug!("Path {} has none of the tags", path.display());
+                continue;
+            }
+            save_meta(path, &meta);
+        }
+        Ok(())
+    }
+
+    fn execute_rename(store: &Store, from: &str, to: &str, merge: bool) -> Result<(), Reported> {
+        let result = if merge {
+            store.merge_tags(from, to)
+        } else {
+            store.rename_tag(from, to)
+        };
+        result.map_err(|cause| {
+            log::error!("Unable to rename tag {from:?} to {to:?}: {cause}");
+            Reported
+        })
+    }
+
+    fn execute_retag(
+        store: &Store,
+        from: &str,
+        to: &str,
+        globals: Globals,
+    ) -> Result<(), Reported> {
+        let summary = format!("Move every path of tag {from:?} to {to:?}?");
+        if !confirm(&summary, globals.assume_yes) {
+            return Ok(());
+        }
+        let moved = store.retag(from, to).map_err(|cause| {
+            log::error!("Unable to retag {from:?} as {to:?}: {cause}");
+            Reported
+        })?;
+        log::info!("Moved {moved} path(s) from tag {from:?} to {to:?}");
+        Ok(())
+    }
+
+    fn execute_link(
+        store: &Store,
+        key: &str,
+        include: String,
+        apply_to_existing: bool,
+        strict: bool,
+    ) -> Result<(), Reported> {
+        if key == include {
+            log::error!("Tag {key:?} can't include itself");
+            return Err(Reported);
+        }
+        let query = RawTag::query(HashSet::from([include.clone()]));
+        let included = match ResolvedTags::resolve_in(query, store) {
+            Ok(tags) => tags,
+            Err(cause) => {
+                log::error!("Unable to resolve the included tag: {cause}");
+                return Err(Reported);
+            }
+        };
+        if !included.tags().contains_key(&include) {
+            log::error!("Tag {include:?} doesn't exist");
+            return Err(Reported);
+        }
+        if included.closure().contains(key) {
+            log::error!(
+                "Tag {include:?} already reaches {key:?}, so including it would form a cycle"
+            );
+            return Err(Reported);
+        }
+
+        let Some(mut tag) = load_tag(store, key, strict)? else {
+            return Ok(());
+        };
+        let metas = if apply_to_existing {
+            load_metas(included.union().into_iter().sorted(), strict)?
+        } else {
+            Vec::new()
+        };
+
+        if tag.include_tags_mut().insert(include) {
+            save_tag(store, key, &tag);
+        } else {
+            log::debug!("Tag {key:?} already includes it");
+        }
+        let mut progress = Progress::new(metas.len());
+        for (path, mut meta) in metas {
+            progress.advance();
+            if meta.tags_mut().insert(key.to_owned()) {
+                save_meta(path, &meta);
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_detach(store: &Store, paths: Paths, globals: Globals) -> Result<(), Reported> {
+        let paths = paths.collect_vec();
+        let summary = format!("Detach {} path(s) from every tag?", paths.len());
+        if paths.is_empty() || !confirm(&summary, globals.assume_yes) {
+            return Ok(());
+        }
+        let mut failed = false;
+        for path in paths {
+            match store.remove_path_everywhere(&path) {
+                Ok(tags) => {
+                    for key in tags {
+                        log::info!("Detached path {} from tag {key:?}", path.display());
+                    }
+                }
+                Err(cause) => {
+                    failed = true;
+                    log::warn!("Unable to detach path {}: {cause}", path.display());
+                }
+            }
+        }
+        if failed {
+            Err(Reported)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn execute_touch(store: &Store, tags: Vec<String>, strict: bool) -> Result<(), Reported> {
+        for (key, mut tag) in load_tags(store, &tags, strict)? {
+            *tag.persistent_mut() = true;
+            save_tag(store, key, &tag);
+        }
+        Ok(())
+    }
+
+    fn execute_exists(store: &Store, tag: Option<String>, query: Vec<String>) -> ExitCode {
+        let found = if let Some(key) = tag {
+            let path = match store.resolve_checked(&key) {
+                Ok(path) => path,
+                Err(cause) => {
+                    log::error!("Unable to look up tag {key:?}: {cause}");
+                    return ExitCode::from(2);
+                }
+            };
+            let found = path.is_file();
+            log::info!("Tag {key:?} is expected at {}: {found}", path.display());
+            found
+        } else {
+            match ResolvedTags::from_names_in(query, store) {
+                Ok(tags) => {
+                    let count = tags.intersection().len();
+                    log::info!("{count} path(s) match the query");
+                    count > 0
+                }
+                Err(cause) => {
+                    log::error!("Unable to search by tag: {cause}");
+                    return ExitCode::from(2);
+                }
+            }
+        };
+        if found {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        }
+    }
+
+    fn execute_clear(
+        store: &Store,
+        paths: Paths,
+        dry_run: bool,
+        globals: Globals,
+    ) -> Result<(), Reported> {
+        let metas = load_metas(paths, globals.strict)?
+            .into_iter()
+            .map(|(path, meta)| (meta, path))
+            .collect_vec();
+        let tagged = metas.iter().filter(|(meta, _)| !meta.is_empty()).count();
+        if tagged == 0 {
+            return Ok(());
+        }
+        let tags = metas
+            .iter()
+            .flat_map(|(meta, _)| meta.tags().iter().cloned())
+            .collect();
+        let mut query = match ResolvedTags::resolve_in(RawTag::query(tags), store) {
+            Ok(query) => query,
+            Err(cause) => {
+                log::error!("Unable retrieve tag data for clearing: {cause}");
+                return Err(Reported);
+            }
+        };
+
+        if dry_run {
+            println!("paths:");
+            for (meta, path) in metas.iter().sorted_by(|(_, lhs), (_, rhs)| lhs.cmp(rhs)) {
+                if !meta.is_empty() {
+                    println!(
+                        "  {}: {}",
+                        path.display(),
+                        meta.tags_sorted().iter().join(", ")
+                    );
+                }
+            }
+            println!("tags:");
+            for key in query.raw().iter_includes().sorted() {
+                let Some(tag) = query.tags().get(key) else {
+                    continue;
+                };
+                let lost = (metas.iter())
+                    .filter(|(meta, path)| meta.tags().contains(key) && tag.paths().contains(path))
+                    .map(|(_, path)| path)
+                    .sorted()
+                    .map(|path| path.display())
+                    .join(", ");
+                if !lost.is_empty() {
+                    println!("  {key}: {lost}");
+                }
+            }
+            return Ok(());
+        }
+        if !confirm(
+            &format!("Clear the tags of {tagged} path(s)?"),
+            globals.assume_yes,
+        ) {
+            return Ok(());
+        }
+
+        for (mut meta, path) in metas {
+            for key in meta.clear() {
+                if let Some(tag) = query.tags_mut().get_mut(&key) {
+                    tag.paths_mut().remove(&path);
+                }
+            }
+            save_meta(path, &meta);
+        }
+
+        for key in query.raw().iter_includes() {
+            if let Some(tag) = query.tags().get(key) {
+                save_tag(store, key, tag);
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_verify(store: &Store) -> ExitCode {
+        let names = match store.names() {
+            Ok(names) => names,
+            Err(cause) => {
+                log::error!("Unable to list tags: {cause}");
+                return ExitCode::from(2);
+            }
+        };
+
+        let mut store = store.clone();
+        store.set_checksum(true);
+        let mut failed = false;
+        for name in names.into_iter().sorted() {
+            if let Err(cause) = store.load(&name) {
+                failed = true;
+                println!("{name}");
+                log::warn!("Unable to verify tag {name:?}: {cause}");
+            }
+        }
+        if failed {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+
+    fn execute_compact(store: &Store, tags: Vec<String>) -> Result<(), Reported> {
+        let tags = if tags.is_empty() {
+            store_names(store)?
+        } else {
+            tags
+        };
+        for key in tags.iter().sorted() {
+            match store.compact(key) {
+                Ok(true) => log::info!("Compacted tag {key:?}"),
+                Ok(false) => log::debug!("Tag {key:?} has no operation log"),
+                Err(cause) => log::warn!("Unable to compact tag {key:?}: {cause}"),
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_convert(store: &Store, to: TagFormat, dry_run: bool) -> Result<(), Reported> {
+        let mut failed = false;
+        for key in store_names(store)? {
+            let from = store.resolve(&key);
+            let into = store.resolve_as(&key, to);
+            if from == into {
+                continue;
+            }
+            println!("{}\t{}", from.display(), into.display());
+            if dry_run {
+                continue;
+            }
+            if let Err(cause) = store.convert(&key, to) {
+                failed = true;
+                log::error!("Unable to convert tag {key:?}: {cause}");
+            }
+        }
+        if *store.format() != to {
+            let to = to.extension();
+            log::warn!(
+                "Set store_format = {to:?} in the configuration to keep saving tags as {to}"
+            );
+        }
+        if failed {
+            Err(Reported)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn execute_dedup_case(store: &Store, dry_run: bool, globals: Globals) -> Result<(), Reported> {
+        let names = store_names(store)?;
+        let mut merges = Vec::new();
+        let groups = names
+            .into_iter()
+            .sorted()
+            .into_group_map_by(|name| case_fold(name));
+        for (folded, names) in groups.into_iter().sorted() {
+            if names.len() < 2 {
+                continue;
+            }
+            let canonical = (names.iter())
+                .find(|name| **name == folded)
+                .unwrap_or(&names[0]);
+            for name in names.iter().filter(|name| *name != canonical) {
+                println!("{name}\t{canonical}");
+                merges.push((name.clone(), canonical.clone()));
+            }
+        }
+
+        let summary = format!("Merge {} tag(s) into another case?", merges.len());
+        if dry_run || merges.is_empty() || !confirm(&summary, globals.assume_yes) {
+            return Ok(());
+        }
+        let mut failed = false;
+        for (name, canonical) in merges {
+            if let Err(cause) = store.merge_tags(&name, &canonical) {
+                failed = true;
+                log::error!("Unable to merge tag {name:?} into {canonical:?}: {cause}");
+            }
+        }
+        if failed {
+            Err(Reported)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn execute_why(store: &Store, key: &str, path: &Path) -> ExitCode {
+        let path = match std::path::absolute(path) {
+            Ok(path) => path,
+            Err(cause) => {
+                log::error!("Unable to resolve path {}: {cause}", path.display());
+                return ExitCode::from(2);
+            }
+        };
+        let query = RawTag::query(HashSet::from([key.to_owned()]));
+        let tags = match ResolvedTags::resolve_in(query, store) {
+            Ok(tags) => tags,
+            Err(cause) => {
+                log::error!("Unable to resolve tag {key:?}: {cause}");
+                return ExitCode::from(2);
+            }
+        };
+        match tags.reachable_from(key).get(&path) {
+            Some(chain) => {
+                println!("{}", chain.join(" -> "));
+                ExitCode::SUCCESS
+            }
+            None => {
+                log::info!("Tag {key:?} doesn't contain path {}", path.display());
+                ExitCode::FAILURE
+            }
+        }
+    }
+
+    fn execute_stats(store: &Store, tags: Vec<String>) -> Result<(), Reported> {
+        let tags = if tags.is_empty() {
+            store_names(store)?
+        } else {
+            tags
+        };
+        let stats = match ResolvedTags::from_names_in(tags, store) {
+            Ok(tags) => tags.stats(),
+            Err(cause) => {
+                log::error!("Unable to resolve tags: {cause}");
+                return Err(Reported);
+            }
+        };
+        println!("tags: {}", stats.tags());
+        println!("includes: {}", stats.include_edges());
+        println!("inherits: {}", stats.inherit_edges());
+        println!("max depth: {}", stats.max_depth());
+        println!("paths: {}", stats.paths());
+        println!("distinct paths: {}", stats.distinct_paths());
+        println!("broken references: {}", stats.broken_refs().len());
+        for broken in stats.broken_refs() {
+            println!("  {broken}");
+        }
+        Ok(())
+    }
 
-        /// The tags to remove from the given paths.
-        #[arg(required = true)]
-        tags: Vec<String>,
-    },
+    fn execute_gc(
+        store: &Store,
+        under: Option<&Path>,
+        dry_run: bool,
+        ignore: bool,
+        globals: Globals,
+    ) -> Result<(), Reported> {
+        let under = match under.map(std::path::absolute).transpose() {
+            Ok(under) => under,
+            Err(cause) => {
+                log::error!("Unable to resolve the directory to collect under: {cause}");
+                return Err(Reported);
+            }
+        };
+        let names = store_names(store)?.into_iter().sorted().collect_vec();
+        let mut ignore = (under.clone()).filter(|_| ignore).map(TagIgnore::new);
 
-    /// Clear all the tags for the given paths.
-    Clear {
-        /// The paths to clear tags.
-        ///
-        /// On most Unix platforms, the separator is `:` and on Windows it is
-        /// `;`.
-        paths: Paths,
-    },
-}
+        let mut pruned = Vec::new();
+        for (key, mut tag) in load_tags(store, &names, globals.strict)? {
+            let mut missing = Vec::new();
+            tag.retain_paths(|path| {
+                let considered = under.as_ref().is_none_or(|under| path.starts_with(under))
+                    && !(ignore.as_mut()).is_some_and(|ignore| ignore.is_ignored(path));
+                if considered && !path.exists() {
+                    missing.push(path.clone());
+                    return false;
+                }
+                true
+            });
+            if missing.is_empty() {
+                continue;
+            }
+            for path in missing.iter().sorted() {
+                println!("{key}\t{}", path.display());
+            }
+            pruned.push((key, tag, missing.len()));
+        }
 
-impl Subcommand {
-    fn execute(self) {
-        match self {
-            Self::Get { tags } => Self::execute_get(tags),
-            Self::List { paths } => Self::execute_list(paths),
-            Self::Tag { paths, tags } => Self::execute_tag(paths, tags),
-            Self::Untag { paths, tags } => Self::execute_untag(paths, tags),
-            Self::Clear { paths } => Self::execute_clear(paths),
+        let missing = pruned.iter().map(|(_, _, missing)| missing).sum::<usize>();
+        let summary = format!(
+            "Remove {missing} missing path(s) from {} tag(s)?",
+            pruned.len()
+        );
+        if !dry_run && !pruned.is_empty() && confirm(&summary, globals.assume_yes) {
+            for (key, tag, _) in pruned {
+                save_tag(store, key, &tag);
+            }
         }
+        Ok(())
     }
 
-    fn execute_get(query: Vec<String>) {
-        match ResolvedTags::try_from(RawTag::query(HashSet::from_iter(query))) {
-            Ok(paths) => {
-                let mut paths = Vec::from_iter(paths.intersection());
-                paths.sort();
-                for path in paths {
-                    println!("{}", path.display());
-                }
+    fn execute_info(store: &Store, key: &str, json: bool) -> ExitCode {
+        let tag = match store.load(key) {
+            Ok(tag) => tag,
+            Err(cause) => {
+                log::error!("Unable to load tag {key:?}: {cause}");
+                return ExitCode::FAILURE;
+            }
+        };
+        let referenced_by = match store.referencing(key) {
+            Ok(names) => names,
+            Err(cause) => {
+                log::error!("Unable to find the tags referring to {key:?}: {cause}");
+                return ExitCode::FAILURE;
+            }
+        };
+        let cyclic = match in_cycle(store, key) {
+            Ok(cyclic) => cyclic,
+            Err(cause) => {
+                log::error!("Unable to look for cycles through {key:?}: {cause}");
+                return ExitCode::FAILURE;
+            }
+        };
+        let recursive = match ResolvedTags::from_names_in([key.to_owned()], store) {
+            Ok(tags) => Some(tags.union().len()),
+            Err(ResolveError::Cyclic { .. }) => None,
+            Err(cause) => {
+                log::error!("Unable to resolve tag {key:?}: {cause}");
+                return ExitCode::FAILURE;
             }
-            Err(cause) => log::error!("Unable to search by tag: {cause}"),
         };
+
+        let includes = tag.sorted_include_tags();
+        let inherits = tag.sorted_inherited_tags();
+        if json {
+            let info = serde_json::json!({
+                "name": key,
+                "paths": tag.paths_len(),
+                "path_globs": tag.path_globs().iter().sorted().collect_vec(),
+                "includes": includes,
+                "inherits": inherits,
+                "recursive_paths": recursive,
+                "referenced_by": referenced_by,
+                "cyclic": cyclic,
+            });
+            println!("{info}");
+        } else {
+            println!("name: {key}");
+            println!("paths: {}", tag.paths_len());
+            if !tag.path_globs().is_empty() {
+                println!(
+                    "path globs: {}",
+                    tag.path_globs().iter().sorted().join(", ")
+                );
+            }
+            println!("includes: {}", includes.iter().join(", "));
+            println!("inherits: {}", inherits.iter().join(", "));
+            match recursive {
+                Some(count) => println!("recursive paths: {count}"),
+                None => println!("recursive paths: unknown, a cycle is reachable"),
+            }
+            println!("referenced by: {}", referenced_by.join(", "));
+            println!("cyclic: {}", if cyclic { "yes" } else { "no" });
+        }
+        ExitCode::SUCCESS
     }
 
-    fn execute_list(paths: Paths) {
-        let tags = paths
-            .filter_map(load_meta)
-            .flat_map(|meta| meta.tags().clone())
-            .collect();
-        match ResolvedTags::try_from(RawTag::query(tags)) {
-            Ok(tag) => {
-                let mut tags = Vec::from_iter(tag.all_tags());
-                tags.sort();
-                for tag in tags {
-                    println!("{tag}");
-                }
+    fn execute_doctor(store: &Store) -> ExitCode {
+        let names = match store.names() {
+            Ok(names) => names,
+            Err(cause) => {
+                log::error!("Unable to list tags: {cause}");
+                return ExitCode::from(2);
             }
-            Err(cause) => log::error!("Unable list tags: {cause}"),
         };
+
+        let broken = match ResolvedTags::from_names_in(names, store) {
+            Ok(tags) => tags.validate(),
+            Err(cause) => {
+                log::error!("Unable to resolve tags: {cause}");
+                return ExitCode::from(2);
+            }
+        };
+        for broken in &broken {
+            println!("{broken}");
+        }
+        if broken.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        }
     }
 
-    fn execute_tag(paths: Paths, tags: Vec<String>) {
-        for key in &tags {
-            let Some(mut tag) = load_tag(key) else {
-                continue;
-            };
-            tag.paths_mut().extend(paths.clone());
-            save_tag(key, &tag);
+    fn execute_self_test(store: &Store) -> ExitCode {
+        let name = format!("self-test-{}", std::process::id());
+        let file = std::env::temp_dir().join(format!("path-tagging-{name}"));
+        // the scratch tag is removed once empty regardless of the configuration
+        let mut store = store.clone();
+        store.set_keep_empty(false);
+        let result = Self::self_test(&store, &name, &file);
+
+        match store.remove(&name) {
+            Err(IoTagError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {}
+            Err(cause) => log::warn!("Unable to clean up tag {name:?}: {cause}"),
+            Ok(()) => {}
+        }
+        for path in [PathMetadata::resolve(&file), file] {
+            if let Err(cause) = std::fs::remove_file(&path) {
+                if cause.kind() != io::ErrorKind::NotFound {
+                    log::warn!("Unable to clean up {}: {cause}", path.display());
+                }
+            }
         }
 
-        for path in paths {
-            let Some(mut meta) = load_meta(&path) else {
-                continue;
-            };
-            meta.tags_mut().extend(tags.iter().cloned());
-            save_meta(path, &meta);
+        match result {
+            Ok(()) => {
+                println!("ok");
+                ExitCode::SUCCESS
+            }
+            Err((step, cause)) => {
+                log::error!("Self-test failed at step {step}: {cause}");
+                ExitCode::FAILURE
+            }
         }
     }
 
-    fn execute_untag(paths: Paths, tags: Vec<String>) {
-        for key in &tags {
-            let Some(mut tag) = load_tag(key) else {
-                continue;
-            };
-            for path in paths.clone() {
-                tag.paths_mut().remove(&path);
+    fn execute_benchmark(store: &Store, tags: usize, paths: usize, in_memory: bool) -> ExitCode {
+        let dir = std::env::temp_dir().join(format!("path-tagging-bench-{}", std::process::id()));
+        let mut bench = store.clone();
+        bench.set_root(dir.join("store"));
+        let result = Self::benchmark(&mut bench, &dir, tags.max(1), paths, in_memory);
+        if let Err(cause) = std::fs::remove_dir_all(&dir) {
+            log::warn!("Unable to clean up {}: {cause}", dir.display());
+        }
+        match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(cause) => {
+                log::error!("Benchmark failed: {cause}");
+                ExitCode::FAILURE
             }
-            save_tag(key, &tag);
+        }
+    }
+
+    fn benchmark(
+        store: &mut Store,
+        dir: &Path,
+        tags: usize,
+        paths: usize,
+        in_memory: bool,
+    ) -> Result<(), String> {
+        fn time<T>(step: &str, f: impl FnOnce() -> T) -> T {
+            let start = Instant::now();
+            let result = f();
+            println!("{step}\t{:?}", start.elapsed());
+            result
         }
 
-        for path in paths {
-            let Some(mut meta) = load_meta(&path) else {
-                continue;
-            };
-            for tag in &tags {
-                meta.tags_mut().remove(tag);
+        let files = dir.join("files");
+        std::fs::create_dir_all(&files).map_err(|cause| cause.to_string())?;
+        if !in_memory {
+            std::fs::create_dir_all(store.root()).map_err(|cause| cause.to_string())?;
+        }
+        let names = (0..tags).map(|i| format!("tag-{i}")).collect_vec();
+        time("setup", || {
+            let mut raws = vec![RawTag::default(); tags];
+            for i in 0..paths {
+                let path = files.join(format!("file-{i}"));
+                std::fs::write(&path, b"").map_err(|cause| cause.to_string())?;
+                let keys = [i % tags, (i * 7 + 3) % tags];
+                let meta = PathMetadata::new(keys.iter().map(|key| names[*key].clone()).collect());
+                meta.save(&path).map_err(|cause| cause.to_string())?;
+                for key in keys {
+                    raws[key].paths_mut().insert(path.clone());
+                }
             }
-            save_meta(path, &meta);
+            for (key, raw) in names.iter().zip(raws) {
+                if in_memory {
+                    *store.tag_mut(key).map_err(|cause| cause.to_string())? = raw;
+                } else {
+                    store.save(key, &raw).map_err(|cause| cause.to_string())?;
+                }
+            }
+            Ok::<_, String>(())
+        })?;
+
+        let query = RawTag::query(names.iter().cloned().collect());
+        let resolved = time("resolve", || ResolvedTags::resolve_in(query, store))
+            .map_err(|cause| cause.to_string())?;
+        time("get", || {
+            let query = Query::And(
+                Box::new(Query::Tag(names[0].clone())),
+                Box::new(Query::Tag(names[3 % tags].clone())),
+            );
+            query.evaluate(&resolved).len()
+        });
+        time("list", || {
+            for i in 0..paths {
+                let _ = load_meta(files.join(format!("file-{i}")), false);
+            }
+        });
+        time("gc", || {
+            Self::execute_gc(store, None, true, false, Globals::default())
+        })
+        .map_err(|Reported| "gc failed".to_owned())?;
+        Ok(())
+    }
+
+    fn self_test(store: &Store, name: &str, file: &Path) -> Result<(), (&'static str, String)> {
+        fn read_step(cause: &IoTagError) -> &'static str {
+            match cause {
+                IoTagError::Serde(_) => "parse",
+                _ => "read",
+            }
+        }
+
+        if !store.root().is_dir() {
+            let cause = format!("store {} is not a directory", store.root().display());
+            return Err(("resolve", cause));
+        }
+        log::info!("Using tag file {}", store.resolve(name).display());
+
+        std::fs::write(file, b"").map_err(|cause| ("write", cause.to_string()))?;
+        let mut tag = RawTag::default();
+        tag.paths_mut().insert(file.to_path_buf());
+        store
+            .save(name, &tag)
+            .map_err(|cause| ("write", cause.to_string()))?;
+        let mut meta = PathMetadata::default();
+        meta.tags_mut().insert(name.to_owned());
+        meta.save(file)
+            .map_err(|cause| ("write", cause.to_string()))?;
+        log::info!("Wrote tag {name:?} and metadata of {}", file.display());
+
+        let loaded = store
+            .load(name)
+            .map_err(|cause| (read_step(&cause), cause.to_string()))?;
+        if loaded != tag {
+            return Err(("read", "tag changed after saving".to_owned()));
+        }
+        let loaded = PathMetadata::load(file).map_err(|cause| ("read", cause.to_string()))?;
+        if loaded != meta {
+            return Err(("read", "metadata changed after saving".to_owned()));
         }
+        log::info!("Read back tag {name:?} and metadata of {}", file.display());
+
+        let query = RawTag::query(HashSet::from([name.to_owned()]));
+        let found = ResolvedTags::resolve_in(query, store)
+            .map_err(|cause| ("query", cause.to_string()))?
+            .intersection();
+        if !found.contains(file) {
+            return Err(("query", "tagged file wasn't found".to_owned()));
+        }
+        log::info!("Found {} by tag {name:?}", file.display());
+
+        tag.paths_mut().clear();
+        store
+            .save(name, &tag)
+            .map_err(|cause| ("untag", cause.to_string()))?;
+        meta.tags_mut().clear();
+        meta.save(file)
+            .map_err(|cause| ("untag", cause.to_string()))?;
+        if store.resolve(name).exists() || PathMetadata::resolve(file).exists() {
+            return Err(("untag", "empty tag files were left behind".to_owned()));
+        }
+        log::info!("Untagged {}", file.display());
+        Ok(())
     }
 
-    fn execute_clear(paths: Paths) {
-        let metas = paths
-            .filter_map(|path| Some((load_meta(&path)?, path)))
-            .collect_vec();
-        let tags = metas
-            .iter()
-            .flat_map(|(meta, _)| meta.tags().clone())
-            .collect();
-        let mut query = match ResolvedTags::try_from(RawTag::query(tags)) {
-            Ok(query) => query,
+    fn execute_clean_sidecars(
+        store: &Store,
+        dir: &Path,
+        dry_run: bool,
+        ignore: bool,
+        globals: Globals,
+    ) -> Result<(), Reported> {
+        let dir = match std::path::absolute(dir) {
+            Ok(dir) => dir,
             Err(cause) => {
-                log::error!("Unable retrieve tag data for clearing: {cause}");
-                return;
+                log::error!("Unable to resolve directory {}: {cause}", dir.display());
+                return Err(Reported);
             }
         };
 
-        for (mut meta, path) in metas {
-            for key in meta.tags_mut().drain() {
-                if let Some(tag) = query.tags_mut().get_mut(&key) {
-                    tag.paths_mut().remove(&path);
+        let mut walk = PathMetadata::load_all_under(&dir);
+        if ignore {
+            walk = walk.respecting_ignore();
+        }
+        let mut metas = Vec::new();
+        for result in walk {
+            match result {
+                Ok(meta) => metas.push(meta),
+                Err((path, cause)) => {
+                    load_failed(
+                        format_args!("Unable to read {}: {cause}", path.display()),
+                        globals.strict,
+                    )?;
                 }
             }
-            save_meta(path, &meta);
         }
+        metas.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+        let (orphans, metas): (Vec<_>, Vec<_>) =
+            metas.into_iter().partition(|(path, _)| !path.exists());
 
-        for key in query.raw().include_tags() {
-            if let Some(tag) = query.tags().get(key) {
-                save_tag(key, tag);
+        // A missing directory can't hold its metadata, so each was a file's
+        let orphans = orphans
+            .into_iter()
+            .map(|(path, _)| {
+                let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+                file_name.push(PathMetadata::EXTENSION_PREFIX);
+                path.with_file_name(file_name)
+            })
+            .collect_vec();
+        for sidecar in &orphans {
+            println!("{}", sidecar.display());
+        }
+
+        for (path, meta) in metas {
+            for key in meta.tags_sorted() {
+                let Some(tag) = load_tag(store, key, globals.strict)? else {
+                    continue;
+                };
+                if !tag.contains_path(&path) {
+                    log::warn!(
+                        "Path {} lists tag {key:?} which doesn't contain it",
+                        path.display()
+                    );
+                }
+            }
+        }
+
+        let summary = format!("Remove {} orphaned metadata file(s)?", orphans.len());
+        if !dry_run && !orphans.is_empty() && confirm(&summary, globals.assume_yes) {
+            for sidecar in &orphans {
+                if let Err(cause) = std::fs::remove_file(sidecar) {
+                    log::warn!("Unable to remove {}: {cause}", sidecar.display());
+                }
             }
         }
+        Ok(())
     }
 }
 
-fn load_meta<P: AsRef<Path>>(path: P) -> Option<PathMetadata> {
+fn write_output<T: Display>(
+    format: Format,
+    output: Option<&Path>,
+    items: impl IntoIterator<Item = T>,
+) -> Result<(), Reported> {
+    let result = with_output(output, |inner| {
+        let mut writer = format.writer(inner);
+        items
+            .into_iter()
+            .try_for_each(|item| writer.write(&item.to_string()))
+            .and_then(|()| writer.finish())
+    });
+    result.map_err(|cause| {
+        log::error!("Unable to write results: {cause}");
+        Reported
+    })
+}
+
+/// Resolves symbolic links in `path`, or else in its longest existing
+/// ancestor, keeping the rest of it.
+fn follow_symlinks(path: &Path) -> PathBuf {
+    let mut existing = path;
+    loop {
+        if let Ok(canonical) = std::fs::canonicalize(existing) {
+            let rest = path.strip_prefix(existing).unwrap_or(Path::new(""));
+            break if rest.as_os_str().is_empty() {
+                canonical
+            } else {
+                canonical.join(rest)
+            };
+        }
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => break path.to_path_buf(),
+        }
+    }
+}
+
+/// Makes `path` relative to `base` when it's under it, with `base` itself
+/// becoming `.`.
+fn relativize(base: Option<&Path>, path: PathBuf) -> PathBuf {
+    let Some(base) = base else {
+        return path;
+    };
+    match path.strip_prefix(base) {
+        Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path,
+    }
+}
+
+/// Keeps the first of `paths` in sorted order per device and inode, except
+/// those whose device and inode are already in `seen`.
+#[cfg(unix)]
+fn dedup_by_inode(
+    paths: HashSet<PathBuf>,
+    drop_missing: bool,
+    seen: &mut HashSet<(u64, u64)>,
+) -> HashSet<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    paths
+        .into_iter()
+        .sorted()
+        .filter(|path| match std::fs::metadata(path) {
+            Ok(metadata) => seen.insert((metadata.dev(), metadata.ino())),
+            Err(cause) => {
+                log::debug!("Unable to inspect {}: {cause}", path.display());
+                !drop_missing
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn dedup_by_inode(
+    paths: HashSet<PathBuf>,
+    _drop_missing: bool,
+    _seen: &mut HashSet<(u64, u64)>,
+) -> HashSet<PathBuf> {
+    log::warn!("Deduplicating by inode is only supported on Unix");
+    paths
+}
+
+/// Runs `write` against the standard output, or the file at `output` if any.
+///
+/// The file is written to a temporary sibling first and then renamed over
+/// `output`, so it is either left untouched or completely replaced.
+fn with_output(
+    output: Option<&Path>,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let Some(output) = output else {
+        return write(&mut io::stdout().lock());
+    };
+
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(output.file_name().unwrap_or(output.as_os_str()));
+    temp_name.push(".tmp");
+    let temp = output.with_file_name(temp_name);
+    let result = std::fs::File::create(&temp).and_then(|file| {
+        let mut writer = io::BufWriter::new(file);
+        write(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&temp, output)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Asks whether to proceed with what `summary` describes, when the standard
+/// input is a terminal and `assume_yes` isn't set; proceeds otherwise.
+fn confirm(summary: &str, assume_yes: bool) -> bool {
+    if assume_yes || !io::stdin().is_terminal() {
+        return true;
+    }
+    eprint!("{summary} [y/N] ");
+    let mut answer = String::new();
+    if let Err(cause) = io::stdin().read_line(&mut answer) {
+        log::error!("Unable to read the answer: {cause}");
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn store_names(store: &Store) -> Result<Vec<String>, Reported> {
+    store.names().map_err(|cause| {
+        log::error!("Unable to list tags: {cause}");
+        Reported
+    })
+}
+
+/// Checks whether `key` can reach itself through includes and inherits;
+/// missing tags are skipped.
+fn in_cycle(store: &Store, key: &str) -> Result<bool, IoTagError> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![key.to_owned()];
+    while let Some(name) = stack.pop() {
+        let tag = match store.load(&name) {
+            Ok(tag) => tag,
+            Err(IoTagError::Resolve(_) | IoTagError::InvalidName(_)) => continue,
+            Err(IoTagError::Io { source, .. })
+                if matches!(source.kind(), io::ErrorKind::NotFound) =>
+            {
+                continue
+            }
+            Err(cause) => return Err(cause),
+        };
+        for next in tag.include_tags().iter().chain(tag.inherited_tags()) {
+            if next == key {
+                return Ok(true);
+            }
+            if visited.insert(next.clone()) {
+                stack.push(next.clone());
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Gets the names equal to `tag` ignoring case, or just `tag` if there are
+/// none.
+fn matching_names(names: &[String], tag: &str) -> Vec<String> {
+    let folded = case_fold(tag);
+    let matches = names
+        .iter()
+        .filter(|name| case_fold(name) == folded)
+        .cloned()
+        .collect_vec();
+    if matches.is_empty() {
+        vec![tag.to_owned()]
+    } else {
+        matches
+    }
+}
+
+/// Replaces each tag of `query` with any of the names equal to it ignoring
+/// case.
+fn fold_case(query: &Query, names: &[String]) -> Query {
+    match query {
+        Query::Tag(tag) => matching_names(names, tag)
+            .into_iter()
+            .map(Query::Tag)
+            .reduce(|lhs, rhs| Query::Or(Box::new(lhs), Box::new(rhs)))
+            .unwrap_or_else(|| query.clone()),
+        Query::And(lhs, rhs) => Query::And(
+            Box::new(fold_case(lhs, names)),
+            Box::new(fold_case(rhs, names)),
+        ),
+        Query::Or(lhs, rhs) => Query::Or(
+            Box::new(fold_case(lhs, names)),
+            Box::new(fold_case(rhs, names)),
+        ),
+        Query::Not(query) => Query::Not(Box::new(fold_case(query, names))),
+    }
+}
+
+/// Normalizes each tag of `query` with the name transform of `store`.
+fn transform_query(query: &mut Query, store: &Store) {
+    match query {
+        Query::Tag(tag) => *tag = store.transform_name(tag),
+        Query::And(lhs, rhs) | Query::Or(lhs, rhs) => {
+            transform_query(lhs, store);
+            transform_query(rhs, store);
+        }
+        Query::Not(query) => transform_query(query, store),
+    }
+}
+
+fn load_meta<P: AsRef<Path>>(path: P, strict: bool) -> Result<Option<PathMetadata>, Reported> {
     let path = path.as_ref();
     match PathMetadata::load(path) {
-        Ok(meta) => Some(meta),
+        Ok(meta) => Ok(Some(meta)),
         Err(cause) if matches!(cause.kind(), io::ErrorKind::NotFound) => {
             log::info!(
                 "Fallback to default metadata for path {} since it doesn't exist: {cause}",
                 path.display()
             );
-            Some(PathMetadata::default())
+            Ok(Some(PathMetadata::default()))
         }
         Err(cause) => {
-            log::warn!(
+            let message = format_args!(
                 "Unable to load metadata for path {}: {cause}",
                 path.display()
             );
-            None
+            load_failed(message, strict).map(|()| None)
+        }
+    }
+}
+
+fn load_inherited_meta<P: AsRef<Path>>(
+    path: P,
+    strict: bool,
+) -> Result<Option<PathMetadata>, Reported> {
+    let path = path.as_ref();
+    match PathMetadata::load_inherited(path) {
+        Ok(meta) => Ok(Some(meta)),
+        Err(cause) => {
+            let message = format_args!(
+                "Unable to load inherited metadata for path {}: {cause}",
+                path.display()
+            );
+            load_failed(message, strict).map(|()| None)
+        }
+    }
+}
+
+/// Loads the metadata of every path before any is changed, leaving out those
+/// that can't be loaded unless `strict`.
+fn load_metas(
+    paths: impl IntoIterator<Item = PathBuf>,
+    strict: bool,
+) -> Result<Vec<(PathBuf, PathMetadata)>, Reported> {
+    let mut metas = Vec::new();
+    for path in paths {
+        if let Some(meta) = load_meta(&path, strict)? {
+            metas.push((path, meta));
         }
     }
+    Ok(metas)
+}
+
+/// Warns about a load failure, or fails the command in strict mode.
+fn load_failed(message: std::fmt::Arguments<'_>, strict: bool) -> Result<(), Reported> {
+    if strict {
+        log::error!("{message}");
+        return Err(Reported);
+    }
+    log::warn!("{message}");
+    Ok(())
 }
 
-fn load_tag(key: &str) -> Option<RawTag> {
-    match RawTag::load(key) {
-        Ok(tag) => Some(tag),
-        Err(IoTagError::Io(cause)) if matches!(cause.kind(), io::ErrorKind::NotFound) => {
-            log::info!("Fallback to default for tag {key:?} since it doesn't exist: {cause}");
-            Some(RawTag::default())
+fn load_tag(store: &Store, key: &str, strict: bool) -> Result<Option<RawTag>, Reported> {
+    match store.load(key) {
+        Ok(tag) => Ok(Some(tag)),
+        Err(IoTagError::Io { source, .. }) if matches!(source.kind(), io::ErrorKind::NotFound) => {
+            log::info!("Fallback to default for tag {key:?} since it doesn't exist: {source}");
+            Ok(Some(RawTag::default()))
         }
         Err(cause) => {
-            log::warn!("Unable to load tag {key:?}: {cause}");
-            None
+            load_failed(format_args!("Unable to load tag {key:?}: {cause}"), strict).map(|()| None)
+        }
+    }
+}
+
+/// Loads every tag before any is changed, leaving out those that can't be
+/// loaded unless `strict`.
+fn load_tags<'a>(
+    store: &Store,
+    keys: &'a [String],
+    strict: bool,
+) -> Result<Vec<(&'a str, RawTag)>, Reported> {
+    let mut tags = Vec::new();
+    for key in keys {
+        if let Some(tag) = load_tag(store, key, strict)? {
+            tags.push((key.as_str(), tag));
         }
     }
+    Ok(tags)
 }
 
 #[inline]
@@ -253,19 +2719,79 @@ fn save_meta<P: AsRef<Path>>(path: P, meta: &PathMetadata) {
 }
 
 #[inline]
-fn save_tag(key: &str, tag: &RawTag) {
-    if let Err(cause) = tag.save(key) {
+fn save_tag(store: &Store, key: &str, tag: &RawTag) {
+    if let Err(cause) = store.save(key, tag) {
         log::warn!("Unable to save tag {key:?}: {cause}");
     }
 }
 
-fn set_union(mut lhs: HashSet<String>, mut rhs: HashSet<String>) -> HashSet<String> {
-    if lhs.capacity() >= rhs.capacity() {
-        lhs.extend(rhs);
-        lhs
-    } else {
-        rhs.extend(lhs);
-        rhs
+/// Collects the `max` smallest items in sorted order without sorting all of
+/// them.
+fn smallest<T: Ord>(items: impl IntoIterator<Item = T>, max: usize) -> Vec<T> {
+    let mut heap = BinaryHeap::with_capacity(max.saturating_add(1));
+    for item in items {
+        heap.push(item);
+        if heap.len() > max {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec()
+}
+
+/// Reports the progress of per-path work on the standard error.
+///
+/// Nothing is reported unless the standard error is a terminal and warnings
+/// aren't suppressed.
+struct Progress {
+    total: usize,
+    done: usize,
+    start: Instant,
+    reported: Option<Instant>,
+    enabled: bool,
+}
+
+impl Progress {
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: 0,
+            start: Instant::now(),
+            reported: None,
+            enabled: io::stderr().is_terminal() && log::log_enabled!(log::Level::Warn),
+        }
+    }
+
+    fn advance(&mut self) {
+        self.done += 1;
+        if !self.enabled {
+            return;
+        }
+
+        let now = Instant::now();
+        if self
+            .reported
+            .is_some_and(|reported| now - reported < Self::INTERVAL)
+        {
+            return;
+        }
+        self.reported = Some(now);
+
+        let remaining = self.total.saturating_sub(self.done);
+        let eta = (now - self.start).as_secs_f64() / self.done as f64 * remaining as f64;
+        eprint!(
+            "\r\x1b[2K{}/{} paths, {eta:.0}s left",
+            self.done, self.total
+        );
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.reported.is_some() {
+            eprint!("\r\x1b[2K");
+        }
     }
 }
 
@@ -288,6 +2814,87 @@ impl FromStr for Paths {
     }
 }
 
+impl Paths {
+    /// Reads paths from `reader`, NUL-terminated if it contains any NUL and
+    /// one per line otherwise.
+    fn from_reader<R: io::Read>(mut reader: R) -> io::Result<Self> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let separator = if input.contains('\0') { '\0' } else { '\n' };
+        let inner = input
+            .split(separator)
+            .map(|path| path.strip_suffix('\r').unwrap_or(path))
+            .filter(|path| !path.is_empty())
+            .map(std::path::absolute)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        Ok(Self { inner })
+    }
+
+    /// Adds what is under the remaining directories after each of them,
+    /// skipping the store, metadata files and, if `respect_ignore` is set,
+    /// what the `.tagignore` files in each directory and below it exclude.
+    ///
+    /// Symbolic links are added as they are, without walking into them.
+    fn recurse(&mut self, store: &Store, respect_ignore: bool) -> Result<(), (PathBuf, io::Error)> {
+        fn walk(
+            dir: &Path,
+            store: &Path,
+            ignore: &mut Option<TagIgnore>,
+            paths: &mut Vec<PathBuf>,
+        ) -> Result<(), (PathBuf, io::Error)> {
+            let entries = std::fs::read_dir(dir).map_err(|cause| (dir.to_path_buf(), cause));
+            let entries = entries?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()
+                .map_err(|cause| (dir.to_path_buf(), cause))?;
+            for path in entries.into_iter().sorted() {
+                if path == store
+                    || PathMetadata::owner_of(&path).is_some()
+                    || (ignore.as_mut()).is_some_and(|ignore| ignore.is_ignored(&path))
+                {
+                    continue;
+                }
+                paths.push(path.clone());
+                if std::fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_dir()) {
+                    walk(&path, store, ignore, paths)?;
+                }
+            }
+            Ok(())
+        }
+
+        let store =
+            std::path::absolute(store.root()).map_err(|cause| (store.root().clone(), cause))?;
+        let mut paths = Vec::new();
+        for path in self.inner.by_ref() {
+            paths.push(path.clone());
+            if path.is_dir() {
+                let mut ignore = respect_ignore.then(|| TagIgnore::new(&path));
+                walk(&path, &store, &mut ignore, &mut paths)?;
+            }
+        }
+        self.inner = paths.into_iter().unique().collect_vec().into_iter();
+        Ok(())
+    }
+
+    /// Resolves symbolic links in the remaining paths.
+    fn follow_symlinks(&mut self) {
+        let paths = self.inner.by_ref().map(|path| follow_symlinks(&path));
+        self.inner = paths.collect_vec().into_iter();
+    }
+}
+
+impl FromIterator<PathBuf> for Paths {
+    #[inline]
+    fn from_iter<T: IntoIterator<Item = PathBuf>>(iter: T) -> Self {
+        let mut paths = Vec::from_iter(iter);
+        paths.sort();
+        Self {
+            inner: paths.into_iter(),
+        }
+    }
+}
+
 impl Iterator for Paths {
     type Item = PathBuf;
 
@@ -295,4 +2902,56 @@ impl Iterator for Paths {
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next()
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Paths {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smallest_keeps_the_first_in_order() {
+        assert_eq!(smallest([5, 1, 4, 2, 3], 3), [1, 2, 3]);
+        assert_eq!(smallest([2, 1], 5), [1, 2]);
+        assert!(smallest([1, 2], 0).is_empty());
+    }
+
+    fn written(format: Format, items: &[&str]) -> String {
+        let mut bytes = Vec::new();
+        let mut writer = format.writer(&mut bytes);
+        for item in items {
+            writer.write(item).unwrap();
+        }
+        writer.finish().unwrap();
+        drop(writer);
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn writers_format_each_item() {
+        let items = ["/a", "/b \"c\""];
+        assert_eq!(written(Format::Lines, &items), "/a\n/b \"c\"\n");
+        assert_eq!(written(Format::Nul, &items), "/a\0/b \"c\"\0");
+        assert_eq!(written(Format::Json, &items), "[\"/a\",\"/b \\\"c\\\"\"]\n");
+        assert_eq!(written(Format::Json, &[]), "[]\n");
+        assert_eq!(written(Format::Lines, &[]), "");
+    }
+
+    #[test]
+    fn templates_render_placeholders() {
+        let path = Path::new("/dir/file.rs");
+        let render = |source: &str| source.parse::<Template>().map(|it| it.render(path));
+        assert_eq!(render("{basename}").unwrap(), "file.rs");
+        assert_eq!(render("{dir}: {path}").unwrap(), "/dir: /dir/file.rs");
+        assert_eq!(render("{{{basename}}}").unwrap(), "{file.rs}");
+        assert_eq!(render("plain").unwrap(), "plain");
+        assert_eq!(render("{tags}").unwrap_err(), "unknown placeholder {tags}");
+        assert_eq!(render("{path").unwrap_err(), "unclosed `{`");
+    }
 }
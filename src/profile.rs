@@ -0,0 +1,113 @@
+//! Process-wide measurements of store operations, for profiling.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static PROFILE: Profile = Profile {
+    files_read: AtomicU64::new(0),
+    load_nanos: AtomicU64::new(0),
+    resolve_nanos: AtomicU64::new(0),
+    set_nanos: AtomicU64::new(0),
+};
+
+/// The time spent in store operations since the process started.
+///
+/// Loads include both tag files and metadata, and resolution includes the
+/// loads it performs.
+#[derive(Debug)]
+pub struct Profile {
+    files_read: AtomicU64,
+    load_nanos: AtomicU64,
+    resolve_nanos: AtomicU64,
+    set_nanos: AtomicU64,
+}
+
+/// Adds the time until dropped to a [`Profile`] measurement.
+pub(crate) struct Timer {
+    nanos: &'static AtomicU64,
+    start: Instant,
+}
+
+impl Profile {
+    #[inline]
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &PROFILE
+    }
+
+    /// The number of files loading was attempted from.
+    #[inline]
+    #[must_use]
+    pub fn files_read(&self) -> u64 {
+        self.files_read.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn load_time(&self) -> Duration {
+        Duration::from_nanos(self.load_nanos.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn resolve_time(&self) -> Duration {
+        Duration::from_nanos(self.resolve_nanos.load(Ordering::Relaxed))
+    }
+
+    /// The time spent computing unions and intersections of paths.
+    #[inline]
+    #[must_use]
+    pub fn set_time(&self) -> Duration {
+        Duration::from_nanos(self.set_nanos.load(Ordering::Relaxed))
+    }
+}
+
+impl Timer {
+    pub(crate) fn load() -> Self {
+        PROFILE.files_read.fetch_add(1, Ordering::Relaxed);
+        Self::start(&PROFILE.load_nanos)
+    }
+
+    pub(crate) fn resolve() -> Self {
+        Self::start(&PROFILE.resolve_nanos)
+    }
+
+    pub(crate) fn set() -> Self {
+        Self::start(&PROFILE.set_nanos)
+    }
+
+    fn start(nanos: &'static AtomicU64) -> Self {
+        Self {
+            nanos,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_add(elapsed, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timers_add_to_the_global_profile() {
+        let profile = Profile::global();
+        let (files_read, load_time) = (profile.files_read(), profile.load_time());
+        drop(Timer::load());
+        assert!(profile.files_read() > files_read);
+        assert!(profile.load_time() >= load_time);
+
+        let resolve_time = profile.resolve_time();
+        {
+            let _timer = Timer::resolve();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(profile.resolve_time() >= resolve_time + Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,346 @@
+//! A small query language combining tags with `AND`, `OR` and `NOT`.
+//!
+//! `NOT` binds tightest, then `AND`, then `OR`; parentheses group. `NOT` also
+//! works between two operands, so `a NOT b` is the same as `a AND NOT b`.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::{RawTag, ResolvedTags};
+
+/// A parsed query over tags.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Query {
+    /// The paths of a tag.
+    Tag(String),
+    /// The paths in both queries.
+    And(Box<Query>, Box<Query>),
+    /// The paths in either query.
+    Or(Box<Query>, Box<Query>),
+    /// The resolved paths not in the query.
+    Not(Box<Query>),
+}
+
+#[derive(Debug, Eq, Error, PartialEq)]
+pub enum QueryError {
+    #[error("expected a tag or `(` at position {0}")]
+    ExpectedOperand(usize),
+    #[error("unexpected {1:?} at position {0}")]
+    Unexpected(usize, String),
+    #[error("unclosed `(` at position {0}")]
+    Unclosed(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token<'a> {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Tag(&'a str),
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    index: usize,
+    end: usize,
+}
+
+impl Query {
+    /// Parses `source` into a query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with the byte position at which the syntax is invalid.
+    pub fn parse(source: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser {
+            tokens: tokenize(source),
+            index: 0,
+            end: source.len(),
+        };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.index) {
+            Some((position, token)) => {
+                Err(QueryError::Unexpected(*position, token.as_str().to_owned()))
+            }
+            None => Ok(query),
+        }
+    }
+
+    /// Returns the names of all tags the query refers to.
+    #[must_use]
+    pub fn tags(&self) -> HashSet<String> {
+        let mut tags = HashSet::new();
+        self.collect_tags(&mut tags);
+        tags
+    }
+
+    /// Returns a raw tag including every tag the query refers to, whose
+    /// resolution can be [`evaluate`]d against.
+    ///
+    /// [`evaluate`]: Self::evaluate
+    #[must_use]
+    pub fn to_raw(&self) -> RawTag {
+        RawTag::query(self.tags())
+    }
+
+    /// Computes the paths matching the query among the tags of `resolved`.
+    ///
+    /// `NOT` is relative to the paths of all resolved tags, and unknown tags
+    /// have no paths.
+    #[must_use]
+    pub fn evaluate(&self, resolved: &ResolvedTags) -> HashSet<PathBuf> {
+        let _timer = crate::profile::Timer::set();
+        let universe = resolved
+            .tags()
+            .values()
+            .flat_map(|tag| ResolvedTags::union_at(resolved.tags(), tag))
+            .collect();
+        self.evaluate_in(resolved, &universe)
+    }
+
+    fn evaluate_in(
+        &self,
+        resolved: &ResolvedTags,
+        universe: &HashSet<PathBuf>,
+    ) -> HashSet<PathBuf> {
+        match self {
+            Self::Tag(name) => resolved
+                .tags()
+                .get(name)
+                .map(|tag| ResolvedTags::union_at(resolved.tags(), tag))
+                .unwrap_or_default(),
+            Self::And(lhs, rhs) => {
+                if let Self::Not(rhs) = rhs.as_ref() {
+                    let rhs = rhs.evaluate_in(resolved, universe);
+                    let mut lhs = lhs.evaluate_in(resolved, universe);
+                    lhs.retain(|path| !rhs.contains(path));
+                    return lhs;
+                }
+                let rhs = rhs.evaluate_in(resolved, universe);
+                let mut lhs = lhs.evaluate_in(resolved, universe);
+                lhs.retain(|path| rhs.contains(path));
+                lhs
+            }
+            Self::Or(lhs, rhs) => {
+                let mut lhs = lhs.evaluate_in(resolved, universe);
+                lhs.extend(rhs.evaluate_in(resolved, universe));
+                lhs
+            }
+            Self::Not(query) => {
+                let excluded = query.evaluate_in(resolved, universe);
+                universe
+                    .iter()
+                    .filter(|path| !excluded.contains(*path))
+                    .cloned()
+                    .collect()
+            }
+        }
+    }
+
+    fn collect_tags(&self, tags: &mut HashSet<String>) {
+        match self {
+            Self::Tag(name) => {
+                tags.insert(name.clone());
+            }
+            Self::And(lhs, rhs) | Self::Or(lhs, rhs) => {
+                lhs.collect_tags(tags);
+                rhs.collect_tags(tags);
+            }
+            Self::Not(query) => query.collect_tags(tags),
+        }
+    }
+}
+
+impl std::str::FromStr for Query {
+    type Err = QueryError;
+
+    #[inline]
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Self::parse(source)
+    }
+}
+
+impl Token<'_> {
+    fn as_str(&self) -> &str {
+        match self {
+            Self::And => "AND",
+            Self::Or => "OR",
+            Self::Not => "NOT",
+            Self::Open => "(",
+            Self::Close => ")",
+            Self::Tag(name) => name,
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.and()?;
+        while self.eat(&Token::Or) {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.not()?;
+        loop {
+            if self.eat(&Token::And) {
+                query = Query::And(Box::new(query), Box::new(self.not()?));
+            } else if self.eat(&Token::Not) {
+                let rhs = Query::Not(Box::new(self.not()?));
+                query = Query::And(Box::new(query), Box::new(rhs));
+            } else {
+                return Ok(query);
+            }
+        }
+    }
+
+    fn not(&mut self) -> Result<Query, QueryError> {
+        if self.eat(&Token::Not) {
+            Ok(Query::Not(Box::new(self.not()?)))
+        } else {
+            self.operand()
+        }
+    }
+
+    fn operand(&mut self) -> Result<Query, QueryError> {
+        match self.tokens.get(self.index).cloned() {
+            Some((_, Token::Tag(name))) => {
+                self.index += 1;
+                Ok(Query::Tag(name.to_owned()))
+            }
+            Some((position, Token::Open)) => {
+                self.index += 1;
+                let query = self.or()?;
+                if self.eat(&Token::Close) {
+                    Ok(query)
+                } else {
+                    Err(QueryError::Unclosed(position))
+                }
+            }
+            Some((position, _)) => Err(QueryError::ExpectedOperand(position)),
+            None => Err(QueryError::ExpectedOperand(self.end)),
+        }
+    }
+
+    fn eat(&mut self, expected: &Token<'a>) -> bool {
+        let found = self
+            .tokens
+            .get(self.index)
+            .is_some_and(|(_, token)| token == expected);
+        if found {
+            self.index += 1;
+        }
+        found
+    }
+}
+
+fn tokenize(source: &str) -> Vec<(usize, Token<'_>)> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '(' => tokens.push((start, Token::Open)),
+            ')' => tokens.push((start, Token::Close)),
+            c if c.is_whitespace() => {}
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(index, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    end = index + c.len_utf8();
+                    chars.next();
+                }
+                let token = match &source[start..end] {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    name => Token::Tag(name),
+                };
+                tokens.push((start, token));
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use itertools::Itertools;
+
+    use super::*;
+
+    fn tag(name: &str) -> Box<Query> {
+        Box::new(Query::Tag(name.to_owned()))
+    }
+
+    #[test]
+    fn parses_with_precedence() {
+        let query = Query::parse("a OR b AND NOT c").unwrap();
+        let expected = Query::Or(
+            tag("a"),
+            Box::new(Query::And(tag("b"), Box::new(Query::Not(tag("c"))))),
+        );
+        assert_eq!(query, expected);
+
+        let query = Query::parse("(a OR b) NOT c").unwrap();
+        let expected = Query::And(
+            Box::new(Query::Or(tag("a"), tag("b"))),
+            Box::new(Query::Not(tag("c"))),
+        );
+        assert_eq!(query, expected);
+        let query = RawTag::from_query_str("NOT NOT a").unwrap();
+        assert_eq!(query, Query::Not(Box::new(Query::Not(tag("a")))));
+        let query = Query::parse("(a OR b) NOT c").unwrap();
+        assert_eq!(
+            query.tags(),
+            HashSet::from(["a", "b", "c"].map(str::to_owned))
+        );
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(Query::parse(""), Err(QueryError::ExpectedOperand(0)));
+        assert_eq!(Query::parse("a AND"), Err(QueryError::ExpectedOperand(5)));
+        assert_eq!(Query::parse("a OR )"), Err(QueryError::ExpectedOperand(5)));
+        assert_eq!(
+            Query::parse("a (b"),
+            Err(QueryError::Unexpected(2, "(".to_owned()))
+        );
+        assert_eq!(Query::parse("x AND (a OR b"), Err(QueryError::Unclosed(6)));
+        assert_eq!(
+            Query::parse("é b"),
+            Err(QueryError::Unexpected(3, "b".to_owned()))
+        );
+    }
+
+    #[test]
+    fn evaluates_against_resolved_tags() {
+        let tags = HashMap::from([
+            ("a".to_owned(), RawTag::default().with_paths(["/1", "/2"])),
+            ("b".to_owned(), RawTag::default().with_paths(["/2", "/3"])),
+            ("c".to_owned(), RawTag::default().with_includes(["a"])),
+        ]);
+        let resolved = ResolvedTags {
+            raw: RawTag::query(tags.keys().cloned().collect()),
+            tags,
+        };
+        let evaluate = |source: &str| {
+            let paths = Query::parse(source).unwrap().evaluate(&resolved);
+            paths.into_iter().sorted().collect::<Vec<_>>()
+        };
+        assert_eq!(evaluate("a AND b"), [PathBuf::from("/2")]);
+        assert_eq!(evaluate("c NOT b"), [PathBuf::from("/1")]);
+        assert_eq!(evaluate("NOT a"), [PathBuf::from("/3")]);
+        assert_eq!(evaluate("a OR missing").len(), 2);
+        assert!(evaluate("missing").is_empty());
+    }
+}
@@ -0,0 +1,692 @@
+//! Reading TOML documents into JSON values, and writing them back.
+//!
+//! This covers what configuration and tag files need: tables, arrays of
+//! tables, dotted and quoted keys, and every kind of value but dates and
+//! times. Documents are read into [`serde_json::Value`]s, so that they
+//! deserialize like the JSON files of a store.
+
+use std::collections::HashSet;
+
+use serde_json::{Map, Value};
+
+/// An error in a TOML document.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+#[error("line {line}: {message}")]
+pub(crate) struct Error {
+    /// The line of the error, starting at 1.
+    line: usize,
+    message: String,
+}
+
+/// Parses a TOML document into a JSON object.
+///
+/// # Errors
+///
+/// Fails when the document is malformed, defines a key twice, or holds a date
+/// or time.
+pub(crate) fn parse(source: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        source,
+        offset: 0,
+        line: 1,
+    };
+    let mut root = Map::new();
+    // the keys of the table that key-value pairs currently go into
+    let mut current = Vec::new();
+    let mut defined = HashSet::new();
+    loop {
+        parser.skip_trivia();
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.bump();
+                let array = parser.eat('[');
+                parser.skip_spaces();
+                let keys = parser.keys()?;
+                parser.skip_spaces();
+                parser.expect(']')?;
+                if array {
+                    parser.expect(']')?;
+                }
+                parser.end_of_line()?;
+                if !array && !defined.insert(keys.clone()) {
+                    return parser.error(format!("table {:?} is defined twice", keys.join(".")));
+                }
+                parser.open_table(&mut root, &keys, array)?;
+                current = keys;
+            }
+            Some(_) => {
+                let keys = parser.keys()?;
+                parser.skip_spaces();
+                parser.expect('=')?;
+                parser.skip_spaces();
+                let value = parser.value()?;
+                parser.end_of_line()?;
+                let table = parser.table_at(&mut root, &current)?;
+                parser.insert(table, &keys, value)?;
+            }
+        }
+    }
+    Ok(Value::Object(root))
+}
+
+/// Writes a JSON object as a TOML document, with nested objects as tables;
+/// `None` if it isn't an object or holds a null, which TOML cannot express.
+pub(crate) fn to_string(value: &Value) -> Option<String> {
+    let Value::Object(root) = value else {
+        return None;
+    };
+    let mut document = String::new();
+    write_table(&mut document, &mut Vec::new(), root)?;
+    Some(document)
+}
+
+fn write_table(
+    document: &mut String,
+    keys: &mut Vec<String>,
+    table: &Map<String, Value>,
+) -> Option<()> {
+    for (key, value) in table {
+        if !value.is_object() {
+            document.push_str(&write_key(key));
+            document.push_str(" = ");
+            write_value(document, value)?;
+            document.push('\n');
+        }
+    }
+    for (key, value) in table {
+        if let Value::Object(nested) = value {
+            keys.push(write_key(key));
+            if !document.is_empty() {
+                document.push('\n');
+            }
+            document.push('[');
+            document.push_str(&keys.join("."));
+            document.push_str("]\n");
+            write_table(document, keys, nested)?;
+            keys.pop();
+        }
+    }
+    Some(())
+}
+
+fn write_value(document: &mut String, value: &Value) -> Option<()> {
+    match value {
+        Value::Null => return None,
+        Value::Bool(value) => document.push_str(if *value { "true" } else { "false" }),
+        Value::Number(value) => document.push_str(&value.to_string()),
+        Value::String(value) => write_string(document, value),
+        Value::Array(items) => {
+            document.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    document.push_str(", ");
+                }
+                write_value(document, item)?;
+            }
+            document.push(']');
+        }
+        Value::Object(table) => {
+            document.push('{');
+            for (index, (key, value)) in table.iter().enumerate() {
+                document.push_str(if index > 0 { ", " } else { " " });
+                document.push_str(&write_key(key));
+                document.push_str(" = ");
+                write_value(document, value)?;
+            }
+            document.push_str(if table.is_empty() { "}" } else { " }" });
+        }
+    }
+    Some(())
+}
+
+/// Writes a key bare if it can be, or else quoted.
+fn write_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_owned()
+    } else {
+        let mut quoted = String::new();
+        write_string(&mut quoted, key);
+        quoted
+    }
+}
+
+fn write_string(document: &mut String, value: &str) {
+    document.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => document.push_str("\\\""),
+            '\\' => document.push_str("\\\\"),
+            '\n' => document.push_str("\\n"),
+            '\t' => document.push_str("\\t"),
+            '\r' => document.push_str("\\r"),
+            c if c.is_control() => document.push_str(&format!("\\u{:04X}", u32::from(c))),
+            c => document.push(c),
+        }
+    }
+    document.push('"');
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    offset: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, Error> {
+        Err(Error {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let eaten = self.peek() == Some(expected);
+        if eaten {
+            self.bump();
+        }
+        eaten
+    }
+
+    fn eat_str(&mut self, expected: &str) -> bool {
+        let eaten = self.rest().starts_with(expected);
+        if eaten {
+            for _ in expected.chars() {
+                self.bump();
+            }
+        }
+        eaten
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            self.error(format!("expected {expected:?}"))
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Skips whitespace, newlines and comments.
+    fn skip_trivia(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            if !(self.eat('\n') || self.eat_str("\r\n")) {
+                break;
+            }
+        }
+    }
+
+    /// Expects nothing but a comment before the end of the line.
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_spaces();
+        self.skip_comment();
+        if self.peek().is_none() || self.eat('\n') || self.eat_str("\r\n") {
+            Ok(())
+        } else {
+            self.error("expected the end of the line")
+        }
+    }
+
+    /// Parses a key of one or more parts separated by dots.
+    fn keys(&mut self) -> Result<Vec<String>, Error> {
+        let mut keys = vec![self.key()?];
+        loop {
+            self.skip_spaces();
+            if !self.eat('.') {
+                return Ok(keys);
+            }
+            self.skip_spaces();
+            keys.push(self.key()?);
+        }
+    }
+
+    fn key(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let rest = self.rest();
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(rest.len());
+                if len == 0 {
+                    return self.error("expected a key");
+                }
+                let key = rest[..len].to_owned();
+                self.offset += len;
+                Ok(key)
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.peek() {
+            Some('"') if self.rest().starts_with("\"\"\"") => self.multiline_basic_string(),
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') if self.rest().starts_with("'''") => self.multiline_literal_string(),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) if self.eat_str("true") => Ok(Value::Bool(true)),
+            Some(_) if self.eat_str("false") => Ok(Value::Bool(false)),
+            Some(_) => self.number(),
+            None => self.error("expected a value"),
+        }
+    }
+
+    fn escape(&mut self, string: &mut String) -> Result<(), Error> {
+        let c = match self.bump() {
+            Some('b') => '\u{8}',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('f') => '\u{c}',
+            Some('r') => '\r',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some(kind @ ('u' | 'U')) => {
+                let len = if kind == 'u' { 4 } else { 8 };
+                let digits = self.rest().get(..len).unwrap_or_default();
+                let c = u32::from_str_radix(digits, 16)
+                    .ok()
+                    .filter(|_| digits.len() == len)
+                    .and_then(char::from_u32);
+                let Some(c) = c else {
+                    return self.error(format!("invalid unicode escape {digits:?}"));
+                };
+                self.offset += len;
+                c
+            }
+            _ => return self.error("invalid escape"),
+        };
+        string.push(c);
+        Ok(())
+    }
+
+    fn basic_string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(string),
+                Some('\\') => self.escape(&mut string)?,
+                Some('\n') | None => return self.error("unterminated string"),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn multiline_basic_string(&mut self) -> Result<Value, Error> {
+        self.eat_str("\"\"\"");
+        // a newline right after the delimiter is trimmed
+        let _ = self.eat('\n') || self.eat_str("\r\n");
+        let mut string = String::new();
+        loop {
+            if self.rest().starts_with("\"\"\"") && !self.rest().starts_with("\"\"\"\"") {
+                self.eat_str("\"\"\"");
+                return Ok(Value::String(string));
+            }
+            match self.bump() {
+                Some('\\') if matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) => {
+                    // a line ending backslash trims the whitespace after it
+                    while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                        self.bump();
+                    }
+                }
+                Some('\\') => self.escape(&mut string)?,
+                Some(c) => string.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, Error> {
+        self.expect('\'')?;
+        let rest = self.rest();
+        match rest.find(['\'', '\n']) {
+            Some(end) if rest[end..].starts_with('\'') => {
+                let string = rest[..end].to_owned();
+                self.offset += end + 1;
+                Ok(string)
+            }
+            _ => self.error("unterminated string"),
+        }
+    }
+
+    fn multiline_literal_string(&mut self) -> Result<Value, Error> {
+        self.eat_str("'''");
+        let _ = self.eat('\n') || self.eat_str("\r\n");
+        let mut string = String::new();
+        loop {
+            if self.rest().starts_with("'''") && !self.rest().starts_with("''''") {
+                self.eat_str("'''");
+                return Ok(Value::String(string));
+            }
+            match self.bump() {
+                Some(c) => string.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.expect('[')?;
+        let mut array = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.eat(']') {
+                return Ok(Value::Array(array));
+            }
+            array.push(self.value()?);
+            self.skip_trivia();
+            if !self.eat(',') {
+                self.skip_trivia();
+                self.expect(']')?;
+                return Ok(Value::Array(array));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, Error> {
+        self.expect('{')?;
+        let mut table = Map::new();
+        self.skip_spaces();
+        if self.eat('}') {
+            return Ok(Value::Object(table));
+        }
+        loop {
+            self.skip_spaces();
+            let keys = self.keys()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            self.insert(&mut table, &keys, value)?;
+            self.skip_spaces();
+            if !self.eat(',') {
+                self.expect('}')?;
+                return Ok(Value::Object(table));
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.')))
+            .unwrap_or(rest.len());
+        let literal = &rest[..len];
+        let value = number(literal);
+        let Some(value) = value else {
+            return self.error(format!("invalid value {literal:?}"));
+        };
+        self.offset += len;
+        Ok(value)
+    }
+
+    /// Gets the table at `keys`, the last element of an array of tables.
+    fn table_at<'m>(
+        &self,
+        root: &'m mut Map<String, Value>,
+        keys: &[String],
+    ) -> Result<&'m mut Map<String, Value>, Error> {
+        let mut table = root;
+        for key in keys {
+            let value = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            let value = match value {
+                Value::Array(array) => array.last_mut(),
+                value => Some(value),
+            };
+            table = match value {
+                Some(Value::Object(table)) => table,
+                _ => return self.error(format!("key {key:?} is not a table")),
+            };
+        }
+        Ok(table)
+    }
+
+    /// Defines the table at `keys` of a header, or adds an element to the
+    /// array of tables there.
+    fn open_table(
+        &self,
+        root: &mut Map<String, Value>,
+        keys: &[String],
+        array: bool,
+    ) -> Result<(), Error> {
+        let (last, parents) = keys.split_last().expect("keys are never empty");
+        let parent = self.table_at(root, parents)?;
+        match (parent.get_mut(last), array) {
+            (None, false) => {
+                parent.insert(last.clone(), Value::Object(Map::new()));
+            }
+            (None, true) => {
+                parent.insert(last.clone(), Value::Array(vec![Value::Object(Map::new())]));
+            }
+            // a table may be defined after its subtables
+            (Some(Value::Object(_)), false) => {}
+            (Some(Value::Array(tables)), true) => tables.push(Value::Object(Map::new())),
+            _ => return self.error(format!("key {last:?} is defined twice")),
+        }
+        Ok(())
+    }
+
+    /// Inserts a value at dotted `keys` under `table`.
+    fn insert(
+        &self,
+        table: &mut Map<String, Value>,
+        keys: &[String],
+        value: Value,
+    ) -> Result<(), Error> {
+        let (last, parents) = keys.split_last().expect("keys are never empty");
+        let table = self.table_at(table, parents)?;
+        if table.contains_key(last) {
+            return self.error(format!("key {last:?} is defined twice"));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+}
+
+/// Parses an integer or float literal; `None` if malformed or a date.
+fn number(literal: &str) -> Option<Value> {
+    let (sign, digits) = match literal.as_bytes().first()? {
+        b'+' => (1, &literal[1..]),
+        b'-' => (-1, &literal[1..]),
+        _ => (1, literal),
+    };
+    match digits {
+        "inf" | "nan" => return None,
+        _ => {}
+    }
+    let radix = match digits.get(..2) {
+        Some("0x") => Some(16),
+        Some("0o") => Some(8),
+        Some("0b") => Some(2),
+        _ => None,
+    };
+    if let Some(radix) = radix {
+        if sign < 0 || literal.starts_with('+') {
+            return None;
+        }
+        let digits = underscored(&digits[2..])?;
+        return i64::from_str_radix(&digits, radix).ok().map(Value::from);
+    }
+
+    let plain = underscored(digits)?;
+    if plain.len() > 1 && plain.starts_with('0') && !plain.starts_with("0.") {
+        // leading zeros are forbidden, and dates start with them
+        if !plain.starts_with("0e") && !plain.starts_with("0E") {
+            return None;
+        }
+    }
+    if plain.bytes().all(|byte| byte.is_ascii_digit()) {
+        let value = plain.parse::<i64>().ok()?;
+        return Some(Value::from(sign * value));
+    }
+    if plain.starts_with('.') || plain.ends_with('.') || plain.contains(".e") {
+        return None;
+    }
+    let value = plain.parse::<f64>().ok()?;
+    let value = if sign < 0 { -value } else { value };
+    serde_json::Number::from_f64(value).map(Value::Number)
+}
+
+/// Removes the underscores between digits; `None` if any isn't between two.
+fn underscored(digits: &str) -> Option<String> {
+    let bytes = digits.as_bytes();
+    for (index, byte) in bytes.iter().enumerate() {
+        if *byte == b'_' {
+            let between = index > 0
+                && bytes[index - 1].is_ascii_alphanumeric()
+                && bytes.get(index + 1).is_some_and(u8::is_ascii_alphanumeric);
+            if !between {
+                return None;
+            }
+        }
+    }
+    if digits.is_empty() {
+        return None;
+    }
+    Some(digits.replace('_', ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_values() {
+        let source = r#"
+            # a comment
+            string = "a\tb\u00e9" # trailing
+            literal = 'C:\path'
+            integers = [1, -2, +3, 1_000, 0x1f, 0o17, 0b101]
+            floats = [1.5, -0.25, 1e3]
+            bools = [true, false]
+            nested = [[1, 2], ["a"],]
+            inline = { a = 1, b.c = "d" }
+            multiline = """
+            one \
+              two"""
+        "#;
+        assert_eq!(
+            parse(source).unwrap(),
+            json!({
+                "string": "a\tbé",
+                "literal": "C:\\path",
+                "integers": [1, -2, 3, 1000, 31, 15, 5],
+                "floats": [1.5, -0.25, 1000.0],
+                "bools": [true, false],
+                "nested": [[1, 2], ["a"]],
+                "inline": { "a": 1, "b": { "c": "d" } },
+                "multiline": "            one two",
+            })
+        );
+    }
+
+    #[test]
+    fn parses_tables() {
+        let source = "
+            top = 1
+            [a.b]
+            c = 2
+            [a]
+            d = 3
+            [\"quoted key\"]
+            e.f = 4
+            [[list]]
+            g = 5
+            [[list]]
+            g = 6
+        ";
+        assert_eq!(
+            parse(source).unwrap(),
+            json!({
+                "top": 1,
+                "a": { "b": { "c": 2 }, "d": 3 },
+                "quoted key": { "e": { "f": 4 } },
+                "list": [{ "g": 5 }, { "g": 6 }],
+            })
+        );
+    }
+
+    #[test]
+    fn writes_what_it_parses() {
+        let value = json!({
+            "top": [1, 2.5, "a \"quoted\" \\ path\n\u{7}"],
+            "flag": false,
+            "empty": [],
+            "a": { "b": { "c": "d" }, "arrays": [{ "x": 1 }, {}] },
+            "odd key": "x",
+        });
+        let document = to_string(&value).unwrap();
+        assert_eq!(parse(&document).unwrap(), value);
+        assert!(
+            document.starts_with("empty = []\nflag = false\n"),
+            "{document}"
+        );
+        assert!(document.contains("\n[a.b]\nc = \"d\"\n"), "{document}");
+        assert_eq!(to_string(&json!({ "a": null })), None);
+        assert_eq!(to_string(&json!([1])), None);
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        let errors = [
+            ("a = 1\na = 2", 2),
+            ("a = 1\n[a]", 2),
+            ("[t]\n[t]", 2),
+            ("a = \"unterminated", 1),
+            ("a = 1979-05-27", 1),
+            ("a = 01", 1),
+            ("a = 1 b = 2", 1),
+            ("a = [1, 2", 1),
+            ("= 1", 1),
+            ("a = \"\\q\"", 1),
+        ];
+        for (source, line) in errors {
+            let error = parse(source).unwrap_err();
+            assert_eq!(error.line, line, "{source:?}: {error}");
+        }
+    }
+}
@@ -0,0 +1,1131 @@
+//! A minimal Zstandard implementation for compressing tag files, following
+//! RFC 8878.
+//!
+//! Compression finds repeats through hash chains and encodes them as
+//! sequences with the predefined FSE tables, leaving literals raw, which
+//! suits the repetitive paths of large tags. Decompression reads any frame
+//! that doesn't need a dictionary.
+
+use std::fmt;
+
+/// Why Zstandard data couldn't be decompressed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Error(&'static str);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+const MAGIC: u32 = 0xfd2f_b528;
+const BLOCK_MAX: usize = 128 * 1024;
+
+const LITERALS_BASES: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+
+const LITERALS_EXTRA: [u32; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+
+const MATCH_BASES: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+
+const MATCH_EXTRA: [u32; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+/// The distributions sequences are coded with in the predefined mode.
+const LITERALS_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const MATCH_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OFFSET_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+const MIN_MATCH: usize = 4;
+/// How far back matches are looked for.
+const WINDOW: usize = 1 << 20;
+/// How many earlier occurrences of a hash are tried for a match.
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 16;
+
+/// Compresses `bytes` into a single Zstandard frame with a content checksum.
+pub(crate) fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut out = MAGIC.to_le_bytes().to_vec();
+    let size = bytes.len() as u64;
+    // a single segment, so the content size also gives the window size
+    let (size_flag, size_bytes) = if size < 256 {
+        (0, vec![size as u8])
+    } else if size < 256 + 0x1_0000 {
+        (1, ((size - 256) as u16).to_le_bytes().to_vec())
+    } else if let Ok(size) = u32::try_from(size) {
+        (2, size.to_le_bytes().to_vec())
+    } else {
+        (3, size.to_le_bytes().to_vec())
+    };
+    out.push(size_flag << 6 | 1 << 5 | 1 << 2);
+    out.extend(size_bytes);
+
+    let encoders = [
+        Encoder::new(&LITERALS_DEFAULT, 6),
+        Encoder::new(&MATCH_DEFAULT, 6),
+        Encoder::new(&OFFSET_DEFAULT, 5),
+    ];
+    let mut chains = Chains {
+        bytes,
+        head: vec![u32::MAX; 1 << HASH_BITS],
+        prev: vec![u32::MAX; bytes.len()],
+    };
+    let mut start = 0;
+    loop {
+        let end = bytes.len().min(start + BLOCK_MAX);
+        let last = u32::from(end == bytes.len());
+        match compress_block(&mut chains, start, end, &encoders) {
+            Some(block) if block.len() < end - start => {
+                let header = (block.len() as u32) << 3 | 2 << 1 | last;
+                out.extend_from_slice(&header.to_le_bytes()[..3]);
+                out.extend(block);
+            }
+            _ => {
+                let header = ((end - start) as u32) << 3 | last;
+                out.extend_from_slice(&header.to_le_bytes()[..3]);
+                out.extend_from_slice(&bytes[start..end]);
+            }
+        }
+        if last == 1 {
+            break;
+        }
+        start = end;
+    }
+    out.extend_from_slice(&(xxh64(bytes) as u32).to_le_bytes());
+    out
+}
+
+/// Decompresses the Zstandard frames of `bytes`, verifying their checksums
+/// and sizes.
+pub(crate) fn decompress(mut bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    if bytes.is_empty() {
+        return Err(Error("not Zstandard data"));
+    }
+    let mut output = Vec::new();
+    while !bytes.is_empty() {
+        let magic = read_le(bytes, 0, 4).ok_or(Error("not Zstandard data"))? as u32;
+        bytes = if magic == MAGIC {
+            decompress_frame(&bytes[4..], &mut output)?
+        } else if magic & 0xffff_fff0 == 0x184d_2a50 {
+            let size = read_le(bytes, 4, 4).ok_or(Error("truncated skippable frame"))?;
+            bytes
+                .get(8 + size as usize..)
+                .ok_or(Error("truncated skippable frame"))?
+        } else {
+            return Err(Error("not Zstandard data"));
+        };
+    }
+    Ok(output)
+}
+
+/// A match that follows `literals` bytes, where `offset` is either how far
+/// back it starts or, when decoding, the offset value naming a repeat.
+struct Sequence {
+    literals: u32,
+    offset: u32,
+    length: u32,
+}
+
+/// Compresses the block of `chains.bytes` at `start..end`, or gets [`None`]
+/// if there aren't any matches in it.
+fn compress_block(
+    chains: &mut Chains<'_>,
+    start: usize,
+    end: usize,
+    encoders: &[Encoder; 3],
+) -> Option<Vec<u8>> {
+    let bytes = chains.bytes;
+    let mut literals = Vec::new();
+    let mut sequences = Vec::new();
+    let mut anchor = start;
+    let mut pos = start;
+    while pos < end {
+        let Some((length, offset)) = chains.longest_match(pos, end) else {
+            chains.insert(pos);
+            pos += 1;
+            continue;
+        };
+        literals.extend_from_slice(&bytes[anchor..pos]);
+        sequences.push(Sequence {
+            literals: (pos - anchor) as u32,
+            offset: offset as u32,
+            length: length as u32,
+        });
+        for pos in pos..pos + length {
+            chains.insert(pos);
+        }
+        pos += length;
+        anchor = pos;
+    }
+    if sequences.is_empty() {
+        return None;
+    }
+    literals.extend_from_slice(&bytes[anchor..end]);
+
+    // raw literals, with a header as short as their size allows
+    let size = literals.len();
+    let mut block = if size < 32 {
+        vec![(size as u8) << 3]
+    } else if size < 4096 {
+        vec![(size as u8) << 4 | 0b0100, (size >> 4) as u8]
+    } else {
+        vec![
+            (size as u8) << 4 | 0b1100,
+            (size >> 4) as u8,
+            (size >> 12) as u8,
+        ]
+    };
+    block.extend(literals);
+
+    let count = sequences.len();
+    if count < 128 {
+        block.push(count as u8);
+    } else if count < 0x7f00 {
+        block.extend([(count >> 8) as u8 + 128, count as u8]);
+    } else {
+        block.push(255);
+        block.extend_from_slice(&((count - 0x7f00) as u16).to_le_bytes());
+    }
+    // predefined tables for all three codes
+    block.push(0);
+    block.extend(encode_sequences(&sequences, encoders));
+    Some(block)
+}
+
+/// Encodes `sequences` into a bitstream read from its end, so the last
+/// sequence starts it.
+fn encode_sequences(
+    sequences: &[Sequence],
+    [literals, matches, offsets]: &[Encoder; 3],
+) -> Vec<u8> {
+    let codes = sequences
+        .iter()
+        .map(|sequence| {
+            let offset = sequence.offset + 3;
+            (
+                code_of(&LITERALS_BASES, sequence.literals),
+                code_of(&MATCH_BASES, sequence.length),
+                31 - offset.leading_zeros(),
+                offset,
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        count: 0,
+    };
+    let write_extra = |writer: &mut BitWriter, n: usize| {
+        let (literals_code, match_code, offset_code, offset) = codes[n];
+        let sequence = &sequences[n];
+        writer.write(
+            sequence.literals - LITERALS_BASES[literals_code],
+            LITERALS_EXTRA[literals_code],
+        );
+        writer.write(
+            sequence.length - MATCH_BASES[match_code],
+            MATCH_EXTRA[match_code],
+        );
+        writer.write(offset - (1 << offset_code), offset_code);
+    };
+
+    let last = codes.len() - 1;
+    let (literals_code, match_code, offset_code, _) = codes[last];
+    let mut match_state = matches.initial_state(match_code);
+    let mut offset_state = offsets.initial_state(offset_code as usize);
+    let mut literals_state = literals.initial_state(literals_code);
+    write_extra(&mut writer, last);
+    for n in (0..last).rev() {
+        let (literals_code, match_code, offset_code, _) = codes[n];
+        offsets.encode(&mut offset_state, offset_code as usize, &mut writer);
+        matches.encode(&mut match_state, match_code, &mut writer);
+        literals.encode(&mut literals_state, literals_code, &mut writer);
+        write_extra(&mut writer, n);
+    }
+    writer.write(match_state - (1 << matches.log), matches.log);
+    writer.write(offset_state - (1 << offsets.log), offsets.log);
+    writer.write(literals_state - (1 << literals.log), literals.log);
+    writer.finish()
+}
+
+/// The code of the largest of `bases` that `value` reaches.
+fn code_of(bases: &[u32], value: u32) -> usize {
+    bases.partition_point(|&base| base <= value) - 1
+}
+
+struct Chains<'a> {
+    bytes: &'a [u8],
+    /// The latest position of each hash, plus one.
+    head: Vec<u32>,
+    /// The position before each one with the same hash.
+    prev: Vec<u32>,
+}
+
+impl Chains<'_> {
+    fn hash(&self, pos: usize) -> Option<usize> {
+        let word = self.bytes.get(pos..pos + MIN_MATCH)?;
+        let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        Some((word.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize)
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if let Some(hash) = self.hash(pos) {
+            self.prev[pos] = self.head[hash];
+            self.head[hash] = pos as u32;
+        }
+    }
+
+    /// Finds the longest earlier repeat of the bytes at `pos`, ending by
+    /// `end`, as its length and how far back it starts.
+    fn longest_match(&self, pos: usize, end: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > end {
+            return None;
+        }
+        let mut candidate = self.head[self.hash(pos)?];
+        let mut best = None;
+        let mut best_length = MIN_MATCH - 1;
+        for _ in 0..MAX_CHAIN {
+            if candidate == u32::MAX || pos - candidate as usize > WINDOW {
+                break;
+            }
+            let from = candidate as usize;
+            let length = self.bytes[from..]
+                .iter()
+                .zip(&self.bytes[pos..end])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                best_length = length;
+                best = Some((length, pos - from));
+                if pos + length == end {
+                    break;
+                }
+            }
+            candidate = self.prev[from];
+        }
+        best
+    }
+}
+
+/// Writes bits from the least significant bit of each byte.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= (u64::from(value) & ((1 << count) - 1)) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Ends the stream with the bit that marks where it ends.
+    fn finish(mut self) -> Vec<u8> {
+        self.write(1, 1);
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// An FSE table for encoding symbols, as the reference encoder lays it out.
+struct Encoder {
+    log: u32,
+    /// The next state for each symbol, in order of symbol.
+    states: Vec<u32>,
+    /// For each symbol, its offset into [`Self::states`] and a bias that
+    /// gives how many bits each state flushes.
+    symbols: Vec<(i32, u32)>,
+}
+
+impl Encoder {
+    fn new(distribution: &[i16], log: u32) -> Self {
+        let size = 1 << log;
+        let spread = spread(distribution, log);
+        let mut next = Vec::with_capacity(distribution.len());
+        let mut symbols = Vec::with_capacity(distribution.len());
+        let mut total = 0;
+        for &count in distribution {
+            next.push(total);
+            let count = u32::from(count.unsigned_abs());
+            let bits = match count {
+                0 => 0,
+                1 => log,
+                _ => log - (31 - (count - 1).leading_zeros()),
+            };
+            symbols.push((total as i32 - count as i32, (bits << 16) - (count << bits)));
+            total += count as usize;
+        }
+        let mut states = vec![0; size];
+        for (state, &symbol) in spread.iter().enumerate() {
+            states[next[symbol]] = (size + state) as u32;
+            next[symbol] += 1;
+        }
+        Self {
+            log,
+            states,
+            symbols,
+        }
+    }
+
+    fn initial_state(&self, symbol: usize) -> u32 {
+        let (offset, bias) = self.symbols[symbol];
+        let bits = (bias + (1 << 15)) >> 16;
+        let value = (bits << 16) - bias;
+        self.states[((value >> bits) as i32 + offset) as usize]
+    }
+
+    fn encode(&self, state: &mut u32, symbol: usize, writer: &mut BitWriter) {
+        let (offset, bias) = self.symbols[symbol];
+        let bits = (*state + bias) >> 16;
+        writer.write(*state, bits);
+        *state = self.states[((*state >> bits) as i32 + offset) as usize];
+    }
+}
+
+/// Spreads the symbols of `distribution` over a table of `1 << log` states,
+/// with the symbols of less than one probability at its end.
+fn spread(distribution: &[i16], log: u32) -> Vec<usize> {
+    let size = 1 << log;
+    let mut table = vec![0; size];
+    let mut high = size;
+    for (symbol, _) in distribution
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count == -1)
+    {
+        high -= 1;
+        table[high] = symbol;
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mut pos = 0;
+    for (symbol, &count) in distribution.iter().enumerate() {
+        for _ in 0..count.max(0) {
+            table[pos] = symbol;
+            pos = (pos + step) & (size - 1);
+            while pos >= high {
+                pos = (pos + step) & (size - 1);
+            }
+        }
+    }
+    table
+}
+
+/// Reads `count` little-endian bytes of `bytes` at `pos`.
+fn read_le(bytes: &[u8], pos: usize, count: usize) -> Option<u64> {
+    let bytes = bytes.get(pos..pos + count)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | u64::from(byte)),
+    )
+}
+
+/// Decompresses the frame at the start of `bytes` into `output`, getting the
+/// bytes after it.
+fn decompress_frame<'a>(bytes: &'a [u8], output: &mut Vec<u8>) -> Result<&'a [u8], Error> {
+    let truncated = Error("truncated frame header");
+    let descriptor = *bytes.first().ok_or(truncated)?;
+    if descriptor & 0x08 != 0 {
+        return Err(Error("reserved frame header bit set"));
+    }
+    let single_segment = descriptor & 0x20 != 0;
+    let mut pos = 1 + usize::from(!single_segment);
+    let dictionary_size = [0, 1, 2, 4][usize::from(descriptor & 3)];
+    if read_le(bytes, pos, dictionary_size).ok_or(truncated)? != 0 {
+        return Err(Error("dictionaries are not supported"));
+    }
+    pos += dictionary_size;
+    let content_size = match (descriptor >> 6, single_segment) {
+        (0, false) => None,
+        (0, true) => Some(read_le(bytes, pos, 1).ok_or(truncated)?),
+        (1, _) => Some(read_le(bytes, pos, 2).ok_or(truncated)? + 256),
+        (2, _) => Some(read_le(bytes, pos, 4).ok_or(truncated)?),
+        _ => Some(read_le(bytes, pos, 8).ok_or(truncated)?),
+    };
+    pos += match descriptor >> 6 {
+        0 => usize::from(single_segment),
+        flag => 1 << flag,
+    };
+
+    let start = output.len();
+    let mut state = FrameState {
+        repeats: [1, 4, 8],
+        huffman: None,
+        tables: [None, None, None],
+    };
+    loop {
+        let header = read_le(bytes, pos, 3).ok_or(Error("truncated block"))? as usize;
+        pos += 3;
+        let size = header >> 3;
+        if size > BLOCK_MAX {
+            return Err(Error("block too large"));
+        }
+        let kind = header >> 1 & 3;
+        let content = bytes
+            .get(pos..pos + if kind == 1 { 1 } else { size })
+            .ok_or(Error("truncated block"))?;
+        match kind {
+            0 => output.extend_from_slice(content),
+            1 => output.resize(output.len() + size, content[0]),
+            2 => state.decompress_block(content, output, start)?,
+            _ => return Err(Error("reserved block type")),
+        }
+        pos += content.len();
+        if header & 1 == 1 {
+            break;
+        }
+    }
+
+    if content_size.is_some_and(|size| size != (output.len() - start) as u64) {
+        return Err(Error("wrong content size"));
+    }
+    if descriptor & 0x04 != 0 {
+        let checksum = read_le(bytes, pos, 4).ok_or(Error("truncated checksum"))?;
+        if checksum != u64::from(xxh64(&output[start..]) as u32) {
+            return Err(Error("checksum mismatch"));
+        }
+        pos += 4;
+    }
+    Ok(&bytes[pos..])
+}
+
+/// What the blocks of a frame can take from the ones before them.
+struct FrameState {
+    repeats: [u32; 3],
+    huffman: Option<Huffman>,
+    /// The literals length, offset and match length tables last used.
+    tables: [Option<Table>; 3],
+}
+
+impl FrameState {
+    fn decompress_block(
+        &mut self,
+        block: &[u8],
+        output: &mut Vec<u8>,
+        start: usize,
+    ) -> Result<(), Error> {
+        let (literals, pos) = self.decompress_literals(block)?;
+        let sequences = self.decode_sequences(&block[pos..])?;
+        let mut literals = &literals[..];
+        for sequence in sequences {
+            let (before, rest) = literals
+                .split_at_checked(sequence.literals as usize)
+                .ok_or(Error("sequence beyond the literals"))?;
+            output.extend_from_slice(before);
+            literals = rest;
+            let offset = self.offset(sequence.offset, sequence.literals)? as usize;
+            if offset > output.len() - start {
+                return Err(Error("match before the start of the frame"));
+            }
+            let from = output.len() - offset;
+            let length = sequence.length as usize;
+            if offset >= length {
+                output.extend_from_within(from..from + length);
+            } else {
+                for pos in from..from + length {
+                    output.push(output[pos]);
+                }
+            }
+        }
+        output.extend_from_slice(literals);
+        Ok(())
+    }
+
+    /// Resolves an offset value into how far back a match starts, updating
+    /// the repeated offsets.
+    fn offset(&mut self, value: u32, literals: u32) -> Result<u32, Error> {
+        let [first, second, third] = self.repeats;
+        if value > 3 {
+            self.repeats = [value - 3, first, second];
+            return Ok(value - 3);
+        }
+        // without literals, the values name the next repeats instead
+        let offset = match value + u32::from(literals == 0) {
+            1 => return Ok(first),
+            2 => {
+                self.repeats = [second, first, third];
+                return Ok(second);
+            }
+            3 => third,
+            _ => first
+                .checked_sub(1)
+                .filter(|&offset| offset > 0)
+                .ok_or(Error("zero offset"))?,
+        };
+        self.repeats = [offset, first, second];
+        Ok(offset)
+    }
+
+    /// Decompresses the literals section at the start of `block`, getting
+    /// its literals and size.
+    fn decompress_literals(&mut self, block: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        let truncated = Error("truncated literals");
+        let first = *block.first().ok_or(truncated)?;
+        let kind = first & 3;
+        let format = first >> 2 & 3;
+        if kind < 2 {
+            let (size, pos) = match format {
+                0 | 2 => (usize::from(first >> 3), 1),
+                1 => (read_le(block, 0, 2).ok_or(truncated)? as usize >> 4, 2),
+                _ => (read_le(block, 0, 3).ok_or(truncated)? as usize >> 4, 3),
+            };
+            return if kind == 0 {
+                let literals = block.get(pos..pos + size).ok_or(truncated)?;
+                Ok((literals.to_vec(), pos + size))
+            } else {
+                let &byte = block.get(pos).ok_or(truncated)?;
+                Ok((vec![byte; size], pos + 1))
+            };
+        }
+
+        let (pos, bits) = match format {
+            0 | 1 => (3, 10),
+            2 => (4, 14),
+            _ => (5, 18),
+        };
+        let header = read_le(block, 0, pos).ok_or(truncated)?;
+        let mask = (1 << bits) - 1;
+        let size = (header >> 4 & mask) as usize;
+        let compressed_size = (header >> (4 + bits) & mask) as usize;
+        let mut compressed = block.get(pos..pos + compressed_size).ok_or(truncated)?;
+        if kind == 2 {
+            let (huffman, table_size) = Huffman::read(compressed)?;
+            self.huffman = Some(huffman);
+            compressed = &compressed[table_size..];
+        }
+        let huffman = self
+            .huffman
+            .as_ref()
+            .ok_or(Error("no Huffman table to repeat"))?;
+        let mut literals = Vec::with_capacity(size);
+        if format == 0 {
+            huffman.decode(compressed, size, &mut literals)?;
+        } else {
+            let jumps = read_le(compressed, 0, 6).ok_or(truncated)?;
+            let mut streams = &compressed[6..];
+            let each = size.div_ceil(4);
+            let last = size
+                .checked_sub(3 * each)
+                .ok_or(Error("too few literals for four streams"))?;
+            for n in 0..4 {
+                let (stream, rest) = if n < 3 {
+                    streams
+                        .split_at_checked((jumps >> (16 * n) & 0xffff) as usize)
+                        .ok_or(truncated)?
+                } else {
+                    (streams, &[][..])
+                };
+                huffman.decode(stream, if n < 3 { each } else { last }, &mut literals)?;
+                streams = rest;
+            }
+        }
+        Ok((literals, pos + compressed_size))
+    }
+
+    /// Decodes the sequences section that follows the literals of a block.
+    fn decode_sequences(&mut self, section: &[u8]) -> Result<Vec<Sequence>, Error> {
+        let truncated = Error("truncated sequences");
+        let first = *section.first().ok_or(truncated)?;
+        let (count, mut pos) = match first {
+            0 => return Ok(Vec::new()),
+            1..=127 => (usize::from(first), 1),
+            128..=254 => {
+                let &low = section.get(1).ok_or(truncated)?;
+                ((usize::from(first) - 128) << 8 | usize::from(low), 2)
+            }
+            255 => (
+                read_le(section, 1, 2).ok_or(truncated)? as usize + 0x7f00,
+                3,
+            ),
+        };
+        let &modes = section.get(pos).ok_or(truncated)?;
+        pos += 1;
+        if modes & 3 != 0 {
+            return Err(Error("reserved sequence modes set"));
+        }
+        let kinds = [
+            (&LITERALS_DEFAULT[..], 6, 9),
+            (&OFFSET_DEFAULT, 5, 8),
+            (&MATCH_DEFAULT, 6, 9),
+        ];
+        for (n, (default, default_log, max_log)) in kinds.into_iter().enumerate() {
+            // 0 stands for up to 32 offset codes
+            let symbols = if n == 1 { 32 } else { default.len() };
+            let table = match modes >> (6 - 2 * n) & 3 {
+                0 => Table::new(default, default_log),
+                1 => {
+                    let &symbol = section.get(pos).ok_or(truncated)?;
+                    pos += 1;
+                    if usize::from(symbol) >= symbols {
+                        return Err(Error("invalid sequence code"));
+                    }
+                    Table {
+                        log: 0,
+                        entries: vec![(symbol, 0, 0)],
+                    }
+                }
+                2 => {
+                    let (distribution, log, size) =
+                        read_distribution(&section[pos..], max_log, symbols)?;
+                    pos += size;
+                    Table::new(&distribution, log)
+                }
+                _ => self.tables[n]
+                    .take()
+                    .ok_or(Error("no sequence table to repeat"))?,
+            };
+            self.tables[n] = Some(table);
+        }
+        let [Some(literals), Some(offsets), Some(matches)] = &self.tables else {
+            unreachable!("every table was just set");
+        };
+
+        let mut reader = ReverseBitReader::new(&section[pos..])?;
+        let mut literals_state = reader.read(literals.log) as usize;
+        let mut offset_state = reader.read(offsets.log) as usize;
+        let mut match_state = reader.read(matches.log) as usize;
+        let mut sequences = Vec::with_capacity(count);
+        for n in 0..count {
+            let offset_code = u32::from(offsets.entries[offset_state].0);
+            let match_code = usize::from(matches.entries[match_state].0);
+            let literals_code = usize::from(literals.entries[literals_state].0);
+            if offset_code > 31 {
+                return Err(Error("invalid sequence code"));
+            }
+            let offset = (1 << offset_code) + reader.read(offset_code) as u32;
+            let length = MATCH_BASES[match_code] + reader.read(MATCH_EXTRA[match_code]) as u32;
+            let literals_length =
+                LITERALS_BASES[literals_code] + reader.read(LITERALS_EXTRA[literals_code]) as u32;
+            sequences.push(Sequence {
+                literals: literals_length,
+                offset,
+                length,
+            });
+            if n + 1 < count {
+                literals_state = literals.next(literals_state, &mut reader);
+                match_state = matches.next(match_state, &mut reader);
+                offset_state = offsets.next(offset_state, &mut reader);
+            }
+        }
+        if !reader.is_done() {
+            return Err(Error("sequence bitstream size mismatch"));
+        }
+        Ok(sequences)
+    }
+}
+
+/// An FSE table for decoding symbols.
+#[derive(Clone)]
+struct Table {
+    log: u32,
+    /// The symbol of each state, and how many bits to read for the next state
+    /// and what to add to them.
+    entries: Vec<(u8, u32, usize)>,
+}
+
+impl Table {
+    fn new(distribution: &[i16], log: u32) -> Self {
+        let size = 1 << log;
+        let mut next = distribution
+            .iter()
+            .map(|&count| count.max(if count == -1 { 1 } else { 0 }) as usize)
+            .collect::<Vec<_>>();
+        let entries = spread(distribution, log)
+            .into_iter()
+            .map(|symbol| {
+                let state = next[symbol];
+                next[symbol] += 1;
+                let bits = log - (usize::BITS - 1 - state.leading_zeros());
+                (symbol as u8, bits, (state << bits) - size)
+            })
+            .collect();
+        Self { log, entries }
+    }
+
+    fn next(&self, state: usize, reader: &mut ReverseBitReader<'_>) -> usize {
+        let (_, bits, base) = self.entries[state];
+        base + reader.read(bits) as usize
+    }
+}
+
+/// Reads an FSE table description, getting its distribution, accuracy log
+/// and size.
+fn read_distribution(
+    bytes: &[u8],
+    max_log: u32,
+    max_symbols: usize,
+) -> Result<(Vec<i16>, u32, usize), Error> {
+    let mut reader = BitReader { bytes, pos: 0 };
+    let log = reader.read(4) + 5;
+    if log > max_log {
+        return Err(Error("FSE table too large"));
+    }
+    let mut remaining = (1 << log) + 1;
+    let mut threshold = 1 << log;
+    let mut bits = log + 1;
+    let mut distribution = Vec::new();
+    while remaining > 1 {
+        let max = 2 * threshold - 1 - remaining;
+        let low = reader.peek(bits - 1);
+        let value = if low < max {
+            reader.pos += bits as usize - 1;
+            low
+        } else {
+            let value = reader.peek(bits);
+            reader.pos += bits as usize;
+            if value >= threshold {
+                value - max
+            } else {
+                value
+            }
+        };
+        let count = value as i32 - 1;
+        remaining = remaining
+            .checked_sub(count.unsigned_abs())
+            .filter(|&remaining| remaining >= 1)
+            .ok_or(Error("invalid FSE table"))?;
+        distribution.push(count as i16);
+        if count == 0 {
+            loop {
+                let repeat = reader.read(2);
+                distribution.extend((0..repeat).map(|_| 0));
+                if repeat < 3 {
+                    break;
+                }
+            }
+        }
+        if distribution.len() > max_symbols {
+            return Err(Error("invalid FSE table"));
+        }
+        while remaining < threshold && threshold > 1 {
+            bits -= 1;
+            threshold >>= 1;
+        }
+    }
+    let size = reader.pos.div_ceil(8);
+    if remaining != 1 || size > bytes.len() {
+        return Err(Error("invalid FSE table"));
+    }
+    Ok((distribution, log, size))
+}
+
+/// A Huffman table for decoding literals, indexed by the next bits.
+struct Huffman {
+    bits: u32,
+    entries: Vec<(u8, u32)>,
+}
+
+impl Huffman {
+    /// Reads a Huffman tree description, getting its table and size.
+    fn read(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let truncated = Error("truncated Huffman table");
+        let header = usize::from(*bytes.first().ok_or(truncated)?);
+        let (mut weights, size) = if header >= 128 {
+            let count = header - 127;
+            let packed = bytes.get(1..1 + count.div_ceil(2)).ok_or(truncated)?;
+            let weights = (0..count)
+                .map(|n| packed[n / 2] >> (4 * (1 - n % 2)) & 15)
+                .collect::<Vec<_>>();
+            (weights, 1 + packed.len())
+        } else {
+            let compressed = bytes.get(1..1 + header).ok_or(truncated)?;
+            let (distribution, log, size) = read_distribution(compressed, 6, 256)?;
+            let table = Table::new(&distribution, log);
+            let mut reader = ReverseBitReader::new(&compressed[size..])?;
+            let mut states = [reader.read(log) as usize, reader.read(log) as usize];
+            let mut weights = Vec::new();
+            // two interleaved states, until the stream runs out
+            'decode: while weights.len() < 256 {
+                for n in 0..2 {
+                    weights.push(table.entries[states[n]].0);
+                    states[n] = table.next(states[n], &mut reader);
+                    if reader.pos < 0 {
+                        weights.push(table.entries[states[1 - n]].0);
+                        break 'decode;
+                    }
+                }
+            }
+            (weights, 1 + header)
+        };
+
+        if weights.len() > 255 || weights.iter().any(|&weight| weight > 11) {
+            return Err(Error("invalid Huffman table"));
+        }
+        let total = weights
+            .iter()
+            .filter(|&&weight| weight > 0)
+            .map(|&weight| 1u32 << (weight - 1))
+            .sum::<u32>();
+        if total == 0 {
+            return Err(Error("invalid Huffman table"));
+        }
+        let bits = 32 - total.leading_zeros();
+        let left = (1 << bits) - total;
+        if bits > 11 || !left.is_power_of_two() {
+            return Err(Error("invalid Huffman table"));
+        }
+        weights.push(left.trailing_zeros() as u8 + 1);
+
+        let mut starts = vec![0; bits as usize + 2];
+        for &weight in &weights {
+            if weight > 0 {
+                starts[usize::from(weight) + 1] += 1 << (weight - 1);
+            }
+        }
+        for weight in 1..starts.len() {
+            starts[weight] += starts[weight - 1];
+        }
+        let mut entries = vec![(0, 0); 1 << bits];
+        for (symbol, &weight) in weights.iter().enumerate() {
+            if weight > 0 {
+                let start = starts[usize::from(weight)];
+                let length = 1 << (weight - 1);
+                entries[start..start + length].fill((symbol as u8, bits + 1 - u32::from(weight)));
+                starts[usize::from(weight)] += length;
+            }
+        }
+        Ok((Self { bits, entries }, size))
+    }
+
+    fn decode(&self, stream: &[u8], count: usize, literals: &mut Vec<u8>) -> Result<(), Error> {
+        let mut reader = ReverseBitReader::new(stream)?;
+        for _ in 0..count {
+            let (symbol, bits) = self.entries[reader.peek(self.bits) as usize];
+            reader.pos -= bits as isize;
+            literals.push(symbol);
+        }
+        if !reader.is_done() {
+            return Err(Error("Huffman stream size mismatch"));
+        }
+        Ok(())
+    }
+}
+
+/// Reads bits from the least significant bit of each byte.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    /// Peeks at the next `count` bits, reading zeros past the end.
+    fn peek(&self, count: u32) -> u32 {
+        (0..count).fold(0, |value, n| {
+            let pos = self.pos + n as usize;
+            let byte = self.bytes.get(pos / 8).copied().unwrap_or(0);
+            value | u32::from(byte >> (pos % 8) & 1) << n
+        })
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = self.peek(count);
+        self.pos += count as usize;
+        value
+    }
+}
+
+/// Reads bits from the end of a stream towards its start, with the value of
+/// each read taken from high to low bits, after the padding of its last byte.
+struct ReverseBitReader<'a> {
+    bytes: &'a [u8],
+    /// How many bits are left to read, which turns negative once the reads
+    /// overflow the start of the stream.
+    pos: isize,
+}
+
+impl<'a> ReverseBitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        match bytes.last() {
+            Some(&last) if last != 0 => Ok(Self {
+                bytes,
+                pos: bytes.len() as isize * 8 - last.leading_zeros() as isize - 1,
+            }),
+            _ => Err(Error("missing bitstream end mark")),
+        }
+    }
+
+    /// Peeks at the next `count` bits, up to 56, reading zeros before the start.
+    fn peek(&self, count: u32) -> u64 {
+        let low = self.pos - count as isize;
+        let first = low.div_euclid(8);
+        let shift = low.rem_euclid(8);
+        let value = (0..(shift as u32 + count).div_ceil(8)).fold(0, |value, n| {
+            let byte = usize::try_from(first + n as isize)
+                .ok()
+                .and_then(|pos| self.bytes.get(pos))
+                .copied()
+                .unwrap_or(0);
+            value | u64::from(byte) << (8 * n)
+        });
+        value >> shift & ((1 << count) - 1)
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.pos -= count as isize;
+        value
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == 0
+    }
+}
+
+/// Hashes `bytes` with XXH64 and a zero seed, as content checksums are.
+fn xxh64(bytes: &[u8]) -> u64 {
+    const PRIMES: [u64; 5] = [
+        0x9e37_79b1_85eb_ca87,
+        0xc2b2_ae3d_27d4_eb4f,
+        0x1656_67b1_9e37_79f9,
+        0x85eb_ca77_c2b2_ae63,
+        0x27d4_eb2f_1656_67c5,
+    ];
+    let round = |acc: u64, lane: u64| {
+        acc.wrapping_add(lane.wrapping_mul(PRIMES[1]))
+            .rotate_left(31)
+            .wrapping_mul(PRIMES[0])
+    };
+    let word = |bytes: &[u8]| read_le(bytes, 0, bytes.len()).unwrap_or(0);
+
+    let mut stripes = bytes.chunks_exact(32);
+    let mut hash = if bytes.len() >= 32 {
+        let mut lanes = [
+            PRIMES[0].wrapping_add(PRIMES[1]),
+            PRIMES[1],
+            0,
+            PRIMES[0].wrapping_neg(),
+        ];
+        for stripe in &mut stripes {
+            for (n, lane) in lanes.iter_mut().enumerate() {
+                *lane = round(*lane, word(&stripe[8 * n..8 * n + 8]));
+            }
+        }
+        let hash = lanes
+            .iter()
+            .zip([1, 7, 12, 18])
+            .fold(0u64, |hash, (lane, bits)| {
+                hash.wrapping_add(lane.rotate_left(bits))
+            });
+        lanes.iter().fold(hash, |hash, &lane| {
+            (hash ^ round(0, lane))
+                .wrapping_mul(PRIMES[0])
+                .wrapping_add(PRIMES[3])
+        })
+    } else {
+        PRIMES[4]
+    };
+    hash = hash.wrapping_add(bytes.len() as u64);
+
+    let mut words = stripes.remainder().chunks_exact(8);
+    for lane in &mut words {
+        hash = (hash ^ round(0, word(lane)))
+            .rotate_left(27)
+            .wrapping_mul(PRIMES[0])
+            .wrapping_add(PRIMES[3]);
+    }
+    let mut halves = words.remainder().chunks_exact(4);
+    for half in &mut halves {
+        hash = (hash ^ word(half).wrapping_mul(PRIMES[0]))
+            .rotate_left(23)
+            .wrapping_mul(PRIMES[1])
+            .wrapping_add(PRIMES[2]);
+    }
+    for &byte in halves.remainder() {
+        hash = (hash ^ u64::from(byte).wrapping_mul(PRIMES[4]))
+            .rotate_left(11)
+            .wrapping_mul(PRIMES[0]);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIMES[1]);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIMES[2]);
+    hash ^ hash >> 32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let repetitive = b"/home/user/projects/".repeat(20_000);
+        let mixed = (0..20_000u32)
+            .map(|n| (n.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        for bytes in [&b""[..], b"a", b"abcabcabcabcabc", &repetitive, &mixed] {
+            let compressed = compress(bytes);
+            assert_eq!(decompress(&compressed).unwrap(), bytes);
+        }
+        assert!(compress(&repetitive).len() < repetitive.len() / 10);
+    }
+
+    #[test]
+    fn hashes_like_xxh64() {
+        assert_eq!(xxh64(b""), 0xef46_db37_51d8_e999);
+        assert_eq!(xxh64(b"a"), 0xd24e_c4f1_a98c_6e5b);
+    }
+
+    #[test]
+    fn decompresses_huffman_literals() {
+        // the lines below as compressed by `zstd -19`
+        let bytes = [
+            0x28, 0xb5, 0x2f, 0xfd, 0x64, 0xc0, 0x02, 0x0d, 0x03, 0x00, 0xd2, 0x44, 0x11, 0x17,
+            0x70, 0x6d, 0x1b, 0x28, 0x8e, 0xa1, 0xbd, 0xb4, 0xd4, 0x22, 0xcb, 0xff, 0x7f, 0x7f,
+            0x18, 0x41, 0x10, 0x91, 0x01, 0x00, 0xa0, 0xc7, 0x04, 0x8e, 0xe3, 0x33, 0x8e, 0xdb,
+            0x17, 0x6e, 0x7b, 0xa5, 0x2d, 0x7b, 0xb2, 0x6c, 0x1d, 0x61, 0x4b, 0x7f, 0x2c, 0xcd,
+            0x1b, 0x9a, 0xf8, 0x22, 0x51, 0x9d, 0x00, 0x5e, 0x0b, 0xa8, 0x9a, 0x0f, 0x81, 0x84,
+            0xca, 0x50, 0x47, 0xc7, 0x7e, 0x6f, 0x7d, 0xd3, 0xf5, 0x81, 0xf6, 0x01, 0x0b, 0xa0,
+            0x40, 0xc2, 0xf0, 0xfe, 0xe7, 0xb0, 0x33, 0xac, 0x03, 0x7d, 0x86, 0xca, 0x1a, 0x86,
+            0x42, 0x61, 0x28, 0x1c, 0x22, 0x2c, 0x8c, 0xba, 0xa5, 0x12, 0x4b, 0xd5, 0x93,
+        ];
+        let expected = (0..10)
+            .map(|n| {
+                format!(
+                    "/home/user/src/project-{n}/{}.rs\n",
+                    &"abcdefghijklm"[n..n + 3]
+                )
+            })
+            .collect::<String>()
+            .repeat(3);
+        assert_eq!(bytes[10] & 3, 2);
+        assert_eq!(decompress(&bytes).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let mut bytes = compress(b"some tag file");
+        assert_eq!(decompress(&bytes[..3]), Err(Error("not Zstandard data")));
+        let last = bytes.len() - 5;
+        bytes[last] ^= 1;
+        assert!(decompress(&bytes).is_err());
+    }
+}
@@ -0,0 +1,1293 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory unique to the test, holding a store in `.tags`.
+fn scratch_dir(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("path-tagging-cli-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join(".tags")).unwrap();
+    root
+}
+
+/// Runs the tool with the store of `root`, in `root`.
+fn run(root: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_path-tagging"))
+        .current_dir(root)
+        .arg("--store")
+        .arg(root.join(".tags"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn strict_tag_with_a_malformed_tag_writes_nothing() {
+    let root = scratch_dir("strict-tag");
+    let file = root.join("file");
+    std::fs::write(&file, "").unwrap();
+    std::fs::write(root.join(".tags/bad.json"), "{").unwrap();
+
+    let output = run(&root, &["--strict", "tag", "file", "good", "bad"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unable to load tag \"bad\""));
+    assert!(!root.join(".tags/good.json").exists());
+    assert!(!root.join("file.tag.list").exists());
+    assert_eq!(
+        std::fs::read_to_string(root.join(".tags/bad.json")).unwrap(),
+        "{"
+    );
+}
+
+#[test]
+fn tag_with_a_malformed_tag_skips_it() {
+    let root = scratch_dir("lenient-tag");
+    std::fs::write(root.join("file"), "").unwrap();
+    std::fs::write(root.join(".tags/bad.json"), "{").unwrap();
+
+    let output = run(&root, &["tag", "file", "good", "bad"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Unable to load tag \"bad\""));
+    assert!(root.join(".tags/good.json").exists());
+    assert!(root.join("file.tag.list").exists());
+}
+
+#[test]
+fn strict_get_with_a_malformed_tag_fails() {
+    let root = scratch_dir("strict-get");
+    std::fs::write(root.join(".tags/bad.json"), "{").unwrap();
+
+    let output = run(&root, &["--strict", "get", "bad"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn link_applied_to_existing_tags_sidecars() {
+    let root = scratch_dir("link-apply");
+    std::fs::write(root.join("file"), "").unwrap();
+    assert!(run(&root, &["tag", "file", "b"]).status.success());
+    assert!(run(&root, &["tag", "file", "c"]).status.success());
+    assert!(run(&root, &["link", "b", "c"]).status.success());
+
+    let output = run(&root, &["link", "a", "b", "--apply-to-existing"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let meta = path_tagging::PathMetadata::load(root.join("file")).unwrap();
+    assert!(meta.tags().contains("a"));
+}
+
+#[test]
+fn link_rejects_itself_cycles_and_missing_tags() {
+    let root = scratch_dir("link-reject");
+    std::fs::write(root.join("file"), "").unwrap();
+    assert!(run(&root, &["tag", "file", "b"]).status.success());
+    assert!(run(&root, &["link", "a", "b"]).status.success());
+    let before = std::fs::read(root.join(".tags/b.json")).unwrap();
+
+    for args in [
+        ["link", "b", "b"],
+        ["link", "b", "a"],
+        ["link", "b", "missing"],
+    ] {
+        let output = run(&root, &args);
+        assert!(!output.status.success(), "{args:?} succeeded");
+    }
+    assert_eq!(std::fs::read(root.join(".tags/b.json")).unwrap(), before);
+}
+
+#[test]
+fn self_test_cleans_up_after_itself() {
+    let root = scratch_dir("self-test");
+    let config = "keep_empty = true\nchecksum = true\n";
+    std::fs::write(root.join("path-tagging.toml"), config).unwrap();
+
+    let output = run(&root, &["self-test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+    assert_eq!(std::fs::read_dir(root.join(".tags")).unwrap().count(), 0);
+}
+
+#[test]
+fn gc_under_only_prunes_paths_under_it() {
+    let root = scratch_dir("gc-under");
+    let store = path_tagging::Store::new(root.join(".tags"));
+    let inside = root.join("sub/gone");
+    let outside = root.join("gone");
+    let mut tag = path_tagging::RawTag::default();
+    tag.paths_mut().extend([inside.clone(), outside.clone()]);
+    store.save("a", &tag).unwrap();
+
+    let output = run(&root, &["gc", "--under", "sub", "--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(store.load("a").unwrap(), tag);
+
+    let output = run(&root, &["gc", "--under", "sub", "--assume-yes"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, format!("a\t{}\n", inside.display()));
+    let tag = store.load("a").unwrap();
+    assert!(!tag.contains_path(&inside));
+    assert!(tag.contains_path(&outside));
+}
+
+#[test]
+fn untag_from_query_untags_the_matching_paths() {
+    let root = scratch_dir("untag-from-query");
+    std::fs::write(root.join("a"), "").unwrap();
+    std::fs::write(root.join("b"), "").unwrap();
+    assert!(run(&root, &["tag", "a", "draft", "published"])
+        .status
+        .success());
+    assert!(run(&root, &["tag", "b", "draft"]).status.success());
+
+    let output = run(&root, &["untag", "--from-query", "published", "draft"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let store = path_tagging::Store::new(root.join(".tags"));
+    let draft = store.load("draft").unwrap();
+    assert!(!draft.contains_path(root.join("a")));
+    assert!(draft.contains_path(root.join("b")));
+    let meta = path_tagging::PathMetadata::load(root.join("a")).unwrap();
+    assert!(!meta.tags().contains("draft"));
+    assert!(meta.tags().contains("published"));
+}
+
+#[test]
+fn config_sets_case_and_format_defaults() {
+    let root = scratch_dir("config-defaults");
+    std::fs::write(root.join("file"), "").unwrap();
+    std::fs::write(
+        root.join("path-tagging.toml"),
+        "ignore_case = true\nformat = \"json\"\n",
+    )
+    .unwrap();
+    assert!(run(&root, &["tag", "file", "rust"]).status.success());
+
+    let output = run(&root, &["get", "RUST"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let found: Vec<String> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(found, [root.join("file").display().to_string()]);
+
+    let output = run(
+        &root,
+        &["get", "RUST", "--case-sensitive", "--format", "lines"],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+
+    let output = run(&root, &["--no-config", "get", "RUST"]);
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn invalid_config_fails() {
+    let root = scratch_dir("config-invalid");
+    std::fs::write(root.join("path-tagging.toml"), "color = \"sometimes\"\n").unwrap();
+    let output = run(&root, &["get", "a"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Invalid color"));
+}
+
+#[test]
+fn json_lines_writes_each_path_once() {
+    let root = scratch_dir("json-lines");
+    for name in ["a", "b", "c"] {
+        std::fs::write(root.join(name), "").unwrap();
+        assert!(run(&root, &["tag", name, "doc"]).status.success());
+    }
+    let other = root.join(".other");
+    std::fs::create_dir_all(&other).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_path-tagging"))
+        .current_dir(&root)
+        .arg("--store")
+        .arg(&other)
+        .args(["tag", "a", "doc"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let other = other.to_str().unwrap();
+    let output = run(&root, &["--store", other, "get", "doc", "--json-lines"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let mut found = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let line: serde_json::Value = serde_json::from_str(line).unwrap();
+            line["path"].as_str().unwrap().to_owned()
+        })
+        .collect::<Vec<_>>();
+    found.sort();
+    let expected = ["a", "b", "c"].map(|name| root.join(name).display().to_string());
+    assert_eq!(found, expected);
+
+    let output = run(&root, &["get", "doc", "--json-lines", "--max-results", "2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 2);
+    assert!(stderr(&output).contains("... 1 more path(s) omitted"));
+}
+
+#[test]
+fn convert_rewrites_every_tag_as_toml() {
+    let root = scratch_dir("convert");
+    std::fs::write(root.join("file"), "").unwrap();
+    std::fs::write(root.join("path-tagging.toml"), "checksum = true\n").unwrap();
+    assert!(run(&root, &["tag", "file", "rust", "lang/c"])
+        .status
+        .success());
+    assert!(run(&root, &["link", "code", "rust"]).status.success());
+    let store = path_tagging::Store::new(root.join(".tags"));
+    let names = store.names().unwrap();
+    assert_eq!(names, ["code", "lang/c", "rust"]);
+    let tags = names
+        .iter()
+        .map(|name| store.load(name).unwrap())
+        .collect::<Vec<_>>();
+
+    let output = run(&root, &["convert", "--to", "toml", "--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 3);
+    assert!(root.join(".tags/rust.json").exists());
+
+    let output = run(&root, &["convert", "--to", "toml"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("store_format = \"toml\""));
+    for name in &names {
+        let tags = root.join(".tags");
+        assert!(!tags.join(format!("{name}.json")).exists());
+        assert!(tags.join(format!("{name}.toml")).exists());
+        assert!(tags.join(format!("{name}.toml.sha256")).exists());
+    }
+    let mut store = store;
+    store.set_checksum(true);
+    assert_eq!(store.names().unwrap(), names);
+    for (name, tag) in names.iter().zip(&tags) {
+        assert_eq!(&store.load(name).unwrap(), tag);
+    }
+
+    let output = run(&root, &["get", "code"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        root.join("file").display().to_string()
+    );
+}
+
+#[test]
+fn quiet_suppresses_load_warnings() {
+    let root = scratch_dir("quiet");
+    std::fs::write(root.join("file"), "").unwrap();
+    std::fs::write(root.join(".tags/bad.json"), "{").unwrap();
+
+    let output = run(&root, &["--quiet", "tag", "file", "good", "bad"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("Unable to load tag"));
+    assert!(root.join(".tags/good.json").exists());
+
+    let output = run(&root, &["--quiet", "--strict", "get", "bad"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unable to search by tag"));
+}
+
+#[test]
+fn list_with_directory_defaults_inherits_directory_tags() {
+    let root = scratch_dir("directory-defaults");
+    std::fs::create_dir(root.join("project")).unwrap();
+    std::fs::write(root.join("project/file"), "").unwrap();
+    assert!(run(&root, &["tag", "project", "work"]).status.success());
+    assert!(run(&root, &["tag", "project/file", "code"])
+        .status
+        .success());
+
+    let output = run(&root, &["list", "project/file"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "code\n");
+    let output = run(&root, &["list", "--directory-defaults", "project/file"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "code\nwork\n");
+}
+
+#[test]
+fn get_max_results_truncates_sorted_output() {
+    let root = scratch_dir("max-results");
+    for name in ["c", "a", "d", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+        assert!(run(&root, &["tag", name, "doc"]).status.success());
+    }
+
+    let output = run(&root, &["get", "doc", "--max-results", "2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let expected = ["a", "b"].map(|name| format!("{}\n", root.join(name).display()));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected.concat());
+    assert!(stderr(&output).contains("... 2 more path(s) omitted"));
+
+    let output = run(&root, &["get", "doc", "--max-results", "4"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 4);
+    assert!(!stderr(&output).contains("omitted"));
+}
+
+#[test]
+fn popular_ranks_paths_by_tag_count() {
+    let root = scratch_dir("popular");
+    std::fs::write(root.join("hub"), "").unwrap();
+    std::fs::write(root.join("leaf"), "").unwrap();
+    assert!(run(&root, &["tag", "hub", "a", "b"]).status.success());
+    assert!(run(&root, &["tag", "leaf", "a"]).status.success());
+
+    let output = run(&root, &["popular", "a", "b"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let expected = format!(
+        "2\t{}\n1\t{}\n",
+        root.join("hub").display(),
+        root.join("leaf").display()
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn touched_tags_survive_losing_their_paths() {
+    let root = scratch_dir("touch");
+    std::fs::write(root.join("file"), "").unwrap();
+
+    let output = run(&root, &["touch", "taxonomy"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(root.join(".tags/taxonomy.json").is_file());
+
+    for tag in ["taxonomy", "plain"] {
+        let output = run(&root, &["tag", "file", tag]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        let output = run(&root, &["untag", "file", tag]);
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+    assert!(root.join(".tags/taxonomy.json").is_file());
+    assert!(!root.join(".tags/plain.json").exists());
+}
+
+#[test]
+fn exists_reports_through_the_exit_status() {
+    let root = scratch_dir("exists");
+    std::fs::write(root.join("file"), "").unwrap();
+    let output = run(&root, &["tag", "file", "a", "b"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    std::fs::write(root.join("other"), "").unwrap();
+    let output = run(&root, &["tag", "other", "c"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let status = |args: &[&str]| {
+        let output = run(&root, args);
+        assert!(output.stdout.is_empty());
+        output.status.code()
+    };
+    assert_eq!(status(&["exists", "--tag", "a"]), Some(0));
+    assert_eq!(status(&["exists", "--tag", "missing"]), Some(1));
+    assert_eq!(status(&["exists", "--query", "a", "b"]), Some(0));
+    assert_eq!(status(&["exists", "--query", "a", "c"]), Some(1));
+
+    std::fs::write(root.join("outside.json"), "{}").unwrap();
+    assert_eq!(status(&["exists", "--tag", "../outside"]), Some(2));
+}
+
+#[test]
+fn clean_sidecars_removes_orphans_and_warns_on_drift() {
+    let root = scratch_dir("clean-sidecars");
+    for file in ["kept", "drifted", "other"] {
+        std::fs::write(root.join(file), "").unwrap();
+    }
+    let output = run(&root, &["tag", "kept", "a"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run(&root, &["tag", "other", "b"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    std::fs::write(root.join("drifted.tag.list"), "b").unwrap();
+    std::fs::write(root.join("gone.tag.list"), "a").unwrap();
+
+    let output = run(&root, &["clean-sidecars", ".", "--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let orphan = root.join("gone.tag.list");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", orphan.display())
+    );
+    assert!(stderr(&output).contains("lists tag \"b\" which doesn't contain it"));
+    assert!(orphan.exists());
+
+    let output = run(&root, &["clean-sidecars", ".", "--assume-yes"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!orphan.exists());
+    assert!(root.join("kept.tag.list").exists());
+    assert!(root.join("drifted.tag.list").exists());
+}
+
+#[test]
+fn get_across_stores_unions_their_results() {
+    let root = scratch_dir("multiple-stores");
+    let other = root.join(".other");
+    std::fs::create_dir_all(&other).unwrap();
+    let other = other.to_str().unwrap();
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    let output = run(&root, &["tag", "a", "doc"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = Command::new(env!("CARGO_BIN_EXE_path-tagging"))
+        .current_dir(&root)
+        .args(["--store", other, "tag", "a:b", "doc"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = run(&root, &["get", "doc"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", root.join("a").display())
+    );
+
+    let output = run(&root, &["--store", other, "get", "doc"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let mut found = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(
+        found,
+        ["a", "b"].map(|name| root.join(name).display().to_string())
+    );
+}
+
+#[test]
+fn rename_merges_onto_existing_tags_only_when_asked() {
+    let root = scratch_dir("rename");
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a", "old"]).status.success());
+    assert!(run(&root, &["tag", "b", "other"]).status.success());
+
+    let output = run(&root, &["rename", "old", "new"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.join(".tags/old.json").exists());
+    assert!(root.join(".tags/new.json").exists());
+    assert_eq!(
+        std::fs::read_to_string(root.join("a.tag.list")).unwrap(),
+        "new"
+    );
+
+    let output = run(&root, &["rename", "new", "other"]);
+    assert!(!output.status.success());
+    assert!(root.join(".tags/new.json").exists());
+
+    let output = run(&root, &["rename", "new", "other", "--merge"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.join(".tags/new.json").exists());
+    let output = run(&root, &["get", "other"]);
+    let mut found = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(
+        found,
+        ["a", "b"].map(|name| root.join(name).display().to_string())
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.join("a.tag.list")).unwrap(),
+        "other"
+    );
+}
+
+#[test]
+fn list_grouped_keeps_the_tags_of_each_path() {
+    let root = scratch_dir("list-grouped");
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a", "x"]).status.success());
+    assert!(run(&root, &["tag", "a:b", "y"]).status.success());
+
+    let output = run(&root, &["list", "a:b", "--grouped"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}\n\tx\n\ty\n{}\n\ty\n",
+            root.join("a").display(),
+            root.join("b").display()
+        )
+    );
+
+    let output = run(&root, &["list", "a:b"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "x\ny\n");
+}
+
+#[test]
+fn bulk_tagging_keeps_progress_off_the_results() {
+    let root = scratch_dir("progress");
+    let names = (0..50).map(|i| format!("file{i:02}")).collect::<Vec<_>>();
+    for name in &names {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+
+    let output = run(&root, &["tag", &names.join(":"), "bulk"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    assert!(!stderr(&output).contains('\r'));
+
+    let output = run(&root, &["get", "bulk", "--sort", "name"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let expected = names
+        .iter()
+        .map(|name| format!("{}\n", root.join(name).display()))
+        .collect::<String>();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn output_writes_results_to_the_file() {
+    let root = scratch_dir("output");
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a:b", "x"]).status.success());
+
+    let output = run(
+        &root,
+        &[
+            "get", "x", "--sort", "name", "--format", "json", "--output", "get.json",
+        ],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    let expected = serde_json::to_string(&["a", "b"].map(|name| root.join(name))).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(root.join("get.json")).unwrap(),
+        format!("{expected}\n")
+    );
+
+    let output = run(&root, &["list", "a", "--output", "list.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(root.join("list.txt")).unwrap(),
+        "x\n"
+    );
+    assert!(!root.join(".list.txt.tmp").exists());
+}
+
+#[test]
+fn repeated_tag_writes_nothing() {
+    let root = scratch_dir("idempotent-tag");
+    std::fs::write(root.join("file"), "").unwrap();
+    assert!(run(&root, &["tag", "file", "x", "y"]).status.success());
+
+    let files = [".tags/x.json", ".tags/y.json", "file.tag.list"].map(|file| root.join(file));
+    let past = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    for file in &files {
+        let file = std::fs::File::options().write(true).open(file).unwrap();
+        file.set_modified(past).unwrap();
+    }
+
+    let output = run(&root, &["tag", "file", "x", "y"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    for file in &files {
+        let modified = std::fs::metadata(file).unwrap().modified().unwrap();
+        assert_eq!(modified, past, "{} was rewritten", file.display());
+    }
+
+    let output = run(&root, &["untag", "file", "z"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        std::fs::metadata(&files[2]).unwrap().modified().unwrap(),
+        past
+    );
+}
+
+#[test]
+fn get_relative_shortens_paths_under_the_base() {
+    let root = scratch_dir("relative");
+    std::fs::create_dir_all(root.join("sub/deep")).unwrap();
+    for file in ["outside", "sub/a", "sub/deep/b"] {
+        std::fs::write(root.join(file), "").unwrap();
+    }
+    let output = run(&root, &["tag", "outside:sub/a:sub/deep/b:sub", "x"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = run(&root, &["get", "x", "--relative", "sub", "--sort", "name"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}\n.\na\n{}\n",
+            root.join("outside").display(),
+            Path::new("deep").join("b").display()
+        )
+    );
+}
+
+#[test]
+fn tag_from_copies_the_recursive_paths_of_a_tag() {
+    let root = scratch_dir("tag-from");
+    for name in ["a", "b", "c"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a", "base"]).status.success());
+    assert!(run(&root, &["tag", "b", "part"]).status.success());
+    assert!(run(&root, &["tag", "c", "unrelated"]).status.success());
+    assert!(run(&root, &["link", "base", "part"]).status.success());
+
+    let output = run(&root, &["tag", "--from", "base", "new1", "new2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    for tag in ["new1", "new2"] {
+        let output = run(&root, &["get", tag, "--sort", "name"]);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!(
+                "{}\n{}\n",
+                root.join("a").display(),
+                root.join("b").display()
+            )
+        );
+    }
+    assert_eq!(
+        std::fs::read_to_string(root.join("a.tag.list")).unwrap(),
+        "base\nnew1\nnew2"
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.join("b.tag.list")).unwrap(),
+        "new1\nnew2\npart"
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.join("c.tag.list")).unwrap(),
+        "unrelated"
+    );
+}
+
+#[test]
+fn ignore_case_matches_tags_regardless_of_case() {
+    let root = scratch_dir("ignore-case");
+    std::fs::write(root.join("file"), "").unwrap();
+    assert!(run(&root, &["tag", "file", "rust"]).status.success());
+
+    let output = run(&root, &["get", "RUST"]);
+    assert!(output.stdout.is_empty());
+    let output = run(&root, &["get", "--ignore-case", "RUST"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", root.join("file").display())
+    );
+
+    std::fs::write(root.join("file.tag.list"), "RUST").unwrap();
+    let output = run(&root, &["list", "file"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "RUST\n");
+    let output = run(&root, &["list", "--ignore-case", "file"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "rust\n");
+}
+
+#[test]
+fn profile_reports_to_stderr() {
+    let root = scratch_dir("profile");
+    std::fs::write(root.join("file"), "").unwrap();
+    assert!(run(&root, &["tag", "file", "x"]).status.success());
+
+    let output = run(&root, &["--profile", "get", "x"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", root.join("file").display())
+    );
+    let stderr = stderr(&output);
+    let summary = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("[PROFILE] "))
+        .unwrap_or_else(|| panic!("no profile summary in {stderr:?}"));
+    let (files_read, _) = summary.split_once(" file(s) read").unwrap();
+    assert!(files_read.parse::<u64>().unwrap() > 0);
+    assert!(summary.contains("resolution"));
+}
+
+#[test]
+fn default_tags_are_added_on_tag_and_kept_on_untag() {
+    let root = scratch_dir("default-tags");
+    std::fs::write(root.join("file"), "").unwrap();
+    std::fs::write(
+        root.join("path-tagging.toml"),
+        "default_tags = [\"managed\"]\n",
+    )
+    .unwrap();
+
+    let output = run(&root, &["tag", "file", "x"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        std::fs::read_to_string(root.join("file.tag.list")).unwrap(),
+        "managed\nx"
+    );
+    let expected = format!("{}\n", root.join("file").display());
+    let output = run(&root, &["get", "managed", "x"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let output = run(&root, &["untag", "file", "x"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        std::fs::read_to_string(root.join("file.tag.list")).unwrap(),
+        "managed"
+    );
+    let output = run(&root, &["get", "managed"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn follow_symlinks_tags_the_link_target() {
+    let root = scratch_dir("follow-symlinks");
+    std::fs::create_dir_all(root.join("real")).unwrap();
+    std::fs::write(root.join("real/file"), "").unwrap();
+    std::os::unix::fs::symlink("real/file", root.join("file-link")).unwrap();
+    std::os::unix::fs::symlink("real", root.join("dir-link")).unwrap();
+    let real = std::fs::canonicalize(root.join("real")).unwrap();
+
+    let output = run(
+        &root,
+        &[
+            "--follow-symlinks",
+            "tag",
+            "file-link:dir-link/missing",
+            "x",
+        ],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run(&root, &["get", "x", "--sort", "name"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}\n{}\n",
+            real.join("file").display(),
+            real.join("missing").display()
+        )
+    );
+
+    let output = run(&root, &["--follow-symlinks", "why", "x", "dir-link/file"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "x\n");
+    let output = run(&root, &["why", "x", "dir-link/file"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = run(&root, &["tag", "file-link", "y"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run(&root, &["get", "y"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", root.join("file-link").display())
+    );
+}
+
+#[test]
+fn retag_moves_paths_and_keeps_the_structure() {
+    let root = scratch_dir("retag");
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a:b", "old", "kept"]).status.success());
+    assert!(run(&root, &["link", "old", "kept"]).status.success());
+
+    let output = run(&root, &["retag", "old", "new"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let old: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(root.join(".tags/old.json")).unwrap())
+            .unwrap();
+    assert_eq!(old["paths"], serde_json::json!([]));
+    assert_eq!(old["include_tags"], serde_json::json!(["kept"]));
+
+    let output = run(&root, &["get", "new", "--sort", "name"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}\n{}\n",
+            root.join("a").display(),
+            root.join("b").display()
+        )
+    );
+    for name in ["a", "b"] {
+        let sidecar = root.join(format!("{name}.tag.list"));
+        assert_eq!(std::fs::read_to_string(sidecar).unwrap(), "kept\nnew");
+    }
+}
+
+#[test]
+fn destructive_commands_proceed_without_a_terminal() {
+    use std::io::Write;
+
+    let root = scratch_dir("confirm");
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a:b", "x", "y"]).status.success());
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_path-tagging"))
+        .current_dir(&root)
+        .arg("--store")
+        .arg(root.join(".tags"))
+        .args(["clear", "a"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"n\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("[y/N]"));
+    assert!(!root.join("a.tag.list").exists());
+
+    let output = run(&root, &["detach", "b", "--assume-yes"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.join("b.tag.list").exists());
+    assert!(!root.join(".tags/x.json").exists());
+}
+
+#[test]
+fn tag_reads_paths_piped_from_get() {
+    use std::io::Write;
+
+    let root = scratch_dir("pipe");
+    for name in ["a", "b", "c"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a:b", "x"]).status.success());
+    assert!(run(&root, &["tag", "c", "other"]).status.success());
+
+    let pipe = |format: &str, tag: &str| {
+        let found = run(&root, &["get", "x", "--format", format]);
+        assert!(found.status.success(), "{}", stderr(&found));
+        let mut child = Command::new(env!("CARGO_BIN_EXE_path-tagging"))
+            .current_dir(&root)
+            .arg("--store")
+            .arg(root.join(".tags"))
+            .args(["tag", "-", tag])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(&found.stdout)
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+    };
+    pipe("lines", "y");
+    pipe("nul", "z");
+
+    for tag in ["y", "z"] {
+        let output = run(&root, &["get", tag, "--sort", "name"]);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!(
+                "{}\n{}\n",
+                root.join("a").display(),
+                root.join("b").display()
+            )
+        );
+    }
+    assert_eq!(
+        std::fs::read_to_string(root.join("a.tag.list")).unwrap(),
+        "x\ny\nz"
+    );
+}
+
+#[test]
+fn get_sort_orders_by_each_key() {
+    let root = scratch_dir("sort");
+    let past = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    // names, sizes, and times each order the files differently
+    for (name, size, age) in [("a", 3, 2), ("b", 1, 3), ("c", 2, 1)] {
+        let path = root.join(name);
+        std::fs::write(&path, "x".repeat(size)).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(past - std::time::Duration::from_secs(age))
+            .unwrap();
+    }
+    assert!(run(&root, &["tag", "a:b:c:gone", "x"]).status.success());
+
+    let sorted = |key: &str| {
+        let output = run(&root, &["get", "x", "--sort", key]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        let found = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| {
+                Path::new(line)
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+        (found, stderr(&output))
+    };
+    assert_eq!(sorted("name").0, ["a", "b", "c", "gone"]);
+    let (found, stderr) = sorted("size");
+    assert_eq!(found, ["b", "c", "a", "gone"]);
+    assert!(stderr.contains("Unable to inspect"));
+    assert_eq!(sorted("mtime").0, ["b", "a", "c", "gone"]);
+    let mut found = sorted("none").0;
+    found.sort();
+    assert_eq!(found, ["a", "b", "c", "gone"]);
+}
+
+#[test]
+fn name_transform_applies_to_tag_files_and_metadata() {
+    let root = scratch_dir("name-transform");
+    std::fs::write(root.join("file"), "").unwrap();
+    std::fs::write(
+        root.join("path-tagging.toml"),
+        "name_transform = \"kebab\"\n",
+    )
+    .unwrap();
+
+    let output = run(&root, &["tag", "file", "Rust Lang"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(root.join(".tags/rust-lang.json").exists());
+    assert_eq!(
+        std::fs::read_to_string(root.join("file.tag.list")).unwrap(),
+        "rust-lang"
+    );
+    let output = run(&root, &["get", "RUST_LANG"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", root.join("file").display())
+    );
+
+    std::fs::write(
+        root.join("path-tagging.toml"),
+        "name_transform = \"lowercase\"\n",
+    )
+    .unwrap();
+    let output = run(&root, &["tag", "file", "Rust Lang"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(root.join(".tags/rust lang.json").exists());
+}
+
+#[test]
+fn no_metadata_only_updates_tag_files() {
+    let root = scratch_dir("no-metadata");
+    std::fs::write(root.join("file"), "").unwrap();
+
+    let output = run(&root, &["tag", "file", "x", "--no-metadata"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.join("file.tag.list").exists());
+    let output = run(&root, &["get", "x"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", root.join("file").display())
+    );
+
+    assert!(run(&root, &["tag", "file", "y"]).status.success());
+    let output = run(&root, &["untag", "file", "y", "--no-metadata"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.join(".tags/y.json").exists());
+    assert_eq!(
+        std::fs::read_to_string(root.join("file.tag.list")).unwrap(),
+        "y"
+    );
+}
+
+#[test]
+fn get_template_renders_each_path() {
+    let root = scratch_dir("template");
+    for name in ["a.rs", "b.rs"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a.rs:b.rs", "x"]).status.success());
+
+    let output = run(&root, &["get", "x", "--template", "{basename}"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a.rs\nb.rs\n");
+
+    let output = run(&root, &["get", "x", "--template", "{size}"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("unknown placeholder {size}"));
+}
+
+#[test]
+fn exclusive_waits_for_or_fails_on_a_held_lock() {
+    let root = scratch_dir("exclusive");
+    std::fs::write(root.join("file"), "").unwrap();
+    let lock = root.join(".tags/.path-tagging.lock");
+    std::fs::write(&lock, "").unwrap();
+
+    let output = run(&root, &["--exclusive", "--no-wait", "tag", "file", "x"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Unable to lock the store"));
+    assert!(!root.join(".tags/x.json").exists());
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_path-tagging"))
+        .current_dir(&root)
+        .arg("--store")
+        .arg(root.join(".tags"))
+        .args(["--exclusive", "tag", "file", "x"])
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert!(child.try_wait().unwrap().is_none());
+    assert!(!root.join(".tags/x.json").exists());
+
+    std::fs::remove_file(&lock).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(root.join(".tags/x.json").exists());
+    assert!(!lock.exists());
+}
+
+#[test]
+fn get_filters_by_file_type() {
+    let root = scratch_dir("file-type");
+    std::fs::create_dir_all(root.join("dir")).unwrap();
+    std::fs::write(root.join("file"), "").unwrap();
+    assert!(run(&root, &["tag", "file:dir:gone", "x"]).status.success());
+
+    let get = |args: &[&str]| {
+        let output = run(&root, &[&["get", "x", "--sort", "name"], args].concat());
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let line = |name: &str| format!("{}\n", root.join(name).display());
+    assert_eq!(get(&["--files-only"]), line("file"));
+    assert_eq!(get(&["--dirs-only"]), line("dir"));
+    assert_eq!(get(&[]), line("dir") + &line("file") + &line("gone"));
+}
+
+#[cfg(unix)]
+#[test]
+fn get_dedup_by_inode_collapses_hardlinks() {
+    let root = scratch_dir("dedup-inode");
+    std::fs::write(root.join("b"), "").unwrap();
+    std::fs::hard_link(root.join("b"), root.join("a")).unwrap();
+    std::fs::hard_link(root.join("b"), root.join("c")).unwrap();
+    std::fs::write(root.join("d"), "").unwrap();
+    assert!(run(&root, &["tag", "c:b:a:d:gone", "x"]).status.success());
+
+    let get = |args: &[&str]| {
+        let output = run(
+            &root,
+            &[&["get", "x", "--dedup-by", "inode"], args].concat(),
+        );
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let lines = |names: &[&str]| {
+        (names.iter())
+            .map(|name| format!("{}\n", root.join(name).display()))
+            .collect::<String>()
+    };
+    assert_eq!(get(&[]), lines(&["a", "d", "gone"]));
+    assert_eq!(get(&["--drop-missing"]), lines(&["a", "d"]));
+}
+
+#[test]
+fn get_existing_only_leaves_the_store_untouched() {
+    let root = scratch_dir("existing-only");
+    std::fs::write(root.join("present"), "").unwrap();
+    assert!(run(&root, &["tag", "present:gone", "x"]).status.success());
+    let stored = std::fs::read(root.join(".tags/x.json")).unwrap();
+
+    let output = run(&root, &["get", "x", "--existing-only"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", root.join("present").display())
+    );
+    assert!(stderr(&output).is_empty());
+    assert_eq!(std::fs::read(root.join(".tags/x.json")).unwrap(), stored);
+}
+
+#[test]
+fn dedup_case_merges_colliding_tags() {
+    let root = scratch_dir("dedup-case");
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a", "rust"]).status.success());
+    assert!(run(&root, &["tag", "b", "Rust"]).status.success());
+    assert!(run(&root, &["touch", "lang"]).status.success());
+    assert!(run(&root, &["link", "lang", "Rust"]).status.success());
+
+    let output = run(&root, &["dedup-case", "--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Rust\trust\n");
+    assert!(root.join(".tags/Rust.json").exists());
+
+    let output = run(&root, &["dedup-case", "--assume-yes"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.join(".tags/Rust.json").exists());
+    let output = run(&root, &["get", "rust", "--sort", "name"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}\n{}\n",
+            root.join("a").display(),
+            root.join("b").display()
+        )
+    );
+    for name in ["a", "b"] {
+        let sidecar = root.join(format!("{name}.tag.list"));
+        assert_eq!(std::fs::read_to_string(sidecar).unwrap(), "rust");
+    }
+    let lang = std::fs::read_to_string(root.join(".tags/lang.json")).unwrap();
+    assert!(lang.contains("\"rust\"") && !lang.contains("\"Rust\""));
+}
+
+#[test]
+fn clear_dry_run_previews_without_writing() {
+    let root = scratch_dir("clear-dry-run");
+    for name in ["a", "b", "c"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a:b:c", "x"]).status.success());
+    assert!(run(&root, &["tag", "a", "y"]).status.success());
+    let files =
+        [".tags/x.json", ".tags/y.json", "a.tag.list", "b.tag.list"].map(|file| root.join(file));
+    let before = files.each_ref().map(|file| std::fs::read(file).unwrap());
+
+    let output = run(&root, &["clear", "a:b", "--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let (a, b) = (root.join("a"), root.join("b"));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "paths:\n  {a}: x, y\n  {b}: x\ntags:\n  x: {a}, {b}\n  y: {a}\n",
+            a = a.display(),
+            b = b.display()
+        )
+    );
+    assert_eq!(files.map(|file| std::fs::read(file).unwrap()), before);
+}
+
+#[test]
+fn benchmark_completes_and_cleans_up() {
+    let root = scratch_dir("benchmark");
+    let output = run(&root, &["benchmark", "--tags", "3", "--paths", "5"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stages = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.split_once('\t').unwrap().0.to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(stages, ["setup", "resolve", "get", "list", "gc"]);
+
+    let args = ["benchmark", "--tags", "3", "--paths", "5", "--in-memory"];
+    let output = run(&root, &args);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 5);
+    assert_eq!(std::fs::read_dir(root.join(".tags")).unwrap().count(), 0);
+    assert_eq!(std::fs::read_dir(&root).unwrap().count(), 1);
+}
+
+#[test]
+fn get_count_by_tag_counts_each_tag_in_the_results() {
+    let root = scratch_dir("count-by-tag");
+    for name in ["a", "b", "c"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a:b:c", "x"]).status.success());
+    assert!(run(&root, &["tag", "a:b", "y"]).status.success());
+    assert!(run(&root, &["tag", "a:c", "z"]).status.success());
+
+    let count = |args: &[&str]| {
+        let output = run(&root, &[&["get", "--count-by-tag"], args].concat());
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(count(&["x", "y"]), "x\t2\ny\t2\n");
+    assert_eq!(
+        count(&["--query", "x AND NOT (y AND z)"]),
+        "x\t2\ny\t1\nz\t1\n"
+    );
+    assert_eq!(count(&["--query", "z OR x"]), "x\t3\nz\t2\n");
+    assert_eq!(count(&["x", "y", "--format", "nul"]), "x\t2\0y\t2\0");
+    std::fs::remove_file(root.join("c")).unwrap();
+    assert_eq!(count(&["x", "--existing-only"]), "x\t2\n");
+
+    let output = run(&root, &["get", "--count-by-tag", "--json-lines", "x"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("cannot be used with"));
+}
+
+#[test]
+fn info_describes_a_single_tag() {
+    let root = scratch_dir("info");
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name), "").unwrap();
+    }
+    assert!(run(&root, &["tag", "a", "base"]).status.success());
+    assert!(run(&root, &["tag", "b", "top"]).status.success());
+    assert!(run(&root, &["link", "top", "base"]).status.success());
+
+    let output = run(&root, &["info", "base"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "name: base\npaths: 1\nincludes: \ninherits: \nrecursive paths: 1\n\
+         referenced by: top\ncyclic: no\n"
+    );
+
+    let output = run(&root, &["info", "top", "--json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["includes"], serde_json::json!(["base"]));
+    assert_eq!(info["recursive_paths"], 2);
+    assert_eq!(info["cyclic"], false);
+
+    let cycle = "{\"include_tags\": [\"top\"], \"inherited_tags\": [], \"paths\": []}";
+    std::fs::write(root.join(".tags/base.json"), cycle).unwrap();
+    let output = run(&root, &["info", "top"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("recursive paths: unknown, a cycle is reachable\n"));
+    assert!(stdout.ends_with("cyclic: yes\n"));
+
+    let output = run(&root, &["info", "missing"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unable to load tag \"missing\""));
+}
+
+#[test]
+fn recursive_tag_skips_what_tagignore_excludes() {
+    let root = scratch_dir("recursive-tag");
+    for dir in ["src", "target"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for file in ["src/a", "target/b", "x.log"] {
+        std::fs::write(root.join(file), "").unwrap();
+    }
+    std::fs::write(root.join(".tagignore"), "target/\n*.log\n").unwrap();
+
+    let get = |tag: &str| {
+        let output = run(&root, &["get", tag]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let prefix = format!("{}/", root.display());
+        stdout.replace(&prefix, "")
+    };
+    for _ in 0..2 {
+        let output = run(&root, &["tag", "--recursive", ".", "kept"]);
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+    assert_eq!(
+        get("kept"),
+        format!("{}\n.tagignore\nsrc\nsrc/a\n", root.display())
+    );
+    assert!(!root.join("target/b.tag.list").exists());
+    assert!(!root.join(".tags.tag.list").exists());
+
+    let output = run(&root, &["tag", "-r", "--no-ignore", "target:x.log", "all"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(get("all"), "target\ntarget/b\nx.log\n");
+}